@@ -8,6 +8,7 @@ use bevy::prelude::EulerRot::YXZ;
 use bevy::window::{CursorGrabMode, PrimaryWindow};
 use crate::components::common::Id;
 use crate::components::player::{PlayerInfo, PlayerMarker};
+use crate::components::player::smoothing::CorrectionOffset;
 
 const LOOK_SENSITIVITY: (f32, f32) = (0.001, 0.001);
 const CAM_SPACE: f32 = 10.0;
@@ -30,11 +31,24 @@ pub fn apply_player_camera_input (
     camera_info.pitch = camera_info.pitch.clamp(-90.0f32.to_radians(), 90.0f32.to_radians());
 }
 
+/// Turns the local player by this frame's mouse motion.
+pub(crate) fn player_look(
+    mut player: Query<(&Id, &mut CameraInfo), With<PlayerMarker>>,
+    player_info: Res<PlayerInfo>,
+) {
+    for (id, mut camera_info) in player.iter_mut() {
+        if *id == player_info.current_player_id {
+            apply_player_camera_input(player_info.mouse_delta, &mut camera_info);
+        }
+    }
+}
+
 pub(crate) fn camera_controller(
     mut camera: Query<&mut Transform, (With<Camera3d>, Without<PlayerMarker>)>,
-    mut player: Query<(&Id, &Position, &mut CameraInfo), (With<PlayerMarker>, Without<Camera3d>)>,
+    player: Query<(&Id, &Position, &CameraInfo), (With<PlayerMarker>, Without<Camera3d>)>,
     mut mouse_wheel: EventReader<MouseWheel>,
     player_info: Res<PlayerInfo>,
+    correction: Res<CorrectionOffset>,
     mut zoom: Local<f32>
 ) {
     for ev in mouse_wheel.read() {
@@ -42,14 +56,13 @@ pub(crate) fn camera_controller(
         *zoom = zoom.clamp(-0.2, 10.0);
     }
     
-    for (id, position, mut camera_info) in player.iter_mut() {
+    for (id, position, camera_info) in player.iter() {
         if *id == player_info.current_player_id {
-            apply_player_camera_input(player_info.mouse_delta, &mut camera_info);
-
             for mut cam in camera.iter_mut() {
                 cam.rotation = Quat::from_euler(YXZ, camera_info.yaw, -camera_info.pitch, 0.0);
 
-                let pivot_shift = position.0 + Vec3::new(0.0, CAMERA_HEIGHT, 0.0);
+                // Follows the drawn player, not the body, so corrections don't jerk the view
+                let pivot_shift = position.0 + correction.offset + Vec3::new(0.0, CAMERA_HEIGHT, 0.0);
                 
                 if CAM_SPACE == 0. {
                     cam.translation = pivot_shift + Vec3::new(0.0, 0.0, *zoom); // 0.0, 0.5, 2.0
@@ -42,7 +42,7 @@ pub fn chat_window(
                     message_buffer.pop();
                 }
                 Key::Enter => {
-                    if connection.stream.is_some() {
+                    if connection.ready {
                         connection.add_message(
                             NetworkMessage(CTcpType::ChatMessage {
                                 player_id: player_info.current_player_id,
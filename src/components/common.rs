@@ -20,6 +20,10 @@ impl Vec3 {
     pub fn new(x: f32, y: f32, z: f32) -> Self {
         Self { x, y, z }
     }
+
+    pub fn distance(&self, other: &Self) -> f32 {
+        bevy::math::Vec3::new(self.x - other.x, self.y - other.y, self.z - other.z).length()
+    }
 }
 
 impl PartialEq<Self> for Vec3 {
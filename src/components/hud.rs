@@ -1,4 +1,27 @@
-use bevy::prelude::Component;
+use bevy::prelude::{Component, EventReader, Query, Res, Text, With};
+use crate::network::net_connection::{ConnectionLifecycle, ConnectionStateChanged};
 
 #[derive(Component)]
 pub struct Hud;
+
+#[derive(Component)]
+pub struct ConnectionStatusHud;
+
+pub fn connection_status_hud(
+    mut state_events: EventReader<ConnectionStateChanged>,
+    mut hud: Query<&mut Text, With<ConnectionStatusHud>>,
+    lifecycle: Res<ConnectionLifecycle>,
+) {
+    for ev in state_events.read() {
+        if let Ok(mut text) = hud.single_mut() {
+            text.clear();
+            match (&lifecycle.rejection, &lifecycle.last_error) {
+                (Some(reason), _) => text.push_str(&format!("Rejected: {}", reason)),
+                (None, Some(error)) if !lifecycle.is_connected() => {
+                    text.push_str(&format!("{:?} (last attempt: {})", ev.current, error))
+                }
+                _ => text.push_str(&format!("{:?}", ev.current)),
+            }
+        }
+    }
+}
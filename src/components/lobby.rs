@@ -1,3 +1,25 @@
+use bevy::prelude::{Commands, Entity, EventReader, Query, ResMut, With};
+use crate::components::common::Id;
+use crate::components::player::{PlayerInfo, PlayerMarker};
+use crate::network::net_connection::{ConnectionState, ConnectionStateChanged};
 
 // #[derive(Resource)]
 // pub struct Lobby(pub u128);
+
+/// The server hands out a new id when we rejoin, so the old one must not keep driving input.
+/// Every player entity goes too, nobody is simulating them anymore.
+pub fn reset_player_on_disconnect(
+    mut state_events: EventReader<ConnectionStateChanged>,
+    mut player_info: ResMut<PlayerInfo>,
+    players: Query<Entity, With<PlayerMarker>>,
+    mut commands: Commands,
+) {
+    for ev in state_events.read() {
+        if ev.previous == ConnectionState::Joined {
+            player_info.current_player_id = Id(0);
+            for entity in players.iter() {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use bevy::input::keyboard::KeyboardInput;
 use bevy::input::mouse::{AccumulatedMouseMotion, MouseMotion};
-use bevy::prelude::{ButtonInput, EventReader, KeyCode, Res, ResMut, Vec2};
+use bevy::prelude::{ButtonInput, EventReader, KeyCode, Res, ResMut};
 use crate::components::player::{MovementState, PlayerInfo};
 
 pub fn input_system(
@@ -10,16 +10,7 @@ pub fn input_system(
 ) {
     player_info.player_inputs = 0;
     player_info.player_movement_state.clear();
-    
-    // Accumulated mouse delta was one frame off
-    // Adjusts the offset of the delta by one frame
-    if player_info.accumulated_mouse_delta == Vec2::ZERO {
-        player_info.accumulated_mouse_delta = player_info.mouse_delta;
-    }
-    
-    player_info.mouse_delta = mouse_input.delta;
-    
-    player_info.accumulated_mouse_delta += mouse_input.delta;
+    player_info.record_mouse_delta(mouse_input.delta);
     
     if keyboard_input.pressed(KeyCode::KeyW) {
         player_info.player_inputs |= 1;
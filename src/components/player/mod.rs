@@ -1,15 +1,18 @@
 pub mod animation;
 pub mod plugin;
+pub mod smoothing;
 mod input;
 
 use crate::components::common::{Id, Vec3};
 use crate::components::hud::Hud;
 use crate::network::net_manage::UdpConnection;
 use crate::network::net_message::{BitMask, NetworkMessage, SequenceNumber, CUdpType};
-use crate::network::net_reconciliation::{ReconcileBuffer, ObjectState, MISS_PREDICT_LIMIT, BUFFER_SIZE};
-use bevy::asset::{AssetServer, Assets};
+use crate::network::net_quantize::{quantize_player, QUANTIZATION};
+use crate::network::net_rollback::{restore_rollback_components, save_rollback_components_at, ResimulateSchedule, Rollback};
+use crate::network::net_reconciliation::{Divergence, InputRedundancy, ReconcileBuffer, ReconcilePolicy, TickRecord};
+use bevy::asset::AssetServer;
 use bevy::input::ButtonInput;
-use bevy::prelude::{error, info, warn, AnimationGraph, AnimationGraphHandle, AnimationNodeIndex, AnimationPlayer, Camera, Capsule3d, ChildOf, Command, Component, Entity, EventReader, Gizmos, GlobalTransform, Handle, Local, Node, Reflect, Resource, Scene, SceneRoot, Time, Val, Vec2, World};
+use bevy::prelude::{error, info, warn, Added, AnimationGraphHandle, AnimationNodeIndex, AnimationPlayer, Camera, Capsule3d, ChildOf, Command, Component, Entity, EventReader, Gizmos, GlobalTransform, Handle, Local, Node, Reflect, Resource, Scene, SceneRoot, Time, Val, Vec2, World};
 use bevy::prelude::{
     Camera3d, Commands, KeyCode, Mesh3d, MeshMaterial3d, Query, ReflectResource, Res, ResMut, Text, TextLayout, Transform, With,
 };
@@ -29,8 +32,8 @@ use bevy::utils::default;
 use crate::components::camera::{apply_player_camera_input, CameraInfo};
 use crate::components::CollisionLayer;
 use crate::components::player::animation::{AnimationState, PlayerAnimationState};
+use crate::components::player::smoothing::{local_player_position, record_correction, PlayerModel, MODEL_OFFSET};
 use crate::DefaultFont;
-use crate::network::net_reconciliation::StateType::{InputState, PlayerState};
 
 #[derive(Reflect, Hash, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum MovementState {
@@ -46,14 +49,44 @@ pub enum MovementState {
 pub struct PlayerInfo {
     pub current_player_id: Id,
     pub player_inputs: BitMask,
+    /// This frame's mouse motion, quantized like the input that sends it.
     pub mouse_delta: Vec2,
     pub accumulated_mouse_delta: Vec2,
+    /// Motion below the quantization step, carried over to the next frame.
+    pub mouse_remainder: Vec2,
     pub player_movement_state: HashSet<MovementState>,
 }
 
+impl PlayerInfo {
+    /// Adds this frame's mouse motion to what the next input sends.
+    pub fn record_mouse_delta(&mut self, delta: Vec2) {
+        // Accumulated mouse delta was one frame off
+        // Adjusts the offset of the delta by one frame
+        if self.accumulated_mouse_delta == Vec2::ZERO {
+            self.accumulated_mouse_delta = self.mouse_delta;
+        }
+
+        // The camera turns by what the server will apply, whole steps only
+        let delta = delta + self.mouse_remainder;
+        self.mouse_delta = QUANTIZATION.quantize_mouse_delta(delta);
+        self.mouse_remainder = delta - self.mouse_delta;
+
+        self.accumulated_mouse_delta += self.mouse_delta;
+    }
+}
+
 #[derive(Component)]
 pub struct PlayerMarker;
 
+/// The last server state the local prediction was checked against, what was predicted, and
+/// what of it was off by more than the [`ReconcilePolicy`] tolerates.
+#[derive(Resource, Default)]
+pub struct PredictionCheck {
+    pub server: Option<Player>,
+    pub predicted: Option<Player>,
+    pub divergence: Divergence,
+}
+
 #[derive(Serialize, Deserialize, Debug, Default, Copy, Clone, PartialEq)]
 pub struct Player {
     pub position: Vec3,
@@ -65,7 +98,8 @@ pub struct Player {
 
 pub struct ResimulatePlayer {
     pub received_sequence_number: SequenceNumber,
-    pub object_states: Vec<ObjectState>,
+    /// The server's state for `received_sequence_number`, replayed from.
+    pub server_state: Player,
 }
 
 impl Player {
@@ -81,45 +115,36 @@ impl Player {
 }
 
 impl ResimulatePlayer {
-    fn rollback_player(&self, world: &mut World) {
-        let rollback_player_state = {
-            let mut reconcile_buffer = world.resource_mut::<ReconcileBuffer>();
-
-            // Save frame state to buffer
-            reconcile_buffer
-                .buffer
-                .insert(self.received_sequence_number, self.object_states.clone());
-            
-            self.object_states
-                .iter()
-                .find_map(|object_state| {
-                        match object_state.0 {
-                            PlayerState { player } => Some((player.position, player.linear_velocity, player.yaw, player.pitch)),
-                            _ => None
-                        }
-                    }
-                )
-        };
+    fn rollback_player(&self, world: &mut World, entity: Entity) {
+        // The player goes after the registered components, its state is the server's
+        restore_rollback_components(world, self.received_sequence_number);
+
+        // Save frame state to buffer
+        if let Some(tick) = world.resource_mut::<ReconcileBuffer>().get_mut(self.received_sequence_number) {
+            tick.player = self.server_state;
+            tick.confirmed = true;
+        }
 
         // Set transform to match historical frame state
-        let mut player = world.query_filtered::<(&mut Position, &mut LinearVelocity, &mut CameraInfo), With<PlayerMarker>>();
-        if let Some(mut p) = player.single_mut(world).ok() {
-            if let Some(pv) = rollback_player_state {
-                info!("Rollback: yaw {:?}, pitch {:?}", pv.2, pv.3);
-
-                p.0.x = pv.0.x;
-                p.0.y = pv.0.y;
-                p.0.z = pv.0.z;
-                p.1.x = pv.1.x;
-                p.1.y = pv.1.y;
-                p.1.z = pv.1.z;
-                p.2.yaw = pv.2;
-                p.2.pitch = pv.3;
-            }
+        let pv = self.server_state;
+        let mut player = world.query::<(&mut Position, &mut LinearVelocity, &mut CameraInfo)>();
+        if let Some(mut p) = player.get_mut(world, entity).ok() {
+            info!("Rollback: yaw {:?}, pitch {:?}", pv.yaw, pv.pitch);
+
+            p.0.x = pv.position.x;
+            p.0.y = pv.position.y;
+            p.0.z = pv.position.z;
+            p.1.x = pv.linear_velocity.x;
+            p.1.y = pv.linear_velocity.y;
+            p.1.z = pv.linear_velocity.z;
+            p.2.yaw = pv.yaw;
+            p.2.pitch = pv.pitch;
         }
     }
 
-    fn resimulate_player(&self, world: &mut World) {
+    /// Replays every tick after the server's, returning the state it ends on.
+    fn resimulate_player(&self, world: &mut World, entity: Entity) -> Option<Player> {
+        let mut resimulated = None;
         for i in self.received_sequence_number + 1.. {
             // Extract input for this tick
             let frame_input = {
@@ -130,17 +155,8 @@ impl ResimulatePlayer {
                 }
 
                 reconcile_buffer
-                    .buffer
-                    .get(&i)
-                    .and_then(|frame_state| {
-                        frame_state.iter().find_map(|object_state| match object_state.0 {
-                            InputState { encoded_input , mouse_delta} => {
-                                info!("Input Found");
-                                Some((encoded_input, mouse_delta))
-                            },
-                            _ => None,
-                        })
-                    })
+                    .get(i)
+                    .map(|tick| (tick.keymask, tick.mouse_delta))
             };
 
             if frame_input.is_none() {
@@ -150,8 +166,8 @@ impl ResimulatePlayer {
             // Apply input
             if let Some(fi) = frame_input {
                 if let Some(mut player) = world
-                    .query_filtered::<(&mut LinearVelocity, &mut Rotation, &mut CameraInfo), With<PlayerMarker>>()
-                    .single_mut(world)
+                    .query::<(&mut LinearVelocity, &mut Rotation, &mut CameraInfo)>()
+                    .get_mut(world, entity)
                     .ok()
                 {
                     if fi.0 != 0 {
@@ -163,81 +179,59 @@ impl ResimulatePlayer {
                 }
             }
 
+            // Per-tick logic of the other rolled back components
+            let _ = world.try_run_schedule(ResimulateSchedule);
+
+            // Saved where the live tick saves them, after its input and before physics
+            save_rollback_components_at(world, i);
+
             // Run the physics schedule
             world.resource_mut::<Time<Physics>>().advance_by(Duration::from_secs_f64(1.0 / 60.0));
             world.run_schedule(PhysicsSchedule);
 
             let new_player_info = {
                 world
-                    .query_filtered::<(&Position, &LinearVelocity, &CameraInfo, &PlayerAnimationState), With<PlayerMarker>>()
-                    .single(world)
+                    .query::<(&Position, &LinearVelocity, &CameraInfo, &PlayerAnimationState)>()
+                    .get(world, entity)
                     .ok()
                     .and_then(|p| Some((p.0.0, p.1.0, p.2.yaw, p.2.pitch, p.3.0)))
             };
+            let new_player = new_player_info.map(|p| quantize_player(&Player::new(
+                Vec3::new(p.0.x, p.0.y, p.0.z),
+                Vec3::new(p.1.x, p.1.y, p.1.z),
+                p.2,
+                p.3,
+                p.4
+            )));
+            resimulated = new_player.or(resimulated);
 
             // Save updated player state
             let mut reconcile_buffer = world.resource_mut::<ReconcileBuffer>();
 
-            let index = if i == BUFFER_SIZE - 1 {
-                0
-            } else {
-                i + 1
-            };
-            
-            let fs = reconcile_buffer.buffer.get_mut(&index);
-            if fs.is_none() {
+            let index = i + 1;
+
+            // The tick after the current one isn't predicted yet, its slot still holds the last lap's
+            let predicted = reconcile_buffer.seq_is_newer(index);
+            let fs = reconcile_buffer.get_mut(index);
+            if fs.is_none() && predicted {
                 info!("Couldn't find frame state for sequence {:?}", index);
             }
 
-            if let Some(frame_state) = fs {
-                for object_state in frame_state.iter_mut() {
-                    match &mut object_state.0 {
-                        PlayerState { player } => {
-                            if let Some(p) = new_player_info {
-                                info!("Set state {:?}: yaw {:?}, pitch {:?}", index, p.2, p.3);
-
-                                *player = Player::new(
-                                    Vec3::new(p.0.x, p.0.y, p.0.z),
-                                    Vec3::new(p.1.x, p.1.y, p.1.z),
-                                    p.2,
-                                    p.3,
-                                    p.4
-                                )
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+            if let Some(tick) = fs
+                && let Some(player) = new_player
+            {
+                info!("Set state {:?}: yaw {:?}, pitch {:?}", index, player.yaw, player.pitch);
+
+                tick.player = player;
+                tick.confirmed = false;
             }
         }
+        resimulated
     }
 
-    fn set_updated_player_state(&self, world: &mut World) {
-        let new_current_data = {
-            let reconcile_buffer = world.resource::<ReconcileBuffer>();
-
-            let index = if reconcile_buffer.sequence_counter == BUFFER_SIZE - 1 {
-                0
-            } else {
-                reconcile_buffer.sequence_counter + 1
-            };
-
-            info!("Updated state sequence {:?}", index);
-
-            reconcile_buffer
-                .buffer
-                .get(&(index))
-                .and_then(|frame_state| {
-                    frame_state.iter().find_map(|object_state| {
-                        match object_state.0 {
-                            PlayerState { player: player_state } => {
-                                Some((player_state.position, player_state.linear_velocity, player_state.yaw, player_state.pitch))
-                            }
-                            _ => None
-                        }
-                    })
-                })
-        };
+    fn set_updated_player_state(&self, world: &mut World, entity: Entity, resimulated: Option<Player>) {
+        let new_current_data = resimulated
+            .map(|player| (player.position, player.linear_velocity, player.yaw, player.pitch));
 
         if new_current_data.is_none() {
             error!("No updated player state found!");
@@ -245,8 +239,8 @@ impl ResimulatePlayer {
 
         if let Some(ncd) = new_current_data {
             if let Some(mut p) = world
-                .query_filtered::<(&mut Position, &mut LinearVelocity, &mut CameraInfo), With<PlayerMarker>>()
-                .single_mut(world)
+                .query::<(&mut Position, &mut LinearVelocity, &mut CameraInfo)>()
+                .get_mut(world, entity)
                 .ok()
             {
                 info!("Updated state: yaw {:?}, pitch {:?}", ncd.2, ncd.3);
@@ -266,27 +260,43 @@ impl ResimulatePlayer {
 impl Command for ResimulatePlayer {
     fn apply(self, world: &mut World) -> () {
         warn!("RESIMULATING");
-        self.rollback_player(world);
+        let Some(player) = local_player(world) else {
+            warn!("No local player to resimulate");
+            return;
+        };
+        let corrected_from = local_player_position(world);
+        self.rollback_player(world, player);
 
-        self.resimulate_player(world);
+        let resimulated = self.resimulate_player(world, player);
 
-        self.set_updated_player_state(world);
+        self.set_updated_player_state(world, player, resimulated);
+        record_correction(world, corrected_from);
     }
 }
 
+/// Every player has a [`PlayerMarker`], the local one is told apart by its id.
+pub(crate) fn local_player(world: &mut World) -> Option<Entity> {
+    let local_id = world.get_resource::<PlayerInfo>()?.current_player_id;
+    world
+        .query_filtered::<(Entity, &Id), With<PlayerMarker>>()
+        .iter(world)
+        .find(|(_, id)| **id == local_id)
+        .map(|(entity, _)| entity)
+}
+
 pub fn set_player_id(
     player_info: &mut ResMut<PlayerInfo>,
     player_id: Id,
     reconcile_buffer: &mut ReconcileBuffer
 ) {
     player_info.current_player_id = player_id;
-    reconcile_buffer.buffer.clear()
+    reconcile_buffer.clear()
 }
 
 const WALK_SPEED: f32 = 1.5;
 const RUN_SPEED: f32 = 5.0;
 
-fn apply_player_movement_input(
+pub fn apply_player_movement_input(
     encoded_input: BitMask,
     linear_velocity: &mut LinearVelocity,
     rotation: &mut Rotation,
@@ -324,10 +334,13 @@ pub fn player_controller(
     mut players: Query<(&Id, &Transform, &mut LinearVelocity, &mut Rotation, &mut CameraInfo, &mut PlayerAnimationState), With<PlayerMarker>>,
     mut hud: Query<&mut Text, With<Hud>>,
     mut connection: ResMut<UdpConnection>,
-    reconcile_buffer: Res<ReconcileBuffer>,
-    mut commands: Commands,
+    mut reconcile_buffer: ResMut<ReconcileBuffer>,
+    input_redundancy: Res<InputRedundancy>,
 ) {
-    if connection.remote_socket.is_some() {
+    // Without a bound session the server can't tell whose input this is
+    if connection.session.is_bound() {
+        let mouse_delta = QUANTIZATION.quantize_mouse_delta(player_info.accumulated_mouse_delta - player_info.mouse_delta);
+
         for (id, transform, mut linear_velo, mut rotation, mut camera_info, mut player_anim_state) in players.iter_mut() {
             if player_info.current_player_id == *id {
                 if player_info.player_inputs != 0 {
@@ -357,24 +370,33 @@ pub fn player_controller(
                     linear_velo.z,
                 );
                 
-                commands.spawn(ObjectState(PlayerState { player: Player::new(position, lv, camera_info.yaw, camera_info.pitch, player_anim_state.0) }));
-                commands.spawn(ObjectState(InputState { encoded_input: player_info.player_inputs, mouse_delta: player_info.accumulated_mouse_delta - player_info.mouse_delta }));
+                // Stored as the server will see them so predictions compare bit-for-bit
+                let player = quantize_player(&Player::new(position, lv, camera_info.yaw, camera_info.pitch, player_anim_state.0));
+                let sequence = reconcile_buffer.sequence_counter;
+                reconcile_buffer.record(sequence, TickRecord {
+                    keymask: player_info.player_inputs,
+                    mouse_delta,
+                    player,
+                    confirmed: false,
+                });
             }
         }
 
         connection.add_message(NetworkMessage(CUdpType::Input {
             keymask: player_info.player_inputs,
-            mouse_delta: player_info.accumulated_mouse_delta - player_info.mouse_delta,
-            player_id: player_info.current_player_id,
+            mouse_delta,
+            history: reconcile_buffer.recent_inputs(input_redundancy.0),
         }));
 
         player_info.accumulated_mouse_delta = Vec2::ZERO;
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn reconcile_player(
     commands: &mut Commands,
-    gizmos: &mut Gizmos,
+    prediction_check: &mut PredictionCheck,
+    policy: &ReconcilePolicy,
     message_seq_num: SequenceNumber,
     server_players: &HashMap<Id, Player>,
     client_players: &mut Query<(&mut Transform, &Id, Entity, &CameraInfo, &mut PlayerAnimationState), With<PlayerMarker>>,
@@ -383,18 +405,13 @@ pub fn reconcile_player(
 ) {
     let server_player_state = server_players.get(&player_info.current_player_id);
 
-    let mut client_player_state = None;
-    
-    if let Some(reconcile_objects) = reconcile_buffer.buffer.get(&message_seq_num) {
-        for r in reconcile_objects {
-            match r.0 {
-                PlayerState { player } => {
-                    client_player_state = Some(player);
-                },
-                _ => {}
-            }
-        }
-        
+    // A tick already settled with the server, like from a duplicated snapshot, isn't checked again
+    let client_player_state = reconcile_buffer
+        .get(message_seq_num)
+        .filter(|tick| !tick.confirmed)
+        .map(|tick| tick.player);
+
+    if client_player_state.is_some() {
         for (t, id, _, _, _) in client_players.iter() {
             if player_info.current_player_id == *id
                 && server_player_state.is_some()
@@ -403,36 +420,24 @@ pub fn reconcile_player(
                 let sps = *server_player_state.unwrap();
                 let cps = client_player_state.unwrap();
 
-                gizmos.cuboid(
-                    Transform::from_xyz(sps.position.x, sps.position.y, sps.position.z)
-                        .with_scale(bevy::math::Vec3::splat(1.1))
-                        .with_rotation(Quat::from_euler(YXZ, sps.yaw,0.0,0.0)),
-                    WHITE
-                );
-                
-                gizmos.cuboid(
-                    Transform::from_xyz(cps.position.x, cps.position.y, cps.position.z).with_rotation(Quat::from_euler(YXZ, cps.yaw,0.0,0.0)),
-                    PURPLE
-                );
+                prediction_check.server = Some(sps);
+                prediction_check.predicted = Some(cps);
+
+                let divergence = policy.divergence(&sps, &cps);
+                prediction_check.divergence = divergence;
 
-                if !sps.eq(&cps) {
-                    if reconcile_buffer.miss_predict_counter >= MISS_PREDICT_LIMIT - 1 {
-                        warn!("RECONCILED");
+                if divergence.is_empty() {
+                    if let Some(tick) = reconcile_buffer.get_mut(message_seq_num) {
+                        tick.confirmed = true;
+                    }
+                    reconcile_buffer.miss_predict_counter = 0;
+                } else {
+                    if reconcile_buffer.miss_predict_counter + 1 >= policy.misses_to_reconcile() {
+                        warn!("RECONCILED: {:?}", divergence);
                         info!("current sequence: {:?}, recieved sequence: {:?}", reconcile_buffer.sequence_counter, message_seq_num);
                         info!("client: {:?}, server: {:?}", (cps.yaw, cps.pitch), (sps.yaw, sps.pitch));
-                        
-
-                        let mut new_frame_state = reconcile_objects.clone();
-                        for object_state in new_frame_state.iter_mut() {
-                            match &mut object_state.0 {
-                                PlayerState { player } => {
-                                    *player = Player::new(sps.position, sps.linear_velocity, sps.yaw, sps.pitch, sps.animation_state);
-                                }
-                                _ => {}
-                            }
-                        }
-
-                        commands.queue(ResimulatePlayer{ received_sequence_number: message_seq_num, object_states: new_frame_state });
+
+                        commands.queue(ResimulatePlayer{ received_sequence_number: message_seq_num, server_state: sps });
                         reconcile_buffer.miss_predict_counter = 0;
                     } else {
                         reconcile_buffer.miss_predict_counter += 1;
@@ -443,6 +448,28 @@ pub fn reconcile_player(
     }
 }
 
+/// Draws the last prediction check, the server's state in white and the prediction in purple.
+pub fn draw_prediction_check(
+    mut gizmos: Gizmos,
+    prediction_check: Res<PredictionCheck>,
+) {
+    if let Some(sps) = prediction_check.server {
+        gizmos.cuboid(
+            Transform::from_xyz(sps.position.x, sps.position.y, sps.position.z)
+                .with_scale(bevy::math::Vec3::splat(1.1))
+                .with_rotation(Quat::from_euler(YXZ, sps.yaw,0.0,0.0)),
+            WHITE
+        );
+    }
+
+    if let Some(cps) = prediction_check.predicted {
+        gizmos.cuboid(
+            Transform::from_xyz(cps.position.x, cps.position.y, cps.position.z).with_rotation(Quat::from_euler(YXZ, cps.yaw,0.0,0.0)),
+            PURPLE
+        );
+    }
+}
+
 // pub fn spawn_players(
 //     mut commands: Commands,
 //     mut meshes: ResMut<Assets<Mesh>>,
@@ -472,9 +499,6 @@ pub fn reconcile_player(
 
 pub fn update_players(
     commands: &mut Commands,
-    default_font: &Res<DefaultFont>,
-    asset_server: &Res<AssetServer>,
-    animation_graphs: &mut Assets<AnimationGraph>,
     // meshes: &mut ResMut<Assets<Mesh>>,
     // materials: &mut ResMut<Assets<StandardMaterial>>,
     server_players: &HashMap<Id, Player>,
@@ -488,7 +512,13 @@ pub fn update_players(
 
         let player = match server_players.get(id) {
             Some(p) => p,
-            None => continue,
+            None => {
+                // Left the server, the label follows once the entity is gone
+                if *id != info.current_player_id {
+                    commands.entity(entity).despawn();
+                }
+                continue;
+            }
         };
 
         if *id != info.current_player_id {
@@ -514,7 +544,7 @@ pub fn update_players(
         if !existing_players.contains(p.0) {
             println!("{:?}", p.1.position);
 
-            let player = commands.spawn((
+            let mut player = commands.spawn((
                 RigidBody::Dynamic,
                 Collider::capsule(0.5, 1.0),
                 Friction::new(1.0),
@@ -528,37 +558,55 @@ pub fn update_players(
                 PlayerAnimationState(AnimationState::Idle),
                 *p.0,
                 PlayerMarker
-            )).with_children( |parent| {
-                parent.spawn((
-                    SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("meshes\\player.glb"))),
-                    Transform::from_xyz(0.0, -1.0, 0.0).with_rotation(Quat::from_euler(YXZ, PI, 0.0, 0.0)),
-                ));
-            }).id();
-
-            commands.spawn((
+            ));
+            // Rotation isn't part of `Player`, it rolls back through the registry
+            if *p.0 == info.current_player_id {
+                player.insert(Rollback);
+            }
+        }
+    }
+}
+
+/// Gives newly spawned players their model and name label. Kept apart from `update_players`
+/// so a client without a renderer can spawn the same players.
+pub fn attach_player_visuals(
+    mut commands: Commands,
+    players: Query<(Entity, &Id), Added<PlayerMarker>>,
+    asset_server: Res<AssetServer>,
+    default_font: Res<DefaultFont>,
+) {
+    for (player, id) in players.iter() {
+        commands.entity(player).with_children(|parent| {
+            parent.spawn((
+                SceneRoot(asset_server.load(GltfAssetLabel::Scene(0).from_asset("meshes\\player.glb"))),
+                Transform::from_translation(MODEL_OFFSET).with_rotation(Quat::from_euler(YXZ, PI, 0.0, 0.0)),
+                PlayerModel,
+            ));
+        });
+
+        commands.spawn((
+            Node {
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            PlayerLabel(player)
+        )).with_children(|parent| {
+            parent.spawn((
+                Text::new(id.0.to_string()),
+                TextFont{
+                    font: default_font.0.clone(),
+                    font_size: 20.0,
+                    line_height: Default::default(),
+                    font_smoothing: FontSmoothing::None,
+                },
                 Node {
                     position_type: PositionType::Absolute,
+                    bottom: Val::ZERO,
                     ..default()
                 },
-                PlayerLabel(player)
-            )).with_children(|parent| {
-                parent.spawn((
-                    Text::new(p.0.0.to_string()),
-                    TextFont{
-                        font: default_font.0.clone(),
-                        font_size: 20.0,
-                        line_height: Default::default(),
-                        font_smoothing: FontSmoothing::None,
-                    },
-                    Node {
-                        position_type: PositionType::Absolute,
-                        bottom: Val::ZERO,
-                        ..default()
-                    },
-                    TextLayout::default().with_no_wrap(),
-                ));
-            });
-        }
+                TextLayout::default().with_no_wrap(),
+            ));
+        });
     }
 }
 
@@ -1,25 +1,46 @@
 use std::collections::HashSet;
+use avian3d::prelude::Rotation;
 use bevy::app::{App, FixedPreUpdate, Plugin, PostUpdate};
 use bevy::math::Vec2;
-use bevy::prelude::{FixedUpdate, PreUpdate, Update};
-use crate::components::camera::{camera_controller, lock_cursor_system};
+use bevy::prelude::{FixedUpdate, IntoScheduleConfigs, PreUpdate, Update};
+use crate::components::camera::{camera_controller, lock_cursor_system, player_look};
 use crate::components::common::Id;
-use crate::components::player::{player_controller, update_label_pos, PlayerInfo};
+use crate::components::lobby::reset_player_on_disconnect;
+use crate::components::player::{attach_player_visuals, draw_prediction_check, player_controller, update_label_pos, PlayerInfo, PredictionCheck};
 use crate::components::player::animation::{animation_control, player_animations, setup_player_animations};
 use crate::components::player::input::input_system;
+use crate::components::player::smoothing::{apply_correction_offset, decay_correction_offset, CorrectionOffset, ErrorSmoothing};
 use crate::components::weapon::weapon_controller;
+use crate::network::net_rollback::{save_rollback_components, RollbackAppExt};
 
-pub struct PlayerPlugin;
+/// Everything about the local player the server sees: input, movement and look.
+/// Leaves out whatever needs a window or a renderer, so headless clients can use it on its own.
+pub struct PlayerGameplayPlugin;
 
-impl Plugin for PlayerPlugin {
+impl Plugin for PlayerGameplayPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(PlayerInfo {
             current_player_id: Id(0),
             player_inputs: 0,
             mouse_delta: Vec2::ZERO,
             accumulated_mouse_delta: Vec2::ZERO,
+            mouse_remainder: Vec2::ZERO,
             player_movement_state: HashSet::new()
         });
+        app.init_resource::<PredictionCheck>();
+        app.register_rollback_component::<Rotation>();
+        app.add_systems(Update, (player_look, reset_player_on_disconnect));
+        app.add_systems(FixedUpdate, (player_controller, save_rollback_components).chain());
+    }
+}
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(PlayerGameplayPlugin);
+        app.init_resource::<ErrorSmoothing>();
+        app.init_resource::<CorrectionOffset>();
         app.add_systems(PreUpdate, (
             input_system,
         ));
@@ -27,8 +48,10 @@ impl Plugin for PlayerPlugin {
             Update, 
             (
                 lock_cursor_system,
-                camera_controller,
+                (decay_correction_offset, apply_correction_offset, camera_controller.after(player_look)).chain(),
                 update_label_pos,
+                attach_player_visuals,
+                draw_prediction_check,
                 setup_player_animations,
                 weapon_controller,
             )
@@ -41,7 +64,6 @@ impl Plugin for PlayerPlugin {
         app.add_systems(
             FixedUpdate,
             (
-                player_controller,
                 player_animations,
                 animation_control
             )
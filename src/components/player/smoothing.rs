@@ -0,0 +1,134 @@
+use avian3d::prelude::{Position, Rotation};
+use bevy::prelude::{Children, Component, Query, Reflect, ReflectResource, Res, ResMut, Resource, Time, Transform, Vec3, With, World};
+use crate::components::common::Id;
+use crate::components::player::{local_player, PlayerInfo, PlayerMarker};
+
+/// Where the model sits relative to the player body.
+pub const MODEL_OFFSET: Vec3 = Vec3::new(0.0, -1.0, 0.0);
+
+/// A player's model, the local one is drawn at the body plus the [`CorrectionOffset`].
+#[derive(Component)]
+pub struct PlayerModel;
+
+/// How the visual offset a correction leaves behind is closed.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+pub enum SmoothingBlend {
+    /// Gone this many seconds after the last correction.
+    Time(f32),
+    /// Shrinks by this many units per second.
+    Distance(f32),
+}
+
+/// How corrections from reconciliation are smoothed on screen.
+#[derive(Reflect, Resource, Clone, Copy, Debug)]
+#[reflect(Resource)]
+pub struct ErrorSmoothing {
+    pub blend: SmoothingBlend,
+    /// Corrections further than this, like teleports, snap instead of blending.
+    pub snap_distance: f32,
+}
+
+impl Default for ErrorSmoothing {
+    fn default() -> Self {
+        Self {
+            blend: SmoothingBlend::Time(0.15),
+            snap_distance: 2.0,
+        }
+    }
+}
+
+/// How far the local player is drawn from its physics body. Corrections move the body right
+/// away and leave this behind, so the model and camera catch up instead of jumping.
+#[derive(Resource, Default, Debug)]
+pub struct CorrectionOffset {
+    pub offset: Vec3,
+    /// Seconds left to close the offset in, for [`SmoothingBlend::Time`].
+    remaining: f32,
+}
+
+impl CorrectionOffset {
+    /// Takes a correction that moved the body from `from` to `to`.
+    pub fn correct(&mut self, from: Vec3, to: Vec3, smoothing: &ErrorSmoothing) {
+        let offset = self.offset + from - to;
+        if offset.length() > smoothing.snap_distance {
+            self.offset = Vec3::ZERO;
+            self.remaining = 0.0;
+            return;
+        }
+
+        self.offset = offset;
+        if let SmoothingBlend::Time(seconds) = smoothing.blend {
+            self.remaining = seconds;
+        }
+    }
+
+    /// Closes the offset by `delta` seconds' worth of blending.
+    pub fn decay(&mut self, delta: f32, smoothing: &ErrorSmoothing) {
+        match smoothing.blend {
+            SmoothingBlend::Time(_) => {
+                if delta >= self.remaining {
+                    self.offset = Vec3::ZERO;
+                    self.remaining = 0.0;
+                } else {
+                    self.offset *= 1.0 - delta / self.remaining;
+                    self.remaining -= delta;
+                }
+            }
+            SmoothingBlend::Distance(speed) => {
+                self.offset = self.offset.move_towards(Vec3::ZERO, speed * delta);
+            }
+        }
+    }
+}
+
+/// Records how far a correction moved the local player, leaving it as a [`CorrectionOffset`].
+/// Does nothing where nothing is drawn.
+pub(crate) fn record_correction(world: &mut World, from: Option<Vec3>) {
+    let Some(from) = from else {
+        return;
+    };
+    let Some(to) = local_player_position(world) else {
+        return;
+    };
+    let Some(smoothing) = world.get_resource::<ErrorSmoothing>().copied() else {
+        return;
+    };
+    if let Some(mut correction) = world.get_resource_mut::<CorrectionOffset>() {
+        correction.correct(from, to, &smoothing);
+    }
+}
+
+pub(crate) fn local_player_position(world: &mut World) -> Option<Vec3> {
+    let player = local_player(world)?;
+    world.get::<Position>(player).map(|p| p.0)
+}
+
+pub fn decay_correction_offset(
+    mut correction: ResMut<CorrectionOffset>,
+    smoothing: Res<ErrorSmoothing>,
+    time: Res<Time>,
+) {
+    correction.decay(time.delta_secs(), &smoothing);
+}
+
+/// Draws the local player's model at the body plus what is left of the correction.
+pub fn apply_correction_offset(
+    correction: Res<CorrectionOffset>,
+    player_info: Res<PlayerInfo>,
+    players: Query<(&Id, &Rotation, &Children), With<PlayerMarker>>,
+    mut models: Query<&mut Transform, With<PlayerModel>>,
+) {
+    for (id, rotation, children) in players.iter() {
+        if *id != player_info.current_player_id {
+            continue;
+        }
+
+        // The model is a child, so the offset has to be turned into the body's frame
+        let local_offset = rotation.0.inverse() * correction.offset;
+        for child in children.iter() {
+            if let Ok(mut transform) = models.get_mut(*child) {
+                transform.translation = MODEL_OFFSET + local_offset;
+            }
+        }
+    }
+}
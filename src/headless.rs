@@ -0,0 +1,153 @@
+use crate::components::common::Id;
+use crate::components::player::plugin::PlayerGameplayPlugin;
+use crate::components::player::PlayerInfo;
+use crate::components::CollisionLayer;
+use crate::network::net_connection::{ConnectionLifecycle, ConnectionState, ConnectionStateChanged};
+use crate::network::net_manage::TcpConnection;
+use crate::network::net_message::{BitMask, CTcpType, NetworkMessage};
+use crate::network::NetworkPlugin;
+use crate::LOBBY_ID;
+use avian3d::prelude::{Collider, CollisionLayers, LayerMask, Position, RigidBody};
+use avian3d::PhysicsPlugins;
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::scene::ScenePlugin;
+use std::time::Duration;
+
+/// How often the headless client prints where it is.
+const STATUS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// One step of the input script, held for `duration`.
+#[derive(Clone, Copy, Debug)]
+pub struct InputStep {
+    pub keymask: BitMask,
+    /// Mouse motion per frame while the step lasts.
+    pub mouse_delta: Vec2,
+    pub duration: Duration,
+}
+
+/// Input the headless client plays in a loop instead of reading a keyboard and mouse.
+#[derive(Resource, Debug)]
+pub struct ScriptedInput {
+    pub steps: Vec<InputStep>,
+}
+
+impl Default for ScriptedInput {
+    /// Walks a loop, turning on the way, with a pause at the end.
+    fn default() -> Self {
+        let step = |keymask, x, secs| InputStep {
+            keymask,
+            mouse_delta: Vec2::new(x, 0.0),
+            duration: Duration::from_secs_f32(secs),
+        };
+        Self {
+            steps: vec![
+                step(1, 0.0, 2.0),
+                step(1 | 4, 20.0, 1.0),
+                step(2, 0.0, 2.0),
+                step(8, -20.0, 1.0),
+                step(0, 0.0, 1.0),
+            ],
+        }
+    }
+}
+
+impl ScriptedInput {
+    /// The step playing `elapsed` into the script, which starts over once it runs out.
+    pub fn step_at(&self, elapsed: Duration) -> Option<&InputStep> {
+        let total: Duration = self.steps.iter().map(|s| s.duration).sum();
+        if total.is_zero() {
+            return None;
+        }
+
+        let mut into = Duration::from_nanos((elapsed.as_nanos() % total.as_nanos()) as u64);
+        for step in self.steps.iter() {
+            if into < step.duration {
+                return Some(step);
+            }
+            into -= step.duration;
+        }
+        None
+    }
+}
+
+/// Runs the client without a window or renderer: physics, networking and the gameplay side of
+/// the player, driven by [`ScriptedInput`]. Joins the lobby on its own once connected.
+pub struct HeadlessPlugin;
+
+impl Plugin for HeadlessPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            // Faster than the fixed rate so every tick sees fresh input
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / 120.0))),
+            TransformPlugin,
+            AssetPlugin::default(),
+            MeshPlugin,
+            ScenePlugin,
+            PhysicsPlugins::default().with_length_unit(10.0),
+            NetworkPlugin,
+            PlayerGameplayPlugin,
+        ));
+        app.init_resource::<ScriptedInput>();
+        app.add_systems(Startup, spawn_ground);
+        app.add_systems(PreUpdate, scripted_input);
+        app.add_systems(Update, (join_on_connect, print_status));
+    }
+}
+
+pub fn spawn_ground(mut commands: Commands) {
+    // Same ground as the windowed client's, without the mesh
+    commands.spawn((
+        RigidBody::Static,
+        Collider::cuboid(40.0, 0.5, 40.0),
+        CollisionLayers::new(CollisionLayer::Ground, [LayerMask::ALL]),
+        Transform::default(),
+    ));
+}
+
+/// Stands in for `input_system`, feeding the current step of the script into [`PlayerInfo`].
+fn scripted_input(
+    script: Res<ScriptedInput>,
+    mut player_info: ResMut<PlayerInfo>,
+    time: Res<Time<Real>>,
+) {
+    let (keymask, mouse_delta) = script
+        .step_at(time.elapsed())
+        .map_or((0, Vec2::ZERO), |step| (step.keymask, step.mouse_delta));
+
+    player_info.player_inputs = keymask;
+    player_info.record_mouse_delta(mouse_delta);
+}
+
+/// Asks to join every time the handshake completes, so a reconnect ends up back in the lobby.
+fn join_on_connect(
+    mut state_events: EventReader<ConnectionStateChanged>,
+    mut connection: ResMut<TcpConnection>,
+) {
+    for ev in state_events.read() {
+        if ev.current == ConnectionState::Connected {
+            connection.add_message(NetworkMessage(CTcpType::Join { lobby_id: Id(LOBBY_ID) }));
+        }
+    }
+}
+
+/// There is no HUD, so the connection state and position go to stdout now and then.
+fn print_status(
+    lifecycle: Res<ConnectionLifecycle>,
+    player_info: Res<PlayerInfo>,
+    players: Query<(&Id, &Position)>,
+    time: Res<Time<Real>>,
+    mut last: Local<Duration>,
+) {
+    if time.elapsed() < *last + STATUS_INTERVAL {
+        return;
+    }
+    *last = time.elapsed();
+
+    let position = players
+        .iter()
+        .find(|(id, _)| **id == player_info.current_player_id)
+        .map(|(_, position)| position.0);
+    println!("{:?} as {:?} at {:?}", lifecycle.state, player_info.current_player_id, position);
+}
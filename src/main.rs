@@ -1,22 +1,25 @@
 mod components;
+mod headless;
+mod mock_server;
 mod network;
+mod replay;
+#[cfg(test)]
 mod test;
 
 use crate::components::chat::{Chat, chat_window};
-use crate::components::hud::Hud;
+use crate::components::hud::{connection_status_hud, ConnectionStatusHud, Hud};
 use crate::components::player::{PlayerInfo, player_controller, PlayerMarker, update_label_pos};
-use crate::network::net_manage::{
-    Communication, TcpConnection,
-};
+use crate::network::net_manage::TcpConnection;
 use crate::network::net_message::{NetworkMessage, CTcpType};
 use bevy::input::ButtonState;
 use bevy::input::keyboard::KeyboardInput;
 use bevy::prelude::*;
 use bevy_inspector_egui::DefaultInspectorConfigPlugin;
-use bevy_inspector_egui::bevy_egui::EguiPlugin;
+use bevy_inspector_egui::bevy_egui::{EguiPlugin, EguiPrimaryContextPass};
 use bevy_inspector_egui::quick::{ResourceInspectorPlugin, WorldInspectorPlugin};
 use std::collections::VecDeque;
 use std::io;
+use std::path::Path;
 use avian3d::PhysicsPlugins;
 use avian3d::prelude::{Collider, CollisionLayers, LayerMask, LinearVelocity, Physics, PhysicsDebugPlugin, PhysicsTime, RigidBody, Sleeping};
 use bevy::dev_tools::fps_overlay::FpsOverlayPlugin;
@@ -26,8 +29,20 @@ use crate::components::CollisionLayer;
 use crate::components::common::Id;
 use crate::components::player::animation::{animation_control, player_animations, setup_player_animations};
 use crate::components::player::plugin::PlayerPlugin;
+use crate::components::player::smoothing::ErrorSmoothing;
 use crate::components::weapon::{weapon_controller, Weapon};
-use crate::network::{NetworkPlugin, RemoteAddress};
+use crate::network::{ClientName, NetworkPlugin, RemoteAddress, SERVER_TICK_RATE};
+use crate::network::net_conditioner::LinkConditioner;
+use crate::network::net_connection::ConnectionLifecycle;
+use crate::network::net_crypto::{load_psk, TransportSecurity, PSK_ENV_VAR};
+use crate::network::net_websocket::check_websocket_url;
+use crate::network::net_reconciliation::{InputRedundancy, ReconcilePolicy};
+use crate::network::net_stats::net_graph_ui;
+use crate::network::net_transport::LOOPBACK_ADDRESS;
+use crate::mock_server::spawn_mock_server;
+use crate::headless::HeadlessPlugin;
+use crate::network::net_capture::PacketCapture;
+use crate::replay::{replay_app, CaptureReplay};
 
 #[derive(Resource)]
 pub struct DefaultFont(pub Handle<Font>);
@@ -36,6 +51,8 @@ const LOBBY_ID: u32 = 1;
 fn join_lobby(
     mut keyboard_input: EventReader<KeyboardInput>,
     mut connection: ResMut<TcpConnection>,
+    mut lifecycle: ResMut<ConnectionLifecycle>,
+    time: Res<Time<Real>>,
 ) {
     for k in keyboard_input.read() {
         if k.state == ButtonState::Released {
@@ -44,10 +61,15 @@ fn join_lobby(
 
         match k.key_code {
             KeyCode::KeyJ => {
-                if connection.stream.is_some() {
+                if lifecycle.is_connected() {
                     connection.add_message(NetworkMessage(CTcpType::Join { lobby_id: Id(LOBBY_ID) }));
                 }
             }
+            KeyCode::KeyR => {
+                if !lifecycle.is_connected() {
+                    lifecycle.retry_now(time.elapsed());
+                }
+            }
             _ => {}
         }
     }
@@ -56,34 +78,77 @@ fn join_lobby(
 fn main() -> io::Result<()> {
     let args: Vec<String> = std::env::args().collect();
     let default_address = "127.0.0.1:4444".to_string();
-    let remote_address = args.get(1).unwrap_or(&default_address);
+    let remote_address = args.iter().skip(1).find(|a| !a.starts_with("--")).unwrap_or(&default_address);
+    check_websocket_url(remote_address)?;
+    if args.iter().any(|a| a.starts_with("--secure=")) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the pre-shared key isn't taken on the command line, set {} or pass --psk-file=<path>", PSK_ENV_VAR)));
+    }
+    let psk_file = args.iter().find_map(|a| a.strip_prefix("--psk-file=")).map(Path::new);
+    let psk = if args.iter().any(|a| a == "--secure") || psk_file.is_some() {
+        Some(load_psk(psk_file)?)
+    } else {
+        None
+    };
+    let mock_server = args.iter().any(|a| a == "--mock-server");
+    let headless = args.iter().any(|a| a == "--headless");
+    let capture = args.iter().find_map(|a| a.strip_prefix("--capture="));
+    let replay = args.iter().find_map(|a| a.strip_prefix("--replay="));
+    let step = args.iter().any(|a| a == "--step");
+    let client_name = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "player".to_string());
     
+    if let Some(path) = replay {
+        replay_app(CaptureReplay::open(path, step)?).run();
+        return Ok(());
+    }
+
     let mut app = App::new();
-    app.add_plugins((
-        DefaultPlugins,
-        PhysicsPlugins::default().with_length_unit(10.0),
-        EguiPlugin::default(),
-        WorldInspectorPlugin::new(),
-        ResourceInspectorPlugin::<PlayerInfo>::default(),
-        FpsOverlayPlugin::default(),
-        // PhysicsDebugPlugin::default(),
-        NetworkPlugin,
-        PlayerPlugin
-    ));
-    app.insert_resource(Time::<Fixed>::from_hz(60.0));
+    if headless {
+        app.add_plugins(HeadlessPlugin);
+    } else {
+        app.add_plugins((
+            DefaultPlugins,
+            PhysicsPlugins::default().with_length_unit(10.0),
+            EguiPlugin::default(),
+            WorldInspectorPlugin::new(),
+            ResourceInspectorPlugin::<PlayerInfo>::default(),
+            ResourceInspectorPlugin::<LinkConditioner>::default(),
+            ResourceInspectorPlugin::<InputRedundancy>::default(),
+            ResourceInspectorPlugin::<ReconcilePolicy>::default(),
+            ResourceInspectorPlugin::<ErrorSmoothing>::default(),
+            FpsOverlayPlugin::default(),
+            // PhysicsDebugPlugin::default(),
+            NetworkPlugin,
+            PlayerPlugin
+        ));
+        app.insert_resource(DefaultFont(Handle::default()));
+        app.add_systems(Startup, setup);
+        app.add_systems(
+            FixedUpdate,
+            (
+                join_lobby,
+                chat_window,
+                // debug_player_sleeping
+                // linear_is_changed
+            )
+        );
+        app.add_systems(Update, connection_status_hud);
+        app.add_systems(EguiPrimaryContextPass, net_graph_ui);
+    }
+    app.insert_resource(Time::<Fixed>::from_hz(SERVER_TICK_RATE));
     app.insert_resource(Time::<Physics>::default().with_relative_speed(1.0));
-    app.insert_resource(DefaultFont(Handle::default()));
     app.insert_resource(RemoteAddress(remote_address.clone()));
-    app.add_systems(Startup, setup);
-    app.add_systems(
-        FixedUpdate,
-        (
-            join_lobby,
-            chat_window,
-            // debug_player_sleeping
-            // linear_is_changed
-        )
-    );
+    app.insert_resource(ClientName(client_name));
+    app.insert_resource(TransportSecurity::new(psk.clone()));
+    if let Some(path) = capture {
+        app.insert_resource(PacketCapture::create(path)?);
+    }
+    if mock_server {
+        // Over the loopback unless an address was given, then on real sockets at that address
+        let listen = (remote_address != LOOPBACK_ADDRESS).then(|| remote_address.clone());
+        app.insert_resource(spawn_mock_server(listen, psk));
+    }
     app.run();
 
     Ok(())
@@ -143,6 +208,24 @@ fn setup(
         },
     ));
 
+    // Connection Status Hud
+    commands.spawn((
+        ConnectionStatusHud,
+        Text::new("Connecting"),
+        TextFont {
+            font: default_font.0.clone(),
+            font_size: 20.0,
+            line_height: Default::default(),
+            font_smoothing: FontSmoothing::None,
+        },
+        Node {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.5),
+            right: Val::Px(0.5),
+            ..default()
+        },
+    ));
+
     // Chat Window
     commands.spawn((
         Chat {
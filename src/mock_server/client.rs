@@ -0,0 +1,298 @@
+use crate::components::chat::ChatMessage;
+use crate::components::common::Id;
+use crate::network::net_channel::{UdpChannel, UdpPacket};
+use crate::network::net_clock::now_ms;
+use crate::network::net_crypto::{server_proof, KeyExchange, Role, SessionKeys};
+use crate::network::net_fragment::{fragment_payload, Datagram, Reassembler};
+use crate::network::net_message::{BitMask, CTcpType, CUdpType, STcpType, SUdpType, SequenceNumber, PROTOCOL_VERSION};
+use crate::network::net_reconciliation::BUFFER_SIZE;
+use crate::network::net_transport::Transport;
+use bevy::math::Vec2;
+use bevy::prelude::Entity;
+use bincode::config;
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// What a client asked for that involves more than its own connection.
+#[derive(Debug)]
+pub enum ClientRequest {
+    Join,
+    Chat(ChatMessage),
+    Leave,
+}
+
+/// Returns true if input `a` comes after input `b`. Client sequences wrap at [`BUFFER_SIZE`].
+pub fn input_is_newer(a: SequenceNumber, b: SequenceNumber) -> bool {
+    a != b && (a + BUFFER_SIZE - b) % BUFFER_SIZE < BUFFER_SIZE / 2
+}
+
+/// Everything the mock server knows about one connected client.
+pub struct MockClient {
+    pub transport: Box<dyn Transport>,
+    /// Address the session token was proven from, for clients on real sockets.
+    pub udp_addr: Option<SocketAddr>,
+    pub name: Option<String>,
+    pub player_id: Option<Id>,
+    pub entity: Option<Entity>,
+    pub token: u64,
+    pub bound: bool,
+    /// Proves our key in the exchange, clients asking for one are turned away without it.
+    psk: Option<Vec<u8>>,
+    keys: Option<SessionKeys>,
+    channel: UdpChannel<SUdpType, CUdpType>,
+    reassembler: Reassembler,
+    next_fragment_id: u16,
+    /// Inputs waiting to be simulated, by client sequence.
+    inputs: HashMap<SequenceNumber, (BitMask, Vec2)>,
+    /// Sequence of the next input to simulate, known once the first one arrives.
+    pub next_input: Option<SequenceNumber>,
+    reliable_out: Vec<STcpType>,
+    unreliable_out: Vec<SUdpType>,
+}
+
+impl MockClient {
+    pub fn new(transport: Box<dyn Transport>, psk: Option<Vec<u8>>) -> Self {
+        Self {
+            transport,
+            udp_addr: None,
+            name: None,
+            player_id: None,
+            entity: None,
+            token: rand::random(),
+            bound: false,
+            psk,
+            keys: None,
+            channel: UdpChannel::new(),
+            reassembler: Reassembler::default(),
+            next_fragment_id: 0,
+            inputs: HashMap::new(),
+            next_input: None,
+            reliable_out: Vec::new(),
+            unreliable_out: Vec::new(),
+        }
+    }
+
+    pub fn send_reliable(&mut self, message: STcpType) {
+        self.reliable_out.push(message);
+    }
+
+    /// Decodes one frame from the stream and answers whatever can be answered right away.
+    pub fn handle_reliable(&mut self, bytes: Vec<u8>) -> Result<Vec<ClientRequest>, Error> {
+        let bytes = match &mut self.keys {
+            Some(keys) => keys.tcp.open(&bytes)?,
+            None => bytes,
+        };
+        let (messages, _): (Vec<CTcpType>, usize) = bincode::serde::decode_from_slice(&bytes, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut requests = Vec::new();
+        let mut welcomed = false;
+        let mut client_key = None;
+        for message in messages {
+            match message {
+                CTcpType::Hello { protocol_version, client_name, .. } => {
+                    if protocol_version != PROTOCOL_VERSION {
+                        self.reliable_out.push(STcpType::Rejected {
+                            reason: format!("Mock server speaks v{}, client v{}", PROTOCOL_VERSION, protocol_version),
+                        });
+                        continue;
+                    }
+                    println!("Mock server: {} said hello", client_name);
+                    self.name = Some(client_name);
+                    self.reliable_out.push(STcpType::Welcome { protocol_version: PROTOCOL_VERSION });
+                    welcomed = true;
+                }
+                CTcpType::KeyExchange { public_key } => client_key = Some(public_key),
+                message => self.handle_control(message, &mut requests),
+            }
+        }
+
+        if welcomed {
+            let mut keys = None;
+            if let Some(client_key) = client_key {
+                let Some(psk) = &self.psk else {
+                    self.reliable_out.push(STcpType::Rejected {
+                        reason: "Mock server has no pre-shared key, start it in secure mode too".to_string(),
+                    });
+                    return self.flush_reliable().map(|_| requests);
+                };
+                let exchange = KeyExchange::new();
+                let proof = server_proof(psk, &client_key, &exchange.public_key);
+                self.reliable_out.push(STcpType::KeyExchange { public_key: exchange.public_key, proof });
+                keys = Some(exchange.finish(client_key, Role::Server)?);
+            }
+            // The handshake answer goes out in the clear, everything after it is sealed
+            self.flush_reliable()?;
+            self.keys = keys;
+            self.reliable_out.push(STcpType::SessionToken { token: self.token });
+        }
+        Ok(requests)
+    }
+
+    fn handle_control(&mut self, message: CTcpType, requests: &mut Vec<ClientRequest>) {
+        match message {
+            CTcpType::Join { .. } if self.name.is_some() => requests.push(ClientRequest::Join),
+            CTcpType::ChatMessage { message, .. } if self.player_id.is_some() => requests.push(ClientRequest::Chat(message)),
+            CTcpType::Leave => requests.push(ClientRequest::Leave),
+            _ => {}
+        }
+    }
+
+    /// Checks whether a datagram from an unknown address proves this client's token. The
+    /// datagram is used up by the check, `Connect` is repeated until the client hears back.
+    pub fn proves_token(&mut self, datagram: &[u8]) -> bool {
+        let Ok((Datagram::Whole(bytes), _)) = bincode::serde::decode_from_slice::<Datagram, _>(datagram, config::standard()) else {
+            return false;
+        };
+        let bytes = match &mut self.keys {
+            Some(keys) => match keys.udp.open(&bytes) {
+                Ok(b) => b,
+                Err(_) => return false,
+            },
+            None => bytes,
+        };
+        let Ok((packet, _)) = bincode::serde::decode_from_slice::<UdpPacket<CUdpType>, _>(&bytes, config::standard()) else {
+            return false;
+        };
+
+        let token = self.token;
+        let proven = packet
+            .unreliable
+            .iter()
+            .chain(packet.sequenced.iter())
+            .chain(packet.reliable.iter().map(|(_, m)| m))
+            .any(|m| matches!(m, CUdpType::Connect { token: t } if *t == token));
+        if proven {
+            self.bound = true;
+            self.unreliable_out.push(SUdpType::SessionBound);
+        }
+        proven
+    }
+
+    /// Drops partial messages older than `timeout`, so a reused fragment id starts over.
+    pub fn expire_fragments(&mut self, now: Duration, timeout: Duration) -> usize {
+        self.reassembler.expire(now, timeout)
+    }
+
+    pub fn handle_datagram(&mut self, datagram: &[u8], now: Duration, server_tick: u32) -> Result<Vec<ClientRequest>, Error> {
+        let Some(bytes) = self.reassembler.push(datagram, now)? else {
+            return Ok(Vec::new());
+        };
+        let bytes = match &mut self.keys {
+            Some(keys) => keys.udp.open(&bytes)?,
+            None => bytes,
+        };
+        let (packet, _): (UdpPacket<CUdpType>, usize) = bincode::serde::decode_from_slice(&bytes, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let mut requests = Vec::new();
+        let Some(messages) = self.channel.receive_packet(packet, now) else {
+            return Ok(requests);
+        };
+
+        let sequence = messages.iter().find_map(|m| match m {
+            CUdpType::Sequence { sequence_number } => Some(*sequence_number),
+            _ => None,
+        });
+
+        for message in messages {
+            match message {
+                CUdpType::Connect { token } => {
+                    if token == self.token {
+                        self.bound = true;
+                        self.unreliable_out.push(SUdpType::SessionBound);
+                    }
+                }
+                CUdpType::Input { keymask, mouse_delta, history } => {
+                    if let Some(sequence) = sequence && self.bound {
+                        self.store_input(sequence, keymask, mouse_delta, true);
+                        for frame in history {
+                            self.store_input(frame.sequence_number, frame.keymask, frame.mouse_delta, false);
+                        }
+                    }
+                }
+                CUdpType::Ping { intitiation_time, .. } => {
+                    self.unreliable_out.push(SUdpType::Pong {
+                        initiation_time: intitiation_time,
+                        server_received_time: now_ms(),
+                        server_tick,
+                    });
+                }
+                CUdpType::Control { message } => self.handle_control(message, &mut requests),
+                // Snapshots always go out in full, so there is no baseline to track
+                CUdpType::SnapshotAck { .. } | CUdpType::Sequence { .. } => {}
+            }
+        }
+        Ok(requests)
+    }
+
+    fn store_input(&mut self, sequence: SequenceNumber, keymask: BitMask, mouse_delta: Vec2, newest: bool) {
+        let next = *self.next_input.get_or_insert(sequence);
+        if sequence != next && !input_is_newer(sequence, next) {
+            if newest {
+                self.unreliable_out.push(SUdpType::InputFeedback { sequence_number: sequence, buffered: -1 });
+            }
+            return;
+        }
+        self.inputs.entry(sequence).or_insert((keymask, mouse_delta));
+    }
+
+    /// Input to simulate this tick. A missing input is skipped once newer ones are waiting.
+    pub fn next_input_frame(&mut self) -> Option<(BitMask, Vec2)> {
+        let next = self.next_input?;
+        if let Some(frame) = self.inputs.remove(&next) {
+            self.next_input = Some((next + 1) % BUFFER_SIZE);
+            self.unreliable_out.push(SUdpType::InputFeedback {
+                sequence_number: next,
+                buffered: self.inputs.len() as i16,
+            });
+            return Some(frame);
+        }
+
+        if !self.inputs.is_empty() {
+            self.next_input = Some((next + 1) % BUFFER_SIZE);
+        }
+        None
+    }
+
+    pub fn queue_unreliable(&mut self, message: SUdpType) {
+        self.unreliable_out.push(message);
+    }
+
+    pub fn flush_reliable(&mut self) -> Result<(), Error> {
+        if self.reliable_out.is_empty() {
+            return Ok(());
+        }
+
+        let bytes = bincode::serde::encode_to_vec(&self.reliable_out, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        self.reliable_out.clear();
+        let bytes = match &mut self.keys {
+            Some(keys) => keys.tcp.seal(&bytes)?,
+            None => bytes,
+        };
+        self.transport.send_reliable(bytes)
+    }
+
+    /// Packs the queued unreliable messages into datagrams no larger than `mtu`.
+    pub fn build_datagrams(&mut self, mtu: usize, now: Duration) -> Result<Vec<Vec<u8>>, Error> {
+        let unreliable = std::mem::take(&mut self.unreliable_out);
+        let packet = self.channel.build_packet(unreliable, Vec::new(), now);
+        let bytes = bincode::serde::encode_to_vec(&packet, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        let bytes = match &mut self.keys {
+            Some(keys) => keys.udp.seal(&bytes)?,
+            None => bytes,
+        };
+
+        let message_id = self.next_fragment_id;
+        self.next_fragment_id = self.next_fragment_id.wrapping_add(1);
+        fragment_payload(&bytes, mtu, message_id)
+    }
+
+    pub fn has_unreliable(&self) -> bool {
+        !self.unreliable_out.is_empty()
+    }
+}
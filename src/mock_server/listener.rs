@@ -0,0 +1,99 @@
+use crate::network::net_address::resolve_server_address;
+use crate::network::net_fragment::MAX_DATAGRAM_SIZE;
+use crate::network::net_manage::{start_tcp_task, LinkEvent};
+use crate::network::net_transport::{FlushRequest, SocketTransport};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver};
+
+/// The mock server's real sockets: a TCP listener handing out one transport per client, and a
+/// single UDP socket shared by all of them.
+pub struct ServerSockets {
+    pub accepted: UnboundedReceiver<SocketTransport>,
+    pub udp_tx: Sender<(Vec<u8>, SocketAddr)>,
+    pub udp_rx: Receiver<(Vec<u8>, SocketAddr)>,
+}
+
+/// Binds TCP and UDP on `address` and starts accepting clients.
+pub fn listen(address: String, runtime: &TokioTasksRuntime) -> ServerSockets {
+    let (accepted_tx, accepted) = mpsc::unbounded_channel::<SocketTransport>();
+    let (udp_tx, mut udp_send_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
+    let (udp_receive_tx, udp_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
+
+    runtime.spawn_background_task(|_| async move {
+        let bound = async {
+            let addr = resolve_server_address(&address).await?[0];
+            let listener = TcpListener::bind(addr).await?;
+            let socket = UdpSocket::bind(addr).await?;
+            Ok::<_, std::io::Error>((addr, listener, Arc::new(socket)))
+        };
+        let (addr, listener, socket) = match bound.await {
+            Ok(b) => b,
+            Err(e) => {
+                println!("Mock server couldn't listen on {}: {}", address, e);
+                return;
+            }
+        };
+        println!("Mock server listening on {}", addr);
+
+        let s = socket.clone();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+            loop {
+                tokio::select! {
+                    // The server was dropped
+                    _ = udp_receive_tx.closed() => break,
+                    received = s.recv_from(&mut buf) => match received {
+                        Ok((len, addr)) => {
+                            if udp_receive_tx.send((buf[..len].to_vec(), addr)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => eprintln!("recv error: {e}, continuing..."),
+                    }
+                }
+            }
+        });
+
+        tokio::spawn(async move {
+            while let Some((bytes, addr)) = udp_send_rx.recv().await {
+                if let Err(e) = socket.send_to(&bytes, &addr).await {
+                    println!("send error: {}", e);
+                }
+            }
+        });
+
+        loop {
+            tokio::select! {
+                _ = accepted_tx.closed() => break,
+                accepted = listener.accept() => match accepted {
+                    Ok((stream, peer)) => {
+                        println!("Mock server accepted {}", peer);
+                        if accepted_tx.send(accept(stream)).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => println!("Mock server couldn't accept: {}", e),
+                }
+            }
+        }
+    });
+
+    ServerSockets { accepted, udp_tx, udp_rx }
+}
+
+/// Wraps an accepted stream in the same transport the client uses. Its datagrams arrive on the
+/// shared socket instead, so the transport's own UDP side stays unused.
+fn accept(stream: TcpStream) -> SocketTransport {
+    let (tcp_send_tx, tcp_send_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_receive_tx, tcp_receive_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_flush_tx, tcp_flush_rx) = mpsc::channel::<FlushRequest>(1);
+    let (status_tx, status_rx) = mpsc::channel::<LinkEvent>(16);
+    let (udp_tx, _) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1);
+    let (_, udp_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1);
+
+    start_tcp_task(stream, tcp_send_rx, tcp_flush_rx, tcp_receive_tx, status_tx);
+    SocketTransport::new(udp_tx, udp_rx, tcp_send_tx, tcp_receive_rx, tcp_flush_tx, status_rx)
+}
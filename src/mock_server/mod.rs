@@ -0,0 +1,321 @@
+pub mod client;
+pub mod listener;
+
+use crate::components::camera::{apply_player_camera_input, CameraInfo};
+use crate::components::common::{Id, Vec3};
+use crate::components::player::animation::AnimationState;
+use crate::components::player::{apply_player_movement_input, Player};
+use crate::components::CollisionLayer;
+use crate::mock_server::client::{ClientRequest, MockClient};
+use crate::mock_server::listener::{listen, ServerSockets};
+use crate::network::net_fragment::FragmentSettings;
+use crate::network::net_manage::LinkEvent;
+use crate::network::net_message::{STcpType, SUdpType, SequenceNumber};
+use crate::network::net_transport::{queue_error, LoopbackListener, LoopbackTransport};
+use crate::network::SERVER_TICK_RATE;
+use avian3d::prelude::{Collider, CollisionLayers, Friction, LayerMask, LinearVelocity, LockedAxes, Position, RigidBody, Rotation};
+use avian3d::PhysicsPlugins;
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::scene::ScenePlugin;
+use bevy_tokio_tasks::{TokioTasksPlugin, TokioTasksRuntime};
+use std::collections::{HashMap, HashSet};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+/// A player simulated by the mock server.
+#[derive(Component, Debug)]
+pub struct MockPlayer {
+    pub id: Id,
+    pub animation_state: AnimationState,
+}
+
+/// Address the mock server listens on besides the loopback, if any.
+#[derive(Resource)]
+pub struct MockServerAddress(pub Option<String>);
+
+/// Stand-in for the game server, enough of it to join, chat and move around without one.
+#[derive(Resource)]
+pub struct MockServer {
+    pub clients: Vec<MockClient>,
+    loopback: UnboundedReceiver<LoopbackTransport>,
+    sockets: Option<ServerSockets>,
+    /// Secure mode's pre-shared key, handed to every client.
+    psk: Option<Vec<u8>>,
+    next_player_id: u32,
+    pub tick: u32,
+    pub snapshot: SequenceNumber,
+}
+
+impl MockServer {
+    pub fn new(loopback: UnboundedReceiver<LoopbackTransport>, psk: Option<Vec<u8>>) -> Self {
+        Self {
+            clients: Vec::new(),
+            loopback,
+            sockets: None,
+            psk,
+            next_player_id: 1,
+            tick: 0,
+            snapshot: 0,
+        }
+    }
+}
+
+/// Builds the headless server app. Clients connect through `loopback`, and over TCP and UDP
+/// on `listen` if given. Secure mode is available to clients with `psk`.
+pub fn mock_server_app(loopback: UnboundedReceiver<LoopbackTransport>, listen: Option<String>, psk: Option<Vec<u8>>) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(1.0 / SERVER_TICK_RATE))),
+        TransformPlugin,
+        AssetPlugin::default(),
+        MeshPlugin,
+        ScenePlugin,
+        PhysicsPlugins::default().with_length_unit(10.0),
+        TokioTasksPlugin::default(),
+    ));
+    app.insert_resource(Time::<Fixed>::from_hz(SERVER_TICK_RATE));
+    app.insert_resource(MockServer::new(loopback, psk));
+    app.insert_resource(MockServerAddress(listen));
+    app.add_systems(Startup, setup_mock_server);
+    app.add_systems(FixedPreUpdate, receive_client_messages);
+    app.add_systems(FixedUpdate, simulate_inputs);
+    app.add_systems(FixedLast, broadcast_snapshot);
+    app
+}
+
+/// Runs the mock server on its own thread and returns the listener to reach it through.
+pub fn spawn_mock_server(listen: Option<String>, psk: Option<Vec<u8>>) -> LoopbackListener {
+    let (listener, incoming) = LoopbackListener::new();
+    thread::spawn(move || {
+        mock_server_app(incoming, listen, psk).run();
+    });
+    listener
+}
+
+fn setup_mock_server(
+    mut commands: Commands,
+    mut server: ResMut<MockServer>,
+    address: Res<MockServerAddress>,
+    runtime: Res<TokioTasksRuntime>,
+) {
+    // Same ground as the client's
+    commands.spawn((
+        RigidBody::Static,
+        Collider::cuboid(40.0, 0.5, 40.0),
+        CollisionLayers::new(CollisionLayer::Ground, [LayerMask::ALL]),
+        Transform::default(),
+    ));
+
+    if let Some(address) = &address.0 {
+        server.sockets = Some(listen(address.clone(), &runtime));
+    }
+}
+
+fn spawn_mock_player(commands: &mut Commands, id: Id) -> Entity {
+    commands
+        .spawn((
+            RigidBody::Dynamic,
+            Collider::capsule(0.5, 1.0),
+            Friction::new(1.0),
+            LockedAxes::new().lock_rotation_x().lock_rotation_y().lock_rotation_z(),
+            Position::from_xyz(0.0, 2.0, 0.0),
+            CollisionLayers::new(CollisionLayer::Player, [LayerMask::ALL]),
+            Transform::default(),
+            CameraInfo { yaw: 0.0, pitch: 0.0 },
+            MockPlayer { id, animation_state: AnimationState::Idle },
+        ))
+        .id()
+}
+
+pub fn receive_client_messages(
+    mut commands: Commands,
+    mut server: ResMut<MockServer>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+    let server = &mut *server;
+
+    while let Ok(transport) = server.loopback.try_recv() {
+        server.clients.push(MockClient::new(Box::new(transport), server.psk.clone()));
+    }
+    if let Some(sockets) = &mut server.sockets {
+        while let Ok(transport) = sockets.accepted.try_recv() {
+            server.clients.push(MockClient::new(Box::new(transport), server.psk.clone()));
+        }
+    }
+
+    let mut requests = Vec::new();
+    for (index, client) in server.clients.iter_mut().enumerate() {
+        while let Some(event) = client.transport.poll_event() {
+            if event == LinkEvent::ReliableClosed {
+                requests.push((index, ClientRequest::Leave));
+            }
+        }
+        while let Some(bytes) = client.transport.receive_reliable() {
+            match client.handle_reliable(bytes) {
+                Ok(r) => requests.extend(r.into_iter().map(|r| (index, r))),
+                Err(e) => println!("Mock server dropped a TCP message: {:?}", e),
+            }
+        }
+        while let Some(bytes) = client.transport.receive_unreliable() {
+            match client.handle_datagram(&bytes, now, server.tick) {
+                Ok(r) => requests.extend(r.into_iter().map(|r| (index, r))),
+                Err(e) => println!("Mock server dropped a UDP message: {:?}", e),
+            }
+        }
+    }
+
+    if let Some(sockets) = &mut server.sockets {
+        while let Ok((bytes, addr)) = sockets.udp_rx.try_recv() {
+            let Some(index) = server.clients.iter().position(|c| c.udp_addr == Some(addr)) else {
+                // Unknown endpoints have to prove a session token first
+                if let Some(client) = server.clients.iter_mut().filter(|c| !c.bound).find_map(|c| c.proves_token(&bytes).then_some(c)) {
+                    client.udp_addr = Some(addr);
+                }
+                continue;
+            };
+            match server.clients[index].handle_datagram(&bytes, now, server.tick) {
+                Ok(r) => requests.extend(r.into_iter().map(|r| (index, r))),
+                Err(e) => println!("Mock server dropped a UDP message: {:?}", e),
+            }
+        }
+    }
+
+    let reassembly_timeout = FragmentSettings::default().reassembly_timeout;
+    for client in server.clients.iter_mut() {
+        client.expire_fragments(now, reassembly_timeout);
+    }
+
+    let mut leaving = HashSet::new();
+    for (index, request) in requests {
+        match request {
+            ClientRequest::Join => {
+                let client = &mut server.clients[index];
+                if client.player_id.is_some() {
+                    continue;
+                }
+                let id = Id(server.next_player_id);
+                server.next_player_id += 1;
+                client.player_id = Some(id);
+                client.entity = Some(spawn_mock_player(&mut commands, id));
+                client.send_reliable(STcpType::PlayerId { player_uid: id });
+            }
+            ClientRequest::Chat(message) => {
+                let Some(sender) = server.clients[index].player_id else {
+                    continue;
+                };
+                for client in server.clients.iter_mut().filter(|c| c.player_id.is_some()) {
+                    client.send_reliable(STcpType::Chat { messages: vec![(sender, message.clone())] });
+                }
+            }
+            ClientRequest::Leave => {
+                leaving.insert(index);
+            }
+        }
+    }
+
+    if leaving.is_empty() {
+        return;
+    }
+    let clients = std::mem::take(&mut server.clients);
+    for (index, client) in clients.into_iter().enumerate() {
+        if !leaving.contains(&index) {
+            server.clients.push(client);
+            continue;
+        }
+        println!("Mock server: {:?} left", client.name);
+        if let Some(entity) = client.entity {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn simulate_inputs(
+    mut server: ResMut<MockServer>,
+    mut players: Query<(&mut LinearVelocity, &mut Rotation, &mut CameraInfo, &mut MockPlayer)>,
+) {
+    for client in server.clients.iter_mut() {
+        let Some(entity) = client.entity else {
+            continue;
+        };
+        let Ok((mut linear_velocity, mut rotation, mut camera_info, mut player)) = players.get_mut(entity) else {
+            continue;
+        };
+        let Some((keymask, mouse_delta)) = client.next_input_frame() else {
+            continue;
+        };
+
+        // Same order as the client's resimulation
+        if keymask != 0 {
+            player.animation_state = AnimationState::Walking;
+            apply_player_movement_input(keymask, &mut linear_velocity, &mut rotation, &camera_info.yaw);
+        } else {
+            player.animation_state = AnimationState::Idle;
+        }
+        apply_player_camera_input(mouse_delta, &mut camera_info);
+    }
+}
+
+/// Sends every client the simulated players, tagged with the sequence of the next input it
+/// expects from them. That is the tick the client stored its prediction of this state under.
+pub fn broadcast_snapshot(
+    mut server: ResMut<MockServer>,
+    players: Query<(&Position, &LinearVelocity, &CameraInfo, &MockPlayer)>,
+    time: Res<Time<Real>>,
+) {
+    let server = &mut *server;
+    server.tick = server.tick.wrapping_add(1);
+    let snapshot = server.snapshot;
+    server.snapshot = server.snapshot.wrapping_add(1);
+
+    let players: HashMap<Id, Player> = players
+        .iter()
+        .map(|(position, linear_velocity, camera_info, player)| {
+            (
+                player.id,
+                Player::new(
+                    Vec3::new(position.x, position.y, position.z),
+                    Vec3::new(linear_velocity.x, linear_velocity.y, linear_velocity.z),
+                    camera_info.yaw,
+                    camera_info.pitch,
+                    player.animation_state,
+                ),
+            )
+        })
+        .collect();
+
+    let mtu = FragmentSettings::default().mtu;
+    for client in server.clients.iter_mut() {
+        if let Err(e) = client.flush_reliable() {
+            println!("Mock server couldn't send TCP message: {:?}", e);
+        }
+
+        if client.bound && let Some(sequence_number) = client.next_input {
+            client.queue_unreliable(SUdpType::Sequence { sequence_number });
+            client.queue_unreliable(SUdpType::Players { snapshot, players: players.clone() });
+        }
+        if !client.has_unreliable() {
+            continue;
+        }
+
+        let datagrams = match client.build_datagrams(mtu, time.elapsed()) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("Mock server couldn't build UDP message: {:?}", e);
+                continue;
+            }
+        };
+        for datagram in datagrams {
+            let sent = match (client.udp_addr, &server.sockets) {
+                (Some(addr), Some(sockets)) => sockets.udp_tx.try_send((datagram, addr)).map_err(queue_error),
+                _ => client.transport.send_unreliable(datagram),
+            };
+            if sent.is_err() {
+                break;
+            }
+        }
+    }
+}
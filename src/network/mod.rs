@@ -1,76 +1,118 @@
-use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::str::FromStr;
-use std::sync::Arc;
 use avian3d::parry::na::DimAdd;
-use bevy::app::{App, Plugin};
-use bevy::prelude::{Commands, FixedPostUpdate, FixedPreUpdate, FixedUpdate, IntoScheduleConfigs, PreStartup, Res, Resource};
+use bevy::app::{App, Plugin, RunFixedMainLoop, RunFixedMainLoopSystem};
+use bevy::prelude::{Commands, FixedFirst, FixedPostUpdate, FixedPreUpdate, FixedUpdate, IntoScheduleConfigs, Last, PreStartup, PreUpdate, Real, Update, Res, ResMut, Resource, Time};
 use bevy_inspector_egui::egui::TextBuffer;
 use bevy_tokio_tasks::{TokioTasksPlugin, TokioTasksRuntime};
-use tokio::net::TcpStream;
-use tokio::sync::mpsc;
-use crate::components::player::Player;
-use crate::network::net_manage::{start_tcp_task, start_udp_task, Communication, TcpConnection, UdpConnection};
-use crate::network::net_message::SequenceNumber;
-use crate::network::net_reconciliation::{game_state_system, ObjectState, ReconcileBuffer, BUFFER_SIZE};
-use crate::network::net_reconciliation::StateType::{InputState, PlayerState};
+use tokio::sync::watch;
+use crate::network::net_capture::flush_packet_capture;
+use crate::network::net_channel::ControlRoute;
+use crate::network::net_conditioner::{sync_link_conditioner, LinkConditioner, LinkConditionerSync};
+use crate::network::net_clock::{advance_clock_tick, ClockSync};
+use crate::network::net_connection::{connection_system, send_leave_on_exit, ConnectionLifecycle, ConnectionState, ConnectionStateChanged};
+use crate::network::net_crypto::TransportSecurity;
+use crate::network::net_dilation::{apply_time_dilation, InputLead};
+use crate::network::net_fragment::FragmentSettings;
+use crate::network::net_manage::{open_communications, TcpConnection, UdpConnection};
+use crate::network::net_reconciliation::{InputRedundancy, ReconcileBuffer, ReconcilePolicy};
+use crate::network::net_session::prove_udp_session;
+use crate::network::net_snapshot::{add_snapshot_ack, SnapshotBuffer};
+use crate::network::net_stats::{sample_net_stats, NetStats};
 use crate::network::net_system::{tcp_client_net_receive, tcp_client_net_send, udp_client_net_receive, udp_client_net_send};
 use crate::network::net_tasks::{add_ping_message, handle_tcp_message, handle_udp_message};
+use crate::network::net_transport::LoopbackListener;
 
+pub mod net_address;
+pub mod net_capture;
+pub mod net_channel;
+pub mod net_clock;
+pub mod net_conditioner;
+pub mod net_connection;
+pub mod net_crypto;
+pub mod net_dilation;
+pub mod net_fragment;
+pub mod net_framing;
 pub mod net_manage;
 pub mod net_message;
+pub mod net_outbound;
+pub mod net_quantize;
 pub mod net_reconciliation;
+pub mod net_rollback;
+pub mod net_session;
+pub mod net_snapshot;
+pub mod net_stats;
 pub mod net_system;
 pub mod net_tasks;
+pub mod net_transport;
+pub mod net_websocket;
+
+/// Fixed ticks per second the server simulates at.
+pub const SERVER_TICK_RATE: f64 = 60.0;
 
 #[derive(Resource)]
 pub struct RemoteAddress(pub String);
 
+/// Name announced to the server in the `Hello` handshake.
+#[derive(Resource)]
+pub struct ClientName(pub String);
+
 pub struct NetworkPlugin;
 
-impl Plugin for NetworkPlugin {
+/// The connection state the message handlers work on, without the sockets or the systems
+/// driving them. Replays use it to run the handlers on their own.
+pub struct NetworkStatePlugin;
+
+impl Plugin for NetworkStatePlugin {
     fn build(&self, app: &mut App) {
-        let mut initial_reconcile_buffer: HashMap<SequenceNumber, Vec<ObjectState>> = HashMap::new();
-        for i in 0..BUFFER_SIZE {
-            let mut object_states = Vec::new();
-            object_states.push(ObjectState(PlayerState{ player: Player::default() }));
-            object_states.push(ObjectState(InputState { encoded_input: 0, mouse_delta: Default::default() }));
-            initial_reconcile_buffer.insert(i, object_states);
-        }
-        
         app
-            .add_plugins(TokioTasksPlugin::default())
-            .insert_resource(UdpConnection::new(None))
-            .insert_resource(TcpConnection::new(None))
-            .insert_resource(ReconcileBuffer {
-                buffer: HashMap::new(),
-                sequence_counter: 0,
-                miss_predict_counter: 0,
-            })
+            .insert_resource(UdpConnection::new())
+            .insert_resource(TcpConnection::new())
+            .insert_resource(ConnectionLifecycle::default())
+            .insert_resource(ControlRoute::default())
+            .insert_resource(FragmentSettings::default())
+            .init_resource::<LinkConditioner>()
+            .init_resource::<NetStats>()
+            .insert_resource(ClockSync::new(SERVER_TICK_RATE))
+            .init_resource::<InputLead>()
+            .init_resource::<InputRedundancy>()
+            .init_resource::<ReconcilePolicy>()
+            .init_resource::<SnapshotBuffer>()
+            .init_resource::<TransportSecurity>()
+            .add_event::<ConnectionStateChanged>()
             // .insert_resource(ReconcilePlayerState{
             //     player: Player::default()
             // })
+            .init_resource::<ReconcileBuffer>();
+    }
+}
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app
+            .add_plugins((TokioTasksPlugin::default(), NetworkStatePlugin))
             .add_systems(PreStartup, setup_communications)
+            .add_systems(PreUpdate, (sync_link_conditioner, connection_system).chain())
+            .add_systems(RunFixedMainLoop, apply_time_dilation.in_set(RunFixedMainLoopSystem::AfterFixedMainLoop))
+            .add_systems(Update, sample_net_stats)
+            .add_systems(Last, (send_leave_on_exit, flush_packet_capture))
+            .add_systems(FixedFirst, advance_clock_tick)
             .add_systems(
                 FixedPreUpdate,
                 (
                     udp_client_net_receive,
                     tcp_client_net_receive,
                     add_ping_message.after(handle_udp_message),
+                    prove_udp_session,
                 )
             )
             .add_systems(
                 FixedPostUpdate,
                 (
-                    game_state_system,
-                    (
-                        handle_udp_message,
-                        handle_tcp_message
-                    ),
-                    (
-                        udp_client_net_send,
-                        tcp_client_net_send
-                    )
+                    handle_udp_message,
+                    handle_tcp_message,
+                    add_snapshot_ack,
+                    // TCP first so control messages routed over UDP leave in the same tick
+                    tcp_client_net_send,
+                    udp_client_net_send,
                 ).chain()
             );
     }
@@ -78,34 +120,19 @@ impl Plugin for NetworkPlugin {
 
 fn setup_communications(
     mut commands: Commands,
+    mut lifecycle: ResMut<ConnectionLifecycle>,
     remote_addr_resource: Res<RemoteAddress>,
-    runtime: Res<TokioTasksRuntime>
+    runtime: Res<TokioTasksRuntime>,
+    conditioner: Res<LinkConditioner>,
+    loopback: Option<Res<LoopbackListener>>,
+    time: Res<Time<Real>>,
 ) {
     println!("Setting up communications...");
-    let (udp_send_tx, udp_send_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
-    let (udp_receive_tx, udp_receive_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
-    let (tcp_send_tx, tcp_send_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
-    let (tcp_receive_tx, tcp_receive_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
-    
-    let remote_string = remote_addr_resource.0.clone();
-    
-    runtime.spawn_background_task(|_| async move {
-        println!("starting communication");
-        println!("remote address: {}", remote_string);
-        let remote_addr = SocketAddr::from_str(format!("{}:4444", remote_string).as_str())
-            .ok()
-            .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 4444)));
-        
-        start_tcp_task(remote_addr, tcp_send_rx, tcp_receive_tx).await.unwrap();
-        start_udp_task(remote_addr, udp_send_rx, udp_receive_tx, 1).await.unwrap();
-    });
-    
-    commands.insert_resource(
-        Communication::new(
-            udp_send_tx,
-            udp_receive_rx,
-            tcp_send_tx,
-            tcp_receive_rx,
-        )
-    )
-}
\ No newline at end of file
+    lifecycle.attempts = 1;
+    lifecycle.state = ConnectionState::Connecting;
+    lifecycle.state_entered = time.elapsed();
+
+    let (conditioner_tx, conditioner_rx) = watch::channel(conditioner.clone());
+    commands.insert_resource(LinkConditionerSync(conditioner_tx));
+    commands.insert_resource(open_communications(remote_addr_resource.0.clone(), &runtime, conditioner_rx, loopback.as_deref()));
+}
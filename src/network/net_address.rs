@@ -0,0 +1,81 @@
+use std::io::{Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use tokio::net::{lookup_host, TcpSocket, TcpStream};
+
+/// Port used when the server address doesn't name one.
+pub const DEFAULT_PORT: u16 = 4444;
+
+/// Splits `host`, `host:port`, `[v6]`, `[v6]:port` or a bare IPv6 address into host and port.
+pub fn split_host_port(address: &str) -> Result<(String, u16), Error> {
+    let address = address.trim();
+    if address.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "server address is empty"));
+    }
+
+    if let Ok(addr) = SocketAddr::from_str(address) {
+        return Ok((addr.ip().to_string(), addr.port()));
+    }
+    if let Ok(ip) = IpAddr::from_str(address) {
+        return Ok((ip.to_string(), DEFAULT_PORT));
+    }
+    if let Some(ip) = address.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+        let ip = IpAddr::from_str(ip).map_err(|e| Error::new(ErrorKind::InvalidInput, format!("'{}': {}", address, e)))?;
+        return Ok((ip.to_string(), DEFAULT_PORT));
+    }
+
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse::<u16>()
+                .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("'{}' is not a valid port", port)))?;
+            (host, port)
+        }
+        None => (address, DEFAULT_PORT),
+    };
+
+    if host.is_empty() || host.contains(['[', ']', ':']) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("'{}' is not a valid host", host)));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Resolves the server address through DNS, every returned address is a candidate to connect to.
+pub async fn resolve_server_address(address: &str) -> Result<Vec<SocketAddr>, Error> {
+    let (host, port) = split_host_port(address)?;
+    let addrs: Vec<SocketAddr> = lookup_host((host.as_str(), port))
+        .await
+        .map_err(|e| Error::new(e.kind(), format!("couldn't resolve '{}': {}", host, e)))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(Error::new(ErrorKind::NotFound, format!("'{}' has no addresses", host)));
+    }
+    Ok(addrs)
+}
+
+/// Tries each address in turn and returns the first stream that connects, along with its address.
+pub async fn connect_any(addrs: &[SocketAddr]) -> Result<(TcpStream, SocketAddr), Error> {
+    let mut last_error = Error::new(ErrorKind::NotFound, "no addresses to connect to");
+
+    for addr in addrs {
+        let socket = if addr.is_ipv6() { TcpSocket::new_v6()? } else { TcpSocket::new_v4()? };
+        match socket.connect(*addr).await {
+            Ok(stream) => return Ok((stream, *addr)),
+            Err(e) => {
+                println!("Couldn't connect to {}: {}", addr, e);
+                last_error = Error::new(e.kind(), format!("couldn't connect to {}: {}", addr, e));
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Wildcard address of the same family as `remote`, for binding the local UDP socket.
+pub fn unspecified_for(remote: &SocketAddr) -> SocketAddr {
+    match remote {
+        SocketAddr::V4(_) => SocketAddr::from(([0, 0, 0, 0], 0)),
+        SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+    }
+}
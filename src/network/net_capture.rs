@@ -0,0 +1,158 @@
+use crate::network::net_message::PROTOCOL_VERSION;
+use bevy::prelude::{ResMut, Resource};
+use bincode::config;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+use std::time::Instant;
+
+/// First bytes of every capture file.
+pub const CAPTURE_MAGIC: [u8; 4] = *b"MPCP";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CaptureChannel {
+    Udp,
+    Tcp,
+    /// Not a socket: the local `Player` predicted for the UDP packet recorded right after it.
+    Prediction,
+}
+
+/// Written once after the magic, captures from another protocol version won't decode.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CaptureHeader {
+    pub protocol_version: u32,
+}
+
+/// One payload as the handlers saw it: reassembled and decrypted on the way in, not yet
+/// encrypted on the way out.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CaptureRecord {
+    /// Milliseconds since the capture started.
+    pub time_ms: u64,
+    /// `ClockSync::local_tick` the payload was handled on.
+    pub tick: u32,
+    pub direction: CaptureDirection,
+    pub channel: CaptureChannel,
+    pub payload: Vec<u8>,
+}
+
+/// Opt-in recorder of every payload sent and received. Inserting it is enough to start capturing.
+#[derive(Resource)]
+pub struct PacketCapture {
+    writer: Option<BufWriter<File>>,
+    started: Instant,
+    pub records: u64,
+}
+
+impl PacketCapture {
+    pub fn create(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(&CAPTURE_MAGIC)?;
+        let header = encode(&CaptureHeader { protocol_version: PROTOCOL_VERSION })?;
+        write_record(&mut writer, &header)?;
+
+        Ok(Self {
+            writer: Some(writer),
+            started: Instant::now(),
+            records: 0,
+        })
+    }
+
+    pub fn record(&mut self, tick: u32, direction: CaptureDirection, channel: CaptureChannel, payload: &[u8]) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+
+        let record = CaptureRecord {
+            time_ms: self.started.elapsed().as_millis() as u64,
+            tick,
+            direction,
+            channel,
+            payload: payload.to_vec(),
+        };
+        let written = encode(&record).and_then(|r| write_record(writer, &r));
+        match written {
+            Ok(()) => self.records += 1,
+            Err(e) => {
+                // A capture with a hole in it would replay something the client never saw
+                println!("Stopped packet capture after {} records: {:?}", self.records, e);
+                self.writer = None;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(writer) = &mut self.writer
+            && let Err(e) = writer.flush()
+        {
+            println!("Stopped packet capture after {} records: {:?}", self.records, e);
+            self.writer = None;
+        }
+    }
+}
+
+/// Flushes every frame, so a crash loses at most the last frame's payloads.
+pub fn flush_packet_capture(capture: Option<ResMut<PacketCapture>>) {
+    if let Some(mut capture) = capture {
+        capture.flush();
+    }
+}
+
+/// Length-prefixed like a network frame, without its size cap: a reassembled UDP payload can
+/// be much larger than a TCP frame.
+fn write_record(writer: &mut impl Write, bytes: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(bytes.len()).map_err(|_| Error::new(ErrorKind::InvalidInput, "record too large"))?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Splits the next record off `bytes`, `None` at the end or at a record cut short.
+fn next_record<'a>(bytes: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, rest) = bytes.split_first_chunk::<4>()?;
+    let record = rest.get(..u32::from_be_bytes(*len) as usize)?;
+    *bytes = &rest[record.len()..];
+    Some(record)
+}
+
+fn encode<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    bincode::serde::encode_to_vec(value, config::standard()).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+    bincode::serde::decode_from_slice(bytes, config::standard())
+        .map(|(value, _)| value)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+/// Decodes a whole capture file, refusing captures from another protocol version.
+pub fn read_capture(bytes: &[u8]) -> Result<Vec<CaptureRecord>, Error> {
+    let Some(mut framed) = bytes.strip_prefix(&CAPTURE_MAGIC) else {
+        return Err(Error::new(ErrorKind::InvalidData, "not a packet capture"));
+    };
+
+    let Some(header) = next_record(&mut framed) else {
+        return Err(Error::new(ErrorKind::UnexpectedEof, "capture has no header"));
+    };
+    let header: CaptureHeader = decode(header)?;
+    if header.protocol_version != PROTOCOL_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("capture is protocol v{}, client v{}", header.protocol_version, PROTOCOL_VERSION),
+        ));
+    }
+
+    let mut records = Vec::new();
+    // A trailing partial record is what a crash mid-write leaves behind, everything before it is good
+    while let Some(record) = next_record(&mut framed) {
+        records.push(decode(record)?);
+    }
+    Ok(records)
+}
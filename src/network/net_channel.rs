@@ -0,0 +1,276 @@
+use crate::network::net_message::SequenceNumber;
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+/// Number of earlier packets acknowledged through [`PacketHeader::ack_bits`].
+pub const ACK_WINDOW: u16 = 32;
+/// Upper bound on reliable messages piggybacked onto a single packet.
+pub const MAX_RELIABLE_PER_PACKET: usize = 8;
+/// Round trip assumed until the first ack comes back.
+pub const INITIAL_RTT: Duration = Duration::from_millis(200);
+/// Shortest wait before an unacked reliable message goes out again.
+pub const MIN_RESEND_TIMEOUT: Duration = Duration::from_millis(30);
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// Fire and forget, may arrive late, twice or not at all.
+    Unreliable,
+    /// Dropped if a newer packet has already been received.
+    UnreliableSequenced,
+    /// Resent until acknowledged and handed out in send order.
+    ReliableOrdered,
+}
+
+/// Chooses which socket carries `CTcpType` messages. The TCP stream stays available as a fallback.
+#[derive(Resource, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ControlRoute {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct PacketHeader {
+    /// Packet sequence, independent from the simulation sequence in `Sequence` messages.
+    pub sequence: SequenceNumber,
+    /// Latest packet sequence received from the other side, if any.
+    pub ack: Option<SequenceNumber>,
+    /// Bit `n` set means packet `ack - 1 - n` was also received.
+    pub ack_bits: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct UdpPacket<T> {
+    pub header: PacketHeader,
+    pub unreliable: Vec<T>,
+    pub sequenced: Vec<T>,
+    pub reliable: Vec<(SequenceNumber, T)>,
+}
+
+/// Returns true if `a` is more recent than `b`, accounting for wrap-around.
+pub fn sequence_greater_than(a: SequenceNumber, b: SequenceNumber) -> bool {
+    a != b && a.wrapping_sub(b) < SequenceNumber::MAX / 2
+}
+
+/// A reliable message waiting for its ack.
+#[derive(Debug)]
+struct PendingReliable<O> {
+    id: SequenceNumber,
+    message: O,
+    /// When it last went out, `None` until it was sent.
+    last_sent: Option<Duration>,
+}
+
+/// A packet sent but not acked yet.
+#[derive(Debug)]
+struct SentPacket {
+    sent_at: Duration,
+    reliable: Vec<SequenceNumber>,
+}
+
+/// Per-connection ack bookkeeping for one direction of outgoing `O` messages and incoming `I` messages.
+#[derive(Debug)]
+pub struct UdpChannel<O, I> {
+    local_sequence: SequenceNumber,
+    remote_sequence: Option<SequenceNumber>,
+    received_bits: u32,
+    next_reliable_id: SequenceNumber,
+    pending_reliable: VecDeque<PendingReliable<O>>,
+    in_flight: HashMap<SequenceNumber, SentPacket>,
+    expected_reliable: SequenceNumber,
+    reorder_buffer: HashMap<SequenceNumber, I>,
+    latest_sequenced: Option<SequenceNumber>,
+    /// Smoothed round trip, measured from the acks of our own packets.
+    pub rtt: Duration,
+    /// Packets received so far, duplicates excluded.
+    pub packets_received: u64,
+    /// Packets skipped over by newer arrivals and not (yet) filled in by late ones.
+    pub packets_missing: u64,
+}
+
+impl<O: Clone, I> Default for UdpChannel<O, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<O: Clone, I> UdpChannel<O, I> {
+    pub fn new() -> Self {
+        Self {
+            local_sequence: 0,
+            remote_sequence: None,
+            received_bits: 0,
+            next_reliable_id: 0,
+            pending_reliable: VecDeque::new(),
+            in_flight: HashMap::new(),
+            expected_reliable: 0,
+            reorder_buffer: HashMap::new(),
+            latest_sequenced: None,
+            rtt: INITIAL_RTT,
+            packets_received: 0,
+            packets_missing: 0,
+        }
+    }
+
+    pub fn queue_reliable(&mut self, message: O) {
+        self.pending_reliable.push_back(PendingReliable { id: self.next_reliable_id, message, last_sent: None });
+        self.next_reliable_id = self.next_reliable_id.wrapping_add(1);
+    }
+
+    /// How long an unacked reliable message waits before it is sent again.
+    pub fn resend_timeout(&self) -> Duration {
+        (self.rtt * 3 / 2).max(MIN_RESEND_TIMEOUT)
+    }
+
+    /// Whether a reliable message is new or its last copy is overdue for an ack at `now`.
+    pub fn has_reliable_due(&self, now: Duration) -> bool {
+        self.pending_reliable.iter().any(|p| self.is_due(p, now))
+    }
+
+    fn is_due(&self, pending: &PendingReliable<O>, now: Duration) -> bool {
+        pending.last_sent.is_none_or(|sent| now.saturating_sub(sent) >= self.resend_timeout())
+    }
+
+    /// Wraps the given messages in a packet, stamping acks and piggybacking the reliable messages
+    /// that are new or whose ack is overdue at `now`.
+    pub fn build_packet(&mut self, unreliable: Vec<O>, sequenced: Vec<O>, now: Duration) -> UdpPacket<O> {
+        let sequence = self.local_sequence;
+        self.local_sequence = self.local_sequence.wrapping_add(1);
+
+        let due: Vec<usize> = (0..self.pending_reliable.len())
+            .filter(|i| self.is_due(&self.pending_reliable[*i], now))
+            .take(MAX_RELIABLE_PER_PACKET)
+            .collect();
+        let mut reliable = Vec::with_capacity(due.len());
+        for i in due {
+            let pending = &mut self.pending_reliable[i];
+            pending.last_sent = Some(now);
+            reliable.push((pending.id, pending.message.clone()));
+        }
+
+        self.in_flight.insert(sequence, SentPacket { sent_at: now, reliable: reliable.iter().map(|(id, _)| *id).collect() });
+        // Packets that fell out of the ack window can never be acknowledged anymore
+        self.in_flight.retain(|s, _| sequence.wrapping_sub(*s) <= ACK_WINDOW + 1);
+
+        UdpPacket {
+            header: PacketHeader {
+                sequence,
+                ack: self.remote_sequence,
+                ack_bits: self.received_bits,
+            },
+            unreliable,
+            sequenced,
+            reliable,
+        }
+    }
+
+    /// Takes back the packet built last when it never went out, so the next packet reuses its
+    /// sequence instead of leaving a gap the other side would count as lost.
+    pub fn retract_packet(&mut self, sequence: SequenceNumber) {
+        if self.local_sequence.wrapping_sub(1) == sequence {
+            self.local_sequence = sequence;
+            // What it carried never went out, so it is due again right away
+            if let Some(packet) = self.in_flight.remove(&sequence) {
+                for pending in self.pending_reliable.iter_mut().filter(|p| packet.reliable.contains(&p.id)) {
+                    pending.last_sent = None;
+                }
+            }
+        }
+    }
+
+    /// Processes acks and returns the messages that should be handed to the game, or `None` for duplicates.
+    pub fn receive_packet(&mut self, packet: UdpPacket<I>, now: Duration) -> Option<Vec<I>> {
+        let header = packet.header;
+        if !self.record_received(header.sequence) {
+            return None;
+        }
+        self.process_acks(header.ack, header.ack_bits, now);
+
+        let mut delivered = packet.unreliable;
+
+        let sequenced_is_fresh = self
+            .latest_sequenced
+            .is_none_or(|latest| sequence_greater_than(header.sequence, latest));
+        if sequenced_is_fresh {
+            self.latest_sequenced = Some(header.sequence);
+            delivered.extend(packet.sequenced);
+        }
+
+        for (id, message) in packet.reliable {
+            if id == self.expected_reliable || sequence_greater_than(id, self.expected_reliable) {
+                self.reorder_buffer.entry(id).or_insert(message);
+            }
+        }
+        while let Some(message) = self.reorder_buffer.remove(&self.expected_reliable) {
+            delivered.push(message);
+            self.expected_reliable = self.expected_reliable.wrapping_add(1);
+        }
+
+        Some(delivered)
+    }
+
+    /// Updates the ack state with a newly received packet sequence. Returns false if it was already received.
+    fn record_received(&mut self, sequence: SequenceNumber) -> bool {
+        let Some(remote) = self.remote_sequence else {
+            self.remote_sequence = Some(sequence);
+            self.packets_received += 1;
+            return true;
+        };
+
+        if sequence == remote {
+            return false;
+        }
+
+        if sequence_greater_than(sequence, remote) {
+            let shift = sequence.wrapping_sub(remote) as u32;
+            self.received_bits = if shift > ACK_WINDOW as u32 {
+                0
+            } else {
+                // The previous head becomes bit `shift - 1`
+                ((self.received_bits << 1) | 1) << (shift - 1)
+            };
+            self.remote_sequence = Some(sequence);
+            self.packets_received += 1;
+            self.packets_missing += shift as u64 - 1;
+            true
+        } else {
+            let distance = remote.wrapping_sub(sequence) as u32;
+            if distance > ACK_WINDOW as u32 {
+                return false;
+            }
+            let bit = 1 << (distance - 1);
+            if self.received_bits & bit != 0 {
+                return false;
+            }
+            self.received_bits |= bit;
+            self.packets_received += 1;
+            self.packets_missing = self.packets_missing.saturating_sub(1);
+            true
+        }
+    }
+
+    fn process_acks(&mut self, ack: Option<SequenceNumber>, ack_bits: u32, now: Duration) {
+        let Some(ack) = ack else {
+            return;
+        };
+        let mut acked_ids = Vec::new();
+
+        if let Some(packet) = self.in_flight.remove(&ack) {
+            // Only the newest ack is fresh, older ones may have waited for a packet to carry them
+            let sample = now.saturating_sub(packet.sent_at);
+            self.rtt = (self.rtt * 7 + sample) / 8;
+            acked_ids.extend(packet.reliable);
+        }
+        for n in (0..ACK_WINDOW).filter(|n| ack_bits & (1 << n) != 0) {
+            if let Some(packet) = self.in_flight.remove(&ack.wrapping_sub(n + 1)) {
+                acked_ids.extend(packet.reliable);
+            }
+        }
+
+        if !acked_ids.is_empty() {
+            self.pending_reliable.retain(|p| !acked_ids.contains(&p.id));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use bevy::prelude::{ResMut, Resource};
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// Number of recent ping exchanges the offset filter picks from.
+pub const CLOCK_SAMPLES: usize = 8;
+/// Weight of a new measurement in the smoothed RTT (RFC 6298 alpha).
+const RTT_ALPHA: f64 = 1.0 / 8.0;
+/// Weight of a new measurement in the RTT variance (RFC 6298 beta).
+const RTT_BETA: f64 = 1.0 / 4.0;
+/// Weight of a new offset estimate, kept low so the mapped clock doesn't jump.
+const OFFSET_SMOOTHING: f64 = 0.1;
+
+/// Milliseconds since the unix epoch truncated to `u32`, the timestamp format used by `Ping`/`Pong`.
+pub fn now_ms() -> u32 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u32)
+        .unwrap_or_default()
+}
+
+/// Signed distance from `from` to `to` for wrapping `u32` millisecond timestamps.
+pub fn ms_between(from: u32, to: u32) -> f64 {
+    to.wrapping_sub(from) as i32 as f64
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ClockSample {
+    rtt_ms: f64,
+    offset_ms: f64,
+    /// Local time at which the server was on `server_tick`.
+    local_ms: u32,
+    /// Local tick we were on at `local_ms`.
+    local_tick: f64,
+    server_tick: u32,
+}
+
+/// NTP-style estimate of the server clock relative to ours.
+#[derive(Resource, Debug)]
+pub struct ClockSync {
+    /// Server time is approximately local time plus this offset.
+    pub offset_ms: f64,
+    pub smoothed_rtt_ms: f64,
+    pub rtt_variance_ms: f64,
+    /// Fixed ticks the server runs per second.
+    pub tick_rate: f64,
+    /// Fixed ticks run locally since startup.
+    pub local_tick: u32,
+    /// Server tick corresponding to the current local tick is `local_tick + tick_offset`, as of the
+    /// best exchange so far.
+    pub tick_offset: i64,
+    samples: VecDeque<ClockSample>,
+    anchor: Option<ClockSample>,
+}
+
+impl ClockSync {
+    pub fn new(tick_rate: f64) -> Self {
+        Self {
+            offset_ms: 0.0,
+            smoothed_rtt_ms: 0.0,
+            rtt_variance_ms: 0.0,
+            tick_rate,
+            local_tick: 0,
+            tick_offset: 0,
+            samples: VecDeque::with_capacity(CLOCK_SAMPLES),
+            anchor: None,
+        }
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.anchor.is_some()
+    }
+
+    /// Feeds one ping exchange: sent locally at `initiation_time`, stamped by the server at
+    /// `server_received_time` while on `server_tick`, and answered locally at `received_time`
+    /// while on the current `local_tick`.
+    pub fn record(&mut self, initiation_time: u32, server_received_time: u32, server_tick: u32, received_time: u32) {
+        let rtt_ms = ms_between(initiation_time, received_time).max(0.0);
+        // Assumes the path is symmetric and the server answers immediately
+        let midpoint = initiation_time.wrapping_add((rtt_ms / 2.0) as u32);
+        let offset_ms = ms_between(midpoint, server_received_time);
+
+        if self.anchor.is_none() {
+            self.smoothed_rtt_ms = rtt_ms;
+            self.rtt_variance_ms = rtt_ms / 2.0;
+            self.offset_ms = offset_ms;
+        } else {
+            self.rtt_variance_ms += RTT_BETA * ((self.smoothed_rtt_ms - rtt_ms).abs() - self.rtt_variance_ms);
+            self.smoothed_rtt_ms += RTT_ALPHA * (rtt_ms - self.smoothed_rtt_ms);
+        }
+
+        if self.samples.len() >= CLOCK_SAMPLES {
+            self.samples.pop_front();
+        }
+        let local_tick = self.local_tick as f64 - rtt_ms / 2.0 * self.tick_rate / 1000.0;
+        self.samples.push_back(ClockSample { rtt_ms, offset_ms, local_ms: midpoint, local_tick, server_tick });
+
+        // The exchange with the lowest RTT had the least room for queuing asymmetry
+        let best = self
+            .samples
+            .iter()
+            .min_by(|a, b| a.rtt_ms.total_cmp(&b.rtt_ms))
+            .copied();
+
+        if let Some(best) = best {
+            if self.anchor.is_some() {
+                self.offset_ms += OFFSET_SMOOTHING * (best.offset_ms - self.offset_ms);
+            }
+            self.anchor = Some(best);
+            self.tick_offset = (best.server_tick as f64 - best.local_tick).round() as i64;
+        }
+    }
+
+    /// Fractional server tick at the given local time, once at least one pong arrived.
+    pub fn server_tick_at(&self, local_ms: u32) -> Option<f64> {
+        self.anchor.map(|anchor| {
+            anchor.server_tick as f64 + ms_between(anchor.local_ms, local_ms) * self.tick_rate / 1000.0
+        })
+    }
+}
+
+/// Counts local ticks. The tick offset comes from the anchor exchange in ticks, not from the wall
+/// clock, so the several ticks a catch-up frame runs don't all map to the same server tick.
+pub fn advance_clock_tick(mut clock: ResMut<ClockSync>) {
+    clock.local_tick = clock.local_tick.wrapping_add(1);
+}
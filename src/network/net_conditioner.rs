@@ -0,0 +1,142 @@
+use bevy::prelude::{DetectChanges, Reflect, ReflectResource, Res, Resource};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio::time::{sleep_until, Instant};
+
+/// Artificial network conditions applied between the [`Communication`](crate::network::net_manage::Communication)
+/// channels and the socket tasks. Editable at runtime through the resource inspector.
+#[derive(Reflect, Resource, Clone, Debug, Default, PartialEq)]
+#[reflect(Resource)]
+pub struct LinkConditioner {
+    pub enabled: bool,
+    pub udp: LinkProfile,
+    /// Loss, duplication and reordering on TCP drop or repeat whole messages, something a real
+    /// stream never does. Useful to shake out handler bugs, misleading for anything else.
+    pub tcp: LinkProfile,
+}
+
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq)]
+pub struct LinkProfile {
+    /// One-way delay added in each direction.
+    pub latency_ms: f32,
+    /// Extra random delay of up to this many milliseconds.
+    pub jitter_ms: f32,
+    /// Chance in `0..=1` that a message is dropped.
+    pub loss: f32,
+    /// Chance in `0..=1` that a message is delivered twice.
+    pub duplicate: f32,
+    /// Chance in `0..=1` that a message is held back long enough to arrive after its successors.
+    pub reorder: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum LinkChannel {
+    Udp,
+    Tcp,
+}
+
+/// Keeps the socket tasks' copy of the [`LinkConditioner`] up to date.
+#[derive(Resource)]
+pub struct LinkConditionerSync(pub watch::Sender<LinkConditioner>);
+
+impl LinkConditioner {
+    fn profile(&self, channel: LinkChannel) -> Option<LinkProfile> {
+        if !self.enabled {
+            return None;
+        }
+
+        Some(match channel {
+            LinkChannel::Udp => self.udp,
+            LinkChannel::Tcp => self.tcp,
+        })
+    }
+}
+
+pub fn sync_link_conditioner(conditioner: Res<LinkConditioner>, sync: Res<LinkConditionerSync>) {
+    if conditioner.is_changed() {
+        sync.0.send_replace(conditioner.clone());
+    }
+}
+
+/// Forwards everything from `inbound` to `outbound` under the current conditions of `channel`.
+/// Messages matching `exempt` (link setup signals) are delayed but never dropped or duplicated.
+/// Stops once either side goes away, so closing one end reaches the other through it.
+pub async fn run_conditioner<T: Clone>(
+    mut inbound: Receiver<T>,
+    outbound: Sender<T>,
+    settings: watch::Receiver<LinkConditioner>,
+    channel: LinkChannel,
+    exempt: fn(&T) -> bool,
+) {
+    let mut rng = StdRng::from_entropy();
+    let mut queue: BTreeMap<(Instant, u64), T> = BTreeMap::new();
+    let mut counter = 0u64;
+    let mut last_delivery = Instant::now();
+
+    loop {
+        let next_due = queue.first_key_value().map(|((at, _), _)| *at);
+
+        tokio::select! {
+            // Nobody reads what we forward anymore, dropping `inbound` tells the producer the same
+            _ = outbound.closed() => return,
+            received = inbound.recv() => {
+                let Some(item) = received else {
+                    break;
+                };
+
+                let now = Instant::now();
+                let Some(profile) = settings.borrow().profile(channel) else {
+                    // Keep ordering with anything still held back from before the conditioner was disabled
+                    queue.insert((now.max(last_delivery), counter), item);
+                    counter += 1;
+                    continue;
+                };
+
+                let exempt = exempt(&item);
+                if !exempt && rng.gen_bool(profile.loss.clamp(0.0, 1.0) as f64) {
+                    continue;
+                }
+
+                let copies = if !exempt && rng.gen_bool(profile.duplicate.clamp(0.0, 1.0) as f64) { 2 } else { 1 };
+                for _ in 0..copies {
+                    let jitter = rng.gen_range(0.0..=profile.jitter_ms.max(0.0));
+                    let mut deliver_at = now + Duration::from_secs_f32((profile.latency_ms.max(0.0) + jitter) / 1000.0);
+
+                    if !exempt && rng.gen_bool(profile.reorder.clamp(0.0, 1.0) as f64) {
+                        // Held back past whatever is sent next
+                        deliver_at += Duration::from_secs_f32((profile.latency_ms.max(0.0) + profile.jitter_ms.max(0.0) + 10.0) / 1000.0);
+                    } else {
+                        deliver_at = deliver_at.max(last_delivery);
+                        last_delivery = deliver_at;
+                    }
+
+                    queue.insert((deliver_at, counter), item.clone());
+                    counter += 1;
+                }
+            }
+            _ = sleep_until(next_due.unwrap_or_else(Instant::now)), if next_due.is_some() => {
+                let now = Instant::now();
+                while let Some(entry) = queue.first_entry() {
+                    if entry.key().0 > now {
+                        break;
+                    }
+                    if outbound.send(entry.remove()).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    // Let whatever is still in flight arrive before the channel closes
+    for ((deliver_at, _), item) in queue {
+        sleep_until(deliver_at).await;
+        if outbound.send(item).await.is_err() {
+            return;
+        }
+    }
+}
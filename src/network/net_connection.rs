@@ -0,0 +1,244 @@
+use crate::network::net_clock::ClockSync;
+use crate::network::net_conditioner::LinkConditionerSync;
+use crate::network::net_crypto::TransportSecurity;
+use crate::network::net_dilation::InputLead;
+use crate::network::net_manage::{open_communications, Communication, LinkEvent, TcpConnection, UdpConnection};
+use crate::network::net_snapshot::SnapshotBuffer;
+use crate::network::net_transport::{LoopbackListener, LoopbackTransport};
+use crate::network::net_message::{CTcpType, NetworkMessage, BUILD_ID, PROTOCOL_VERSION};
+use crate::network::{ClientName, RemoteAddress, SERVER_TICK_RATE};
+use bevy::prelude::{AppExit, Commands, DetectChanges, Event, EventReader, EventWriter, Real, Reflect, Res, ResMut, Resource, Time};
+use bincode::config;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// How long a connect attempt may stay in [`ConnectionState::Connecting`] or
+/// [`ConnectionState::Handshaking`] before it is abandoned.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the server may stay silent before an established connection is considered lost.
+pub const SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Reflect, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConnectionState {
+    #[default]
+    Disconnected,
+    Connecting,
+    /// The sockets are up and `Hello` was sent, waiting for `Welcome` or `Rejected`.
+    Handshaking,
+    Connected,
+    Joined,
+    Lost,
+}
+
+#[derive(Event, Clone, Copy, Debug)]
+pub struct ConnectionStateChanged {
+    pub previous: ConnectionState,
+    pub current: ConnectionState,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct ConnectionLifecycle {
+    pub state: ConnectionState,
+    /// Connect attempts made since the last successful connection.
+    pub attempts: u32,
+    pub next_attempt: Duration,
+    pub state_entered: Duration,
+    pub last_heard: Duration,
+    /// Set when the server refused our handshake. Retrying won't help, so reconnects stop until
+    /// the user asks for one or changes the address.
+    pub rejection: Option<String>,
+    /// Why the latest attempt failed, cleared once connected.
+    pub last_error: Option<String>,
+}
+
+impl ConnectionLifecycle {
+    pub fn transition(
+        &mut self,
+        state: ConnectionState,
+        now: Duration,
+        events: &mut EventWriter<ConnectionStateChanged>,
+    ) {
+        if self.state == state {
+            return;
+        }
+
+        println!("Connection state: {:?} -> {:?}", self.state, state);
+        if state == ConnectionState::Connected {
+            self.last_error = None;
+        }
+        events.write(ConnectionStateChanged { previous: self.state, current: state });
+        self.state = state;
+        self.state_entered = now;
+    }
+
+    pub fn reject(
+        &mut self,
+        reason: String,
+        now: Duration,
+        events: &mut EventWriter<ConnectionStateChanged>,
+    ) {
+        println!("Server rejected connection: {}", reason);
+        self.rejection = Some(reason);
+        self.transition(ConnectionState::Disconnected, now, events);
+    }
+
+    pub fn is_connected(&self) -> bool {
+        matches!(self.state, ConnectionState::Connected | ConnectionState::Joined)
+    }
+
+    pub fn schedule_retry(&mut self, now: Duration) {
+        self.next_attempt = now + backoff_delay(self.attempts);
+    }
+
+    /// Connects again right away, forgetting any rejection and backoff.
+    pub fn retry_now(&mut self, now: Duration) {
+        self.rejection = None;
+        self.attempts = 0;
+        self.next_attempt = now;
+    }
+}
+
+/// Exponential backoff: [`INITIAL_BACKOFF`] after the first failed attempt, doubling
+/// with every further failure up to [`MAX_BACKOFF`].
+pub fn backoff_delay(attempts: u32) -> Duration {
+    let factor = 1u32 << attempts.saturating_sub(1).min(16);
+    INITIAL_BACKOFF.saturating_mul(factor).min(MAX_BACKOFF)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn connection_system(
+    mut commands: Commands,
+    mut comm: ResMut<Communication>,
+    mut lifecycle: ResMut<ConnectionLifecycle>,
+    mut state_events: EventWriter<ConnectionStateChanged>,
+    mut tcp_connection: ResMut<TcpConnection>,
+    mut udp_connection: ResMut<UdpConnection>,
+    mut security: ResMut<TransportSecurity>,
+    remote_addr: Res<RemoteAddress>,
+    client_name: Res<ClientName>,
+    conditioner: Res<LinkConditionerSync>,
+    loopback: Option<Res<LoopbackListener>>,
+    runtime: Res<TokioTasksRuntime>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+
+    // A new address is a new server, whatever the old one thought of us
+    if remote_addr.is_changed() && !remote_addr.is_added() {
+        lifecycle.retry_now(now);
+        if !matches!(lifecycle.state, ConnectionState::Disconnected | ConnectionState::Lost) {
+            lifecycle.transition(ConnectionState::Disconnected, now, &mut state_events);
+        }
+    }
+
+    while let Some(event) = comm.transport.poll_event() {
+        match event {
+            LinkEvent::ConnectFailed(reason) => {
+                println!("Connection attempt {} failed: {}", lifecycle.attempts, reason);
+                lifecycle.last_error = Some(reason);
+                lifecycle.transition(ConnectionState::Disconnected, now, &mut state_events);
+                lifecycle.schedule_retry(now);
+            }
+            LinkEvent::ReliableReady => tcp_connection.ready = true,
+            LinkEvent::UnreliableReady => udp_connection.ready = true,
+            LinkEvent::ReliableClosed => {
+                if lifecycle.state != ConnectionState::Disconnected && lifecycle.state != ConnectionState::Lost {
+                    lifecycle.transition(ConnectionState::Lost, now, &mut state_events);
+                    lifecycle.schedule_retry(now);
+                }
+            }
+        }
+    }
+
+    match lifecycle.state {
+        ConnectionState::Connecting => {
+            if tcp_connection.ready && udp_connection.ready {
+                tcp_connection.add_message(NetworkMessage(CTcpType::Hello {
+                    protocol_version: PROTOCOL_VERSION,
+                    build_id: BUILD_ID.to_string(),
+                    client_name: client_name.0.clone(),
+                }));
+                if let Some(public_key) = security.begin() {
+                    tcp_connection.add_message(NetworkMessage(CTcpType::KeyExchange { public_key }));
+                }
+                lifecycle.transition(ConnectionState::Handshaking, now, &mut state_events);
+            } else if now - lifecycle.state_entered > CONNECT_TIMEOUT {
+                println!("Connection attempt {} timed out", lifecycle.attempts);
+                lifecycle.last_error = Some("timed out".to_string());
+                lifecycle.transition(ConnectionState::Disconnected, now, &mut state_events);
+                lifecycle.schedule_retry(now);
+            }
+        }
+        ConnectionState::Handshaking => {
+            if now - lifecycle.state_entered > CONNECT_TIMEOUT {
+                println!("Server never answered our handshake");
+                lifecycle.transition(ConnectionState::Lost, now, &mut state_events);
+                lifecycle.schedule_retry(now);
+            }
+        }
+        ConnectionState::Connected | ConnectionState::Joined => {
+            if now.saturating_sub(lifecycle.last_heard) > SERVER_TIMEOUT {
+                println!("Server silent for {:?}, connection lost", SERVER_TIMEOUT);
+                lifecycle.transition(ConnectionState::Lost, now, &mut state_events);
+                lifecycle.schedule_retry(now);
+            }
+        }
+        ConnectionState::Disconnected | ConnectionState::Lost => {
+            if lifecycle.rejection.is_none() && now >= lifecycle.next_attempt {
+                lifecycle.attempts += 1;
+                lifecycle.transition(ConnectionState::Connecting, now, &mut state_events);
+
+                // Dropping the old channels shuts down the socket tasks of the previous attempt
+                commands.insert_resource(UdpConnection::new());
+                commands.insert_resource(TcpConnection::new());
+                // The next server may run on a different clock altogether
+                commands.insert_resource(ClockSync::new(SERVER_TICK_RATE));
+                commands.insert_resource(InputLead::default());
+                commands.insert_resource(SnapshotBuffer::default());
+                commands.insert_resource(open_communications(
+                    remote_addr.0.clone(),
+                    &runtime,
+                    conditioner.0.subscribe(),
+                    loopback.as_deref(),
+                ));
+            }
+        }
+    }
+}
+
+/// Drops the link to the server without opening a new one. Nothing is sent or received
+/// anymore until the next attempt connects again.
+pub fn close_link(commands: &mut Commands) {
+    commands.insert_resource(Communication::new(LoopbackTransport::closed()));
+    commands.insert_resource(UdpConnection::new());
+    commands.insert_resource(TcpConnection::new());
+}
+
+/// Says goodbye on the way out. There won't be another frame to send it in, so this waits until
+/// the message, together with anything still queued, is written.
+pub fn send_leave_on_exit(
+    mut exit_events: EventReader<AppExit>,
+    mut comm: ResMut<Communication>,
+    mut tcp_connection: ResMut<TcpConnection>,
+    mut security: ResMut<TransportSecurity>,
+    lifecycle: Res<ConnectionLifecycle>,
+) {
+    if exit_events.read().next().is_none() || !lifecycle.is_connected() || !tcp_connection.ready {
+        return;
+    }
+
+    tcp_connection.add_message(NetworkMessage(CTcpType::Leave));
+    let sent = bincode::serde::encode_to_vec(tcp_connection.get_current_messages(), config::standard())
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+        .and_then(|bytes| security.seal_tcp(bytes))
+        .and_then(|bytes| comm.transport.send_reliable_now(bytes));
+    tcp_connection.clear_messages();
+
+    match sent {
+        Ok(()) => println!("Left the server"),
+        Err(e) => println!("Couldn't send leave message: {:?}", e),
+    }
+}
@@ -0,0 +1,314 @@
+use bevy::prelude::Resource;
+use bincode::config;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Counters further behind the newest one than this are rejected as replays.
+pub const REPLAY_WINDOW: u64 = 64;
+
+/// Environment variable the pre-shared key is read from when no key file is given.
+pub const PSK_ENV_VAR: &str = "MPCLIENT_PSK";
+
+/// Reads the pre-shared key from `file`, or from [`PSK_ENV_VAR`] without one. It's never taken
+/// from the command line, where anyone listing processes can read it.
+pub fn load_psk(file: Option<&Path>) -> Result<Vec<u8>, Error> {
+    let key = match file {
+        Some(path) => std::fs::read(path)?,
+        None => std::env::var(PSK_ENV_VAR)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, format!("secure mode needs the pre-shared key in {} or --psk-file=<path>", PSK_ENV_VAR)))?
+            .into_bytes(),
+    };
+
+    // The newline a key file usually ends in isn't part of the key
+    let key = key.trim_ascii_end();
+    if key.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "the pre-shared key is empty"));
+    }
+    Ok(key.to_vec())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// An encrypted payload. `counter` is never reused under the same key and doubles as the nonce.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Sealed {
+    pub counter: u64,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Remembers which counters were already accepted, in the same way the UDP channel tracks acks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReplayWindow {
+    latest: Option<u64>,
+    /// Bit `n` set means counter `latest - 1 - n` was accepted.
+    bits: u64,
+}
+
+impl ReplayWindow {
+    /// Returns false for counters that were already seen or are too old to tell.
+    pub fn accept(&mut self, counter: u64) -> bool {
+        let Some(latest) = self.latest else {
+            self.latest = Some(counter);
+            return true;
+        };
+
+        if counter > latest {
+            let shift = counter - latest;
+            self.bits = if shift > REPLAY_WINDOW { 0 } else { ((self.bits << 1) | 1) << (shift - 1) };
+            self.latest = Some(counter);
+            return true;
+        }
+
+        let distance = latest - counter;
+        if distance == 0 || distance > REPLAY_WINDOW {
+            return false;
+        }
+        let bit = 1 << (distance - 1);
+        if self.bits & bit != 0 {
+            return false;
+        }
+        self.bits |= bit;
+        true
+    }
+}
+
+/// One direction of traffic on one socket.
+struct DirectionKey {
+    cipher: ChaCha20Poly1305,
+    label: &'static [u8],
+}
+
+impl DirectionKey {
+    fn derive(hkdf: &Hkdf<Sha256>, label: &'static [u8]) -> Self {
+        let mut key = [0u8; 32];
+        hkdf.expand(label, &mut key).expect("32 bytes is a valid HKDF output length");
+        Self {
+            cipher: ChaCha20Poly1305::new(Key::from_slice(&key)),
+            label,
+        }
+    }
+
+    fn nonce(counter: u64) -> Nonce {
+        let mut nonce = [0u8; 12];
+        nonce[4..].copy_from_slice(&counter.to_be_bytes());
+        *Nonce::from_slice(&nonce)
+    }
+}
+
+/// Keys for one socket: we seal with `send` and open with `receive`.
+pub struct ChannelKeys {
+    send: DirectionKey,
+    send_counter: u64,
+    receive: DirectionKey,
+    replay: ReplayWindow,
+}
+
+impl ChannelKeys {
+    fn new(hkdf: &Hkdf<Sha256>, role: Role, client_label: &'static [u8], server_label: &'static [u8]) -> Self {
+        let (send, receive) = match role {
+            Role::Client => (client_label, server_label),
+            Role::Server => (server_label, client_label),
+        };
+        Self {
+            send: DirectionKey::derive(hkdf, send),
+            send_counter: 0,
+            receive: DirectionKey::derive(hkdf, receive),
+            replay: ReplayWindow::default(),
+        }
+    }
+
+    pub fn seal(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let counter = self.send_counter;
+        self.send_counter += 1;
+
+        let payload = Payload { msg: plaintext, aad: self.send.label };
+        let ciphertext = self
+            .send
+            .cipher
+            .encrypt(&DirectionKey::nonce(counter), payload)
+            .map_err(|_| Error::new(ErrorKind::InvalidInput, "couldn't encrypt payload"))?;
+
+        bincode::serde::encode_to_vec(Sealed { counter, ciphertext }, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    pub fn open(&mut self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let (sealed, _): (Sealed, usize) = bincode::serde::decode_from_slice(bytes, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        let payload = Payload { msg: &sealed.ciphertext, aad: self.receive.label };
+        let plaintext = self
+            .receive
+            .cipher
+            .decrypt(&DirectionKey::nonce(sealed.counter), payload)
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "payload failed authentication"))?;
+
+        // Only authenticated counters may move the window
+        if !self.replay.accept(sealed.counter) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("replayed payload {}", sealed.counter)));
+        }
+        Ok(plaintext)
+    }
+}
+
+pub struct SessionKeys {
+    pub tcp: ChannelKeys,
+    pub udp: ChannelKeys,
+}
+
+/// Our half of an X25519 exchange, consumed once the peer's public key arrives.
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    pub public_key: [u8; 32],
+}
+
+impl KeyExchange {
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random_from_rng(OsRng);
+        let public_key = PublicKey::from(&secret).to_bytes();
+        Self { secret, public_key }
+    }
+
+    pub fn finish(self, peer_public_key: [u8; 32], role: Role) -> Result<SessionKeys, Error> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer_public_key));
+        if !shared.was_contributory() {
+            return Err(Error::new(ErrorKind::InvalidData, "peer sent a low order public key"));
+        }
+
+        let (client_key, server_key) = match role {
+            Role::Client => (self.public_key, peer_public_key),
+            Role::Server => (peer_public_key, self.public_key),
+        };
+        let salt = [client_key, server_key].concat();
+        let hkdf = Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes());
+
+        Ok(SessionKeys {
+            tcp: ChannelKeys::new(&hkdf, role, b"mpclient tcp client", b"mpclient tcp server"),
+            udp: ChannelKeys::new(&hkdf, role, b"mpclient udp client", b"mpclient udp server"),
+        })
+    }
+}
+
+impl Default for KeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn server_mac(psk: &[u8], client_public_key: &[u8; 32], server_public_key: &[u8; 32]) -> Hmac<Sha256> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(psk).expect("HMAC takes keys of any length");
+    mac.update(b"mpclient server key");
+    mac.update(client_public_key);
+    mac.update(server_public_key);
+    mac
+}
+
+/// Sent by the server with its public key to show it holds the pre-shared key. Covers both
+/// public keys, so it can't be reused for a key of someone else's choosing.
+pub fn server_proof(psk: &[u8], client_public_key: &[u8; 32], server_public_key: &[u8; 32]) -> [u8; 32] {
+    server_mac(psk, client_public_key, server_public_key).finalize().into_bytes().into()
+}
+
+/// Optional encryption of every UDP and TCP payload, keyed during the TCP handshake.
+#[derive(Resource, Default)]
+pub struct TransportSecurity {
+    /// Key the server proves its half of the exchange with, secure mode is on when set.
+    psk: Option<Vec<u8>>,
+    exchange: Option<KeyExchange>,
+    keys: Option<SessionKeys>,
+}
+
+impl TransportSecurity {
+    pub fn new(psk: Option<Vec<u8>>) -> Self {
+        Self { psk, ..Default::default() }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.psk.is_some()
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.keys.is_some()
+    }
+
+    /// Anything may be sent: secure mode is off or the keys are in. Until then only the
+    /// handshake travels, in the clear and over TCP.
+    pub fn is_ready(&self) -> bool {
+        !self.is_enabled() || self.is_established()
+    }
+
+    /// Starts a fresh exchange and returns the public key to send with `Hello`, if secure mode is on.
+    pub fn begin(&mut self) -> Option<[u8; 32]> {
+        self.keys = None;
+        if !self.is_enabled() {
+            return None;
+        }
+
+        let exchange = KeyExchange::new();
+        let public_key = exchange.public_key;
+        self.exchange = Some(exchange);
+        Some(public_key)
+    }
+
+    /// Takes the server's half of the exchange, if `proof` shows it holds the pre-shared key.
+    pub fn complete(&mut self, server_public_key: [u8; 32], proof: [u8; 32]) -> Result<(), Error> {
+        let (Some(psk), Some(exchange)) = (&self.psk, self.exchange.take()) else {
+            return Err(Error::new(ErrorKind::InvalidData, "server sent a key we never asked for"));
+        };
+        server_mac(psk, &exchange.public_key, &server_public_key)
+            .verify_slice(&proof)
+            .map_err(|_| Error::new(ErrorKind::PermissionDenied, "server couldn't prove it holds the pre-shared key"))?;
+        self.keys = Some(exchange.finish(server_public_key, Role::Client)?);
+        Ok(())
+    }
+
+    /// Without keys, payloads pass through untouched only where they can't be a downgrade: with
+    /// secure mode off, or for the TCP handshake while our exchange waits for the server's key.
+    fn plaintext(&self, bytes: Vec<u8>, handshake: bool) -> Result<Vec<u8>, Error> {
+        if !self.is_enabled() || (handshake && self.exchange.is_some()) {
+            Ok(bytes)
+        } else {
+            Err(Error::new(ErrorKind::PermissionDenied, "refusing plaintext, the session requires encryption"))
+        }
+    }
+
+    pub fn seal_tcp(&mut self, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match &mut self.keys {
+            Some(keys) => keys.tcp.seal(&bytes),
+            None => self.plaintext(bytes, true),
+        }
+    }
+
+    pub fn open_tcp(&mut self, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match &mut self.keys {
+            Some(keys) => keys.tcp.open(&bytes),
+            None => self.plaintext(bytes, true),
+        }
+    }
+
+    pub fn seal_udp(&mut self, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match &mut self.keys {
+            Some(keys) => keys.udp.seal(&bytes),
+            None => self.plaintext(bytes, false),
+        }
+    }
+
+    pub fn open_udp(&mut self, bytes: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match &mut self.keys {
+            Some(keys) => keys.udp.open(&bytes),
+            None => self.plaintext(bytes, false),
+        }
+    }
+}
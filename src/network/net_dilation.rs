@@ -0,0 +1,145 @@
+use crate::network::net_clock::ClockSync;
+use bevy::app::FixedMain;
+use bevy::prelude::{Fixed, Resource, Time, Virtual, World};
+use std::time::Duration;
+
+/// Ticks of lead kept on top of the jitter margin so an input is never due the tick it arrives.
+pub const BASE_LEAD: f32 = 1.0;
+/// Largest fraction by which the fixed step is sped up or slowed down.
+pub const MAX_DILATION: f64 = 0.05;
+/// Errors smaller than this many ticks are left alone to avoid hunting around the target.
+const DEADBAND: f32 = 0.25;
+/// Dilation per tick of error.
+const GAIN: f64 = 0.01;
+/// Weight of a new buffer depth report in the smoothed depth.
+const SMOOTHING: f32 = 0.1;
+
+/// Keeps our inputs arriving a small, steady number of ticks ahead of the server simulation by
+/// producing fixed ticks slightly faster or slower than the server. The step itself never changes,
+/// prediction has to integrate exactly like the server and the resimulation.
+#[derive(Resource, Debug)]
+pub struct InputLead {
+    /// Smoothed number of ticks our inputs wait in the server buffer.
+    pub buffered: f32,
+    /// Lead we are steering towards, in ticks.
+    pub target: f32,
+    /// Current multiplier on the fixed tick rate, above 1 runs ahead.
+    pub speed: f64,
+    /// Inputs the server reported as late or missing.
+    pub starved: u64,
+    /// Time the fixed loop is ahead of (positive) or behind (negative) where `speed` wants it, in
+    /// seconds. Settled a whole tick at a time.
+    drift: f64,
+    has_feedback: bool,
+}
+
+/// What the fixed loop does about the drift this frame.
+#[derive(Debug, PartialEq)]
+pub enum TickAdjustment {
+    None,
+    /// Hold this much accumulated time back so the next tick comes later.
+    Discard(Duration),
+    /// Run one more tick than the frame's time called for.
+    ExtraTick,
+}
+
+impl Default for InputLead {
+    fn default() -> Self {
+        Self {
+            buffered: 0.0,
+            target: BASE_LEAD,
+            speed: 1.0,
+            starved: 0,
+            drift: 0.0,
+            has_feedback: false,
+        }
+    }
+}
+
+impl InputLead {
+    /// Feeds one `InputFeedback` report, negative `buffered` meaning the input missed its tick.
+    pub fn record(&mut self, buffered: i16) {
+        let buffered = buffered as f32;
+        if buffered < 0.0 {
+            self.starved += 1;
+        }
+
+        if !self.has_feedback {
+            self.buffered = buffered;
+            self.has_feedback = true;
+        } else if buffered < self.buffered {
+            // React to starvation right away, a dropped input is worse than a bit of extra latency
+            self.buffered = buffered.max(self.buffered - 1.0);
+        } else {
+            self.buffered += SMOOTHING * (buffered - self.buffered);
+        }
+    }
+
+    /// Recomputes the target from the current jitter estimate and returns the new speed.
+    pub fn update(&mut self, tick_ms: f64, rtt_variance_ms: f64) -> f64 {
+        if !self.has_feedback {
+            self.speed = 1.0;
+            return self.speed;
+        }
+
+        self.target = BASE_LEAD + (rtt_variance_ms / tick_ms.max(f64::EPSILON)) as f32;
+
+        let error = self.target - self.buffered;
+        self.speed = if error.abs() < DEADBAND {
+            1.0
+        } else {
+            1.0 + (error as f64 * GAIN).clamp(-MAX_DILATION, MAX_DILATION)
+        };
+        self.speed
+    }
+
+    /// Adds a frame of `delta` seconds at the current speed to the drift and settles what it can:
+    /// time owed is taken out of the fixed loop's `overstep`, a whole `timestep` gained is one
+    /// extra tick.
+    pub fn adjust(&mut self, delta: f64, overstep: Duration, timestep: Duration) -> TickAdjustment {
+        self.drift += delta * (self.speed - 1.0);
+
+        if self.drift < 0.0 {
+            let discard = overstep.min(Duration::from_secs_f64(-self.drift));
+            if discard.is_zero() {
+                return TickAdjustment::None;
+            }
+            self.drift += discard.as_secs_f64();
+            return TickAdjustment::Discard(discard);
+        }
+
+        if self.drift >= timestep.as_secs_f64() {
+            self.drift -= timestep.as_secs_f64();
+            return TickAdjustment::ExtraTick;
+        }
+        TickAdjustment::None
+    }
+}
+
+/// Runs after the frame's fixed ticks, making up or holding back ticks as `speed` asks.
+pub fn apply_time_dilation(world: &mut World) {
+    let (tick_rate, rtt_variance_ms) = {
+        let clock = world.resource::<ClockSync>();
+        (clock.tick_rate, clock.rtt_variance_ms)
+    };
+    let delta = world.resource::<Time<Virtual>>().delta().as_secs_f64();
+    let fixed_time = world.resource::<Time<Fixed>>();
+    let (overstep, timestep) = (fixed_time.overstep(), fixed_time.timestep());
+
+    let adjustment = {
+        let mut lead = world.resource_mut::<InputLead>();
+        lead.update(1000.0 / tick_rate, rtt_variance_ms);
+        lead.adjust(delta, overstep, timestep)
+    };
+
+    match adjustment {
+        TickAdjustment::None => {}
+        TickAdjustment::Discard(time) => world.resource_mut::<Time<Fixed>>().discard_overstep(time),
+        TickAdjustment::ExtraTick => {
+            // Same as the fixed loop does around every tick it runs
+            *world.resource_mut::<Time>() = world.resource::<Time<Fixed>>().as_generic();
+            world.run_schedule(FixedMain);
+            *world.resource_mut::<Time>() = world.resource::<Time<Virtual>>().as_generic();
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use bevy::prelude::Resource;
+use bincode::config;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+/// Space reserved in every datagram for the encoded [`Datagram`] envelope.
+pub const FRAGMENT_HEADER_LEN: usize = 16;
+pub const MAX_FRAGMENTS: usize = u8::MAX as usize;
+/// Largest datagram the socket tasks will ever read.
+pub const MAX_DATAGRAM_SIZE: usize = 65_535;
+/// Partial messages kept at once, the oldest is dropped to make room for a new one.
+pub const MAX_PENDING_MESSAGES: usize = 32;
+
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct FragmentSettings {
+    /// Largest datagram we send. Payloads that don't fit are split into fragments.
+    pub mtu: usize,
+    /// Partially received messages are dropped once they are older than this.
+    pub reassembly_timeout: Duration,
+}
+
+impl Default for FragmentSettings {
+    fn default() -> Self {
+        Self {
+            mtu: 1200,
+            reassembly_timeout: Duration::from_millis(500),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Datagram {
+    Whole(Vec<u8>),
+    Fragment {
+        message_id: u16,
+        index: u8,
+        count: u8,
+        bytes: Vec<u8>,
+    },
+}
+
+/// Wraps a payload in one or more datagrams no larger than `mtu`.
+pub fn fragment_payload(payload: &[u8], mtu: usize, message_id: u16) -> Result<Vec<Vec<u8>>, Error> {
+    let chunk_size = mtu.saturating_sub(FRAGMENT_HEADER_LEN);
+    if chunk_size == 0 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("mtu of {} is too small", mtu)));
+    }
+
+    let datagrams = if payload.len() <= chunk_size {
+        vec![Datagram::Whole(payload.to_vec())]
+    } else {
+        let count = payload.len().div_ceil(chunk_size);
+        if count > MAX_FRAGMENTS {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("payload of {} bytes needs {} fragments, maximum is {}", payload.len(), count, MAX_FRAGMENTS),
+            ));
+        }
+
+        payload
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(index, chunk)| Datagram::Fragment {
+                message_id,
+                index: index as u8,
+                count: count as u8,
+                bytes: chunk.to_vec(),
+            })
+            .collect()
+    };
+
+    datagrams
+        .iter()
+        .map(|d| bincode::serde::encode_to_vec(d, config::standard()).map_err(|e| Error::new(ErrorKind::InvalidData, e)))
+        .collect()
+}
+
+#[derive(Debug)]
+struct PartialMessage {
+    first_seen: Duration,
+    parts: Vec<Option<Vec<u8>>>,
+    received: usize,
+}
+
+/// Collects fragments until their message is complete.
+#[derive(Debug, Default)]
+pub struct Reassembler {
+    pending: HashMap<u16, PartialMessage>,
+}
+
+impl Reassembler {
+    /// Decodes a datagram and returns the full payload once every fragment of it has arrived.
+    pub fn push(&mut self, datagram: &[u8], now: Duration) -> Result<Option<Vec<u8>>, Error> {
+        let (datagram, _): (Datagram, usize) = bincode::serde::decode_from_slice(datagram, config::standard())
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+
+        match datagram {
+            Datagram::Whole(bytes) => Ok(Some(bytes)),
+            Datagram::Fragment { message_id, index, count, bytes } => {
+                if count == 0 || index >= count {
+                    return Err(Error::new(ErrorKind::InvalidData, format!("bad fragment {}/{}", index, count)));
+                }
+
+                // Bounds what a peer sending nothing but first fragments can make us hold
+                if !self.pending.contains_key(&message_id) && self.pending.len() >= MAX_PENDING_MESSAGES {
+                    self.drop_oldest();
+                }
+
+                let partial = self.pending.entry(message_id).or_insert_with(|| PartialMessage {
+                    first_seen: now,
+                    parts: vec![None; count as usize],
+                    received: 0,
+                });
+
+                // A reused id with a different layout means the old message is stale
+                if partial.parts.len() != count as usize {
+                    *partial = PartialMessage {
+                        first_seen: now,
+                        parts: vec![None; count as usize],
+                        received: 0,
+                    };
+                }
+
+                let slot = &mut partial.parts[index as usize];
+                if slot.is_none() {
+                    *slot = Some(bytes);
+                    partial.received += 1;
+                }
+
+                if partial.received < partial.parts.len() {
+                    return Ok(None);
+                }
+
+                Ok(self
+                    .pending
+                    .remove(&message_id)
+                    .map(|p| p.parts.into_iter().flatten().flatten().collect()))
+            }
+        }
+    }
+
+    fn drop_oldest(&mut self) {
+        let oldest = self.pending.iter().min_by_key(|(_, p)| p.first_seen).map(|(id, _)| *id);
+        if let Some(id) = oldest {
+            self.pending.remove(&id);
+        }
+    }
+
+    /// Drops partially received messages older than `timeout`, returning how many were discarded.
+    pub fn expire(&mut self, now: Duration, timeout: Duration) -> usize {
+        let before = self.pending.len();
+        self.pending.retain(|_, p| now.saturating_sub(p.first_seen) <= timeout);
+        before - self.pending.len()
+    }
+}
@@ -0,0 +1,64 @@
+use std::io::{Error, ErrorKind};
+
+/// Size of the big-endian `u32` length prefix written before every TCP frame.
+pub const FRAME_HEADER_LEN: usize = 4;
+/// Largest payload accepted in a single frame. Anything bigger is treated as a corrupt stream.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+pub fn encode_frame(payload: &[u8]) -> Result<Vec<u8>, Error> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("frame of {} bytes exceeds maximum of {}", payload.len(), MAX_FRAME_SIZE),
+        ));
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(payload);
+    Ok(frame)
+}
+
+/// Buffers raw bytes read from a TCP stream and splits them back into the frames
+/// produced by [`encode_frame`], regardless of how the reads were coalesced or split.
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self { buffer: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns the next complete frame, `Ok(None)` if more bytes are needed, or an
+    /// error if the length prefix is larger than [`MAX_FRAME_SIZE`].
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        if self.buffer.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; FRAME_HEADER_LEN];
+        header.copy_from_slice(&self.buffer[..FRAME_HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+
+        if len > MAX_FRAME_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("incoming frame of {} bytes exceeds maximum of {}", len, MAX_FRAME_SIZE),
+            ));
+        }
+
+        if self.buffer.len() < FRAME_HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        let frame = self.buffer[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len].to_vec();
+        self.buffer.drain(..FRAME_HEADER_LEN + len);
+        Ok(Some(frame))
+    }
+}
@@ -1,34 +1,62 @@
-use crate::network::net_message::{NetworkMessage, CTcpType, CUdpType};
+use crate::network::net_address::{connect_any, resolve_server_address, unspecified_for};
+use crate::network::net_session::UdpSession;
+use crate::network::net_fragment::{fragment_payload, Reassembler, MAX_DATAGRAM_SIZE};
+use crate::network::net_framing::{encode_frame, FrameDecoder};
+use crate::network::net_conditioner::{run_conditioner, LinkChannel, LinkConditioner};
+use crate::network::net_channel::{Delivery, UdpChannel, UdpPacket};
+use crate::network::net_message::{NetworkMessage, CTcpType, CUdpType, STcpType, SUdpType};
+use crate::network::net_outbound::{OutboundQueue, TCP_QUEUE_CAPACITY, UDP_QUEUE_CAPACITY};
+use crate::network::net_transport::{FlushRequest, LoopbackListener, SocketTransport, Transport, LOOPBACK_ADDRESS};
+use crate::network::net_websocket::WebSocketTransport;
 use bevy::prelude::{Component, Resource};
 use std::collections::VecDeque;
-use std::io::Error;
+use std::io::{Error, ErrorKind};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+use bevy_tokio_tasks::TokioTasksRuntime;
 use tokio::io::Interest;
-use tokio::net::{TcpSocket, TcpStream, UdpSocket};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{mpsc, watch};
 use tokio::sync::mpsc::{Receiver, Sender};
 
+/// The transport of the current connection attempt.
 #[derive(Resource)]
 pub struct Communication {
-    pub udp_tx: Sender<(Vec<u8>, SocketAddr)>,
-    pub udp_rx: Receiver<(Vec<u8>, SocketAddr)>,
-    pub tcp_tx: Sender<(Vec<u8>, Arc<TcpStream>)>,
-    pub tcp_rx: Receiver<(Vec<u8>, Arc<TcpStream>)>,
+    pub transport: Box<dyn Transport>,
+}
+
+/// Reported by a transport when something happens to the link that the payloads can't express.
+#[derive(Debug, PartialEq)]
+pub enum LinkEvent {
+    ConnectFailed(String),
+    ReliableReady,
+    UnreliableReady,
+    ReliableClosed,
 }
 
 #[derive(Resource, Debug)]
 pub struct UdpConnection {
-    pub remote_socket: Option<SocketAddr>,
+    /// Set once the transport can carry unreliable payloads.
+    pub ready: bool,
     pub input_packet_buffer: VecDeque<Packet>,
-    output_message: Vec<NetworkMessage<CUdpType>>,
+    output_message: OutboundQueue<CUdpType>,
+    output_sequenced_message: OutboundQueue<CUdpType>,
+    pub channel: UdpChannel<CUdpType, SUdpType>,
+    pub reassembler: Reassembler,
+    next_fragment_id: u16,
+    pub session: UdpSession,
     pub ping: u32
 }
 
 #[derive(Resource, Debug)]
 pub struct TcpConnection {
-    pub stream: Option<Arc<TcpStream>>,
+    /// Set once the transport can carry reliable payloads.
+    pub ready: bool,
     pub input_packet_buffer: VecDeque<Packet>,
-    output_message: Vec<NetworkMessage<CTcpType>>,
+    /// Server messages that arrived over the reliable UDP channel instead of the stream.
+    pub relayed_messages: VecDeque<STcpType>,
+    output_message: OutboundQueue<CTcpType>,
     pub ping: u32
 }
 
@@ -38,64 +66,99 @@ pub struct Packet {
 }
 
 impl Communication {
-    pub fn new(
-        udp_tx: Sender<(Vec<u8>, SocketAddr)>,
-        udp_rx: Receiver<(Vec<u8>, SocketAddr)>,
-        tcp_tx: Sender<(Vec<u8>, Arc<TcpStream>)>,
-        tcp_rx: Receiver<(Vec<u8>, Arc<TcpStream>)>,
-    ) -> Self {
+    pub fn new(transport: impl Transport + 'static) -> Self {
         Self {
-            udp_tx,
-            udp_rx,
-            tcp_tx,
-            tcp_rx,
+            transport: Box::new(transport),
         }
     }
 }
 
 impl UdpConnection {
-    pub fn new(ip_addrs: Option<SocketAddr>) -> Self {
+    pub fn new() -> Self {
         Self {
-            remote_socket: ip_addrs,
+            ready: false,
             input_packet_buffer: VecDeque::new(),
-            output_message: Vec::new(),
+            output_message: OutboundQueue::new(UDP_QUEUE_CAPACITY),
+            output_sequenced_message: OutboundQueue::new(UDP_QUEUE_CAPACITY),
+            channel: UdpChannel::new(),
+            reassembler: Reassembler::default(),
+            next_fragment_id: 0,
+            session: UdpSession::Unbound,
             ping: 0
         }
     }
 
     pub fn add_message(&mut self, message: NetworkMessage<CUdpType>) {
-        self.output_message.push(message);
+        self.output_message.push(message.0);
     }
 
-    pub fn get_current_messages(&self) -> &Vec<NetworkMessage<CUdpType>> {
-        &self.output_message
+    pub fn add_message_with(&mut self, message: NetworkMessage<CUdpType>, delivery: Delivery) {
+        match delivery {
+            Delivery::Unreliable => self.output_message.push(message.0),
+            Delivery::UnreliableSequenced => self.output_sequenced_message.push(message.0),
+            Delivery::ReliableOrdered => self.channel.queue_reliable(message.0),
+        }
     }
 
     pub fn is_empty_messages(&self) -> bool {
-        self.output_message.is_empty()
+        self.output_message.is_empty() && self.output_sequenced_message.is_empty()
     }
 
     pub fn clear_messages(&mut self) {
         self.output_message.clear();
+        self.output_sequenced_message.clear();
+    }
+
+    /// Builds the next packet from the queued messages, most urgent first. They stay queued
+    /// until [`Self::clear_messages`], and [`Self::retract_packet`] takes the packet back if it can't be sent.
+    pub fn build_packet(&mut self, now: Duration) -> UdpPacket<CUdpType> {
+        let unreliable = self.output_message.scheduled();
+        let sequenced = self.output_sequenced_message.scheduled();
+        self.channel.build_packet(unreliable, sequenced, now)
+    }
+
+    pub fn retract_packet(&mut self, packet: &UdpPacket<CUdpType>) {
+        self.channel.retract_packet(packet.header.sequence);
+    }
+
+    /// Messages dropped and coalesced while queued since the last call.
+    pub fn take_queue_counts(&mut self) -> (u64, u64) {
+        let (dropped, coalesced) = self.output_message.take_counts();
+        let (sequenced_dropped, sequenced_coalesced) = self.output_sequenced_message.take_counts();
+        (dropped + sequenced_dropped, coalesced + sequenced_coalesced)
+    }
+
+    /// Splits an encoded packet into datagrams that fit within `mtu`.
+    pub fn fragment(&mut self, payload: &[u8], mtu: usize) -> Result<Vec<Vec<u8>>, Error> {
+        let message_id = self.next_fragment_id;
+        self.next_fragment_id = self.next_fragment_id.wrapping_add(1);
+        fragment_payload(payload, mtu, message_id)
     }
 }
 
 impl TcpConnection {
-    pub fn new(stream: Option<Arc<TcpStream>>) -> Self {
+    pub fn new() -> Self {
         Self {
-            stream,
+            ready: false,
             input_packet_buffer: Default::default(),
-            output_message: vec![],
+            relayed_messages: Default::default(),
+            output_message: OutboundQueue::new(TCP_QUEUE_CAPACITY),
             ping: 0
         }
     }
 
     pub fn add_message(&mut self, message: NetworkMessage<CTcpType>) {
-        self.output_message.push(message);
+        self.output_message.push(message.0);
     }
 
-    pub fn get_current_messages(&self) -> &Vec<NetworkMessage<CTcpType>> {
-        &self.output_message
+    /// Queued messages, most urgent first.
+    pub fn get_current_messages(&self) -> Vec<CTcpType> {
+        self.output_message.scheduled()
+    }
+
+    /// Messages dropped and coalesced while queued since the last call.
+    pub fn take_queue_counts(&mut self) -> (u64, u64) {
+        self.output_message.take_counts()
     }
 
     pub fn is_empty_messages(&self) -> bool {
@@ -105,6 +168,92 @@ impl TcpConnection {
     pub fn clear_messages(&mut self) {
         self.output_message.clear();
     }
+
+    /// Queued handshake messages, the only ones that may leave before the keys are in.
+    pub fn get_handshake_messages(&self) -> Vec<CTcpType> {
+        self.output_message.scheduled().into_iter().filter(CTcpType::is_handshake).collect()
+    }
+
+    pub fn clear_handshake_messages(&mut self) {
+        self.output_message.retain(|m| !m.is_handshake());
+    }
+}
+
+/// Picks the transport for the server address, `ws://` addresses go over a WebSocket and
+/// [`LOOPBACK_ADDRESS`] to an in-process server. Dropping the returned [`Communication`] shuts its tasks down again.
+pub fn open_communications(
+    remote_string: String,
+    runtime: &TokioTasksRuntime,
+    conditioner: watch::Receiver<LinkConditioner>,
+    loopback: Option<&LoopbackListener>,
+) -> Communication {
+    if remote_string == LOOPBACK_ADDRESS {
+        return match loopback {
+            Some(listener) => Communication::new(listener.connect()),
+            None => Communication::new(LoopbackListener::new().0.connect()),
+        };
+    }
+    if remote_string.starts_with("ws://") || remote_string.starts_with("wss://") {
+        return Communication::new(WebSocketTransport::connect(remote_string, runtime, conditioner));
+    }
+    Communication::new(open_sockets(remote_string, runtime, conditioner))
+}
+
+/// Creates fresh channels and spawns the socket tasks for one connection attempt.
+pub fn open_sockets(
+    remote_string: String,
+    runtime: &TokioTasksRuntime,
+    conditioner: watch::Receiver<LinkConditioner>,
+) -> SocketTransport {
+    let (udp_send_tx, udp_send_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
+    let (udp_receive_tx, udp_receive_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
+    let (tcp_send_tx, tcp_send_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_receive_tx, tcp_receive_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_flush_tx, tcp_flush_rx) = mpsc::channel::<FlushRequest>(1);
+    let (status_tx, status_rx) = mpsc::channel::<LinkEvent>(16);
+
+    // Socket side of the link conditioner hops
+    let (udp_socket_send_tx, udp_socket_send_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
+    let (udp_socket_receive_tx, udp_socket_receive_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1_000);
+    let (tcp_socket_send_tx, tcp_socket_send_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_socket_receive_tx, tcp_socket_receive_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+
+    runtime.spawn_background_task(|_| async move {
+        println!("starting communication");
+        println!("remote address: {}", remote_string);
+
+        tokio::spawn(run_conditioner(udp_send_rx, udp_socket_send_tx, conditioner.clone(), LinkChannel::Udp, |m| m.0.is_empty()));
+        tokio::spawn(run_conditioner(udp_socket_receive_rx, udp_receive_tx, conditioner.clone(), LinkChannel::Udp, |m| m.0.is_empty()));
+        tokio::spawn(run_conditioner(tcp_send_rx, tcp_socket_send_tx, conditioner.clone(), LinkChannel::Tcp, |m| m.0.is_empty()));
+        tokio::spawn(run_conditioner(tcp_socket_receive_rx, tcp_receive_tx, conditioner, LinkChannel::Tcp, |m| m.0.is_empty()));
+
+        let connected = match resolve_server_address(&remote_string).await {
+            Ok(addrs) => connect_any(&addrs).await,
+            Err(e) => Err(e),
+        };
+        let (stream, remote_addr) = match connected {
+            Ok(c) => c,
+            Err(e) => {
+                let _ = status_tx.send(LinkEvent::ConnectFailed(e.to_string())).await;
+                return;
+            }
+        };
+        println!("Connected to server via TCP: {}", remote_addr);
+
+        start_tcp_task(stream, tcp_socket_send_rx, tcp_flush_rx, tcp_socket_receive_tx, status_tx.clone());
+        if let Err(e) = start_udp_task(remote_addr, udp_socket_send_rx, udp_socket_receive_tx, 1).await {
+            let _ = status_tx.send(LinkEvent::ConnectFailed(e.to_string())).await;
+        }
+    });
+
+    SocketTransport::new(
+        udp_send_tx,
+        udp_receive_rx,
+        tcp_send_tx,
+        tcp_receive_rx,
+        tcp_flush_tx,
+        status_rx,
+    )
 }
 
 pub async fn start_udp_task(
@@ -113,7 +262,7 @@ pub async fn start_udp_task(
     inbound: Sender<(Vec<u8>, SocketAddr)>,
     pool_size: usize,
 ) -> Result<(), Error> {
-    let socket = Arc::new(UdpSocket::bind(SocketAddr::from(([0, 0, 0, 0], 0))).await?);
+    let socket = Arc::new(UdpSocket::bind(unspecified_for(&remote_addr)).await?);
 
     println!("Socket bound on {:?}", socket.local_addr()?);
 
@@ -124,14 +273,20 @@ pub async fn start_udp_task(
         let s = socket.clone();
 
         tokio::spawn(async move {
-            let mut buf = vec![0u8; 1024];
+            let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
             loop {
-                match s.clone().recv_from(&mut buf).await {
-                    Ok((len, addr)) => {
-                        let _ = inbound_tx.send((buf[..len].to_vec(), addr)).await;
-                    }
-                    Err(e) => {
-                        eprintln!("recv error: {e}, continuing...");
+                tokio::select! {
+                    // The client dropped this connection attempt
+                    _ = inbound_tx.closed() => break,
+                    received = s.recv_from(&mut buf) => match received {
+                        Ok((len, addr)) => {
+                            if inbound_tx.send((buf[..len].to_vec(), addr)).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("recv error: {e}, continuing...");
+                        }
                     }
                 }
             }
@@ -150,70 +305,114 @@ pub async fn start_udp_task(
     Ok(())
 }
 
-pub async fn start_tcp_task(
-    remote_addr: SocketAddr,
+pub fn start_tcp_task(
+    stream: TcpStream,
     mut outbound: Receiver<(Vec<u8>, Arc<TcpStream>)>,
+    mut flush: Receiver<FlushRequest>,
     inbound: Sender<(Vec<u8>, Arc<TcpStream>)>,
-) -> Result<(), Error> {
-    let socket = TcpSocket::new_v4()?;
+    status: Sender<LinkEvent>,
+) {
+    // TODO: Apparently this can create false positives and what it reads because of that may be empty, therefore we have to check that
+    let stream = Arc::new(stream);
+    let writer = stream.clone();
 
-    let inbound_accept = inbound.clone();
-    // Task responsible for accepting new TCP connections
+    // Task responsible for reading from the connected stream
     tokio::spawn(async move {
-        // Accept first connection in queue
-        match socket.connect(remote_addr).await {
-            Ok(stream) => {
-                println!("Connected to server via TCP: {:?}", remote_addr.ip().to_string());
-
-                // TODO: Apparently this can create false positives and what it reads because of that may be empty, therefore we have to check that
-                // Get the ready-ness value for the stream
-                let stream = Arc::new(stream);
-
-                // Save stream
-                let _ = inbound_accept.send((vec![], stream.clone())).await;
-
-                // Spawn a task dedicated to continuously reading from this client
-                let inbound_task = inbound_accept.clone();
-                let stream_task = stream.clone();
-                let mut read_buf = vec![0u8; 1024];
-                loop {
-                    let ready = stream_task.ready(Interest::READABLE).await.unwrap();
-                    if ready.is_readable() {
-                        match stream_task.try_read(&mut read_buf) {
-                            Ok(0) => { break }
-                            Ok(len) => {
-                                let _ = inbound_task
-                                    .send((read_buf[..len].to_vec(), stream_task.clone()))
-                                    .await;
-                            }
-                            Err(e) => {
-                                println!("Couldn't read: {:?}", e);
+        // Save stream
+        let _ = inbound.send((vec![], stream.clone())).await;
+
+        let mut read_buf = vec![0u8; 4096];
+        let mut decoder = FrameDecoder::new();
+        'read: loop {
+            let ready = tokio::select! {
+                // The client dropped this connection attempt
+                _ = inbound.closed() => break,
+                ready = stream.ready(Interest::READABLE) => match ready {
+                    Ok(r) => r,
+                    Err(e) => {
+                        println!("Couldn't poll stream: {:?}", e);
+                        break;
+                    }
+                }
+            };
+            if ready.is_readable() {
+                match stream.try_read(&mut read_buf) {
+                    Ok(0) => { break }
+                    Ok(len) => {
+                        decoder.push(&read_buf[..len]);
+                        loop {
+                            match decoder.next_frame() {
+                                Ok(Some(frame)) => {
+                                    if inbound.send((frame, stream.clone())).await.is_err() {
+                                        break 'read;
+                                    }
+                                }
+                                Ok(None) => break,
+                                Err(e) => {
+                                    println!("Dropping TCP connection: {:?}", e);
+                                    break 'read;
+                                }
                             }
                         }
                     }
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                    Err(e) => {
+                        println!("Couldn't read: {:?}", e);
+                        break;
+                    }
                 }
             }
-            Err(_) => {
-                println!("Couldn't connect to remote server")
-            }
         }
+
+        println!("TCP connection to server closed");
+        let _ = status.send(LinkEvent::ReliableClosed).await;
     });
 
     // Task responsible for sending queued TCP messages
     tokio::spawn(async move {
-        while let Some((bytes, stream)) = outbound.recv().await {
-            let ready = stream.ready(Interest::WRITABLE).await.unwrap();
+        let mut markers = 0u64;
+        let mut waiting: Option<FlushRequest> = None;
+        loop {
+            // A flush goes out right behind what was queued before it, however long that took to get here
+            if let Some(request) = waiting.take_if(|r| r.after <= markers) {
+                let _ = request.ack.send(write_frame(&writer, &request.payload).await);
+                continue;
+            }
 
-            if ready.is_writable() {
-                match stream.try_write(&*bytes) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Couldn't write: {:?}", e)
+            tokio::select! {
+                queued = outbound.recv() => {
+                    let Some((bytes, _)) = queued else {
+                        break;
+                    };
+                    if bytes.is_empty() {
+                        markers += 1;
+                        continue;
                     }
-                };
+                    if let Err(e) = write_frame(&writer, &bytes).await {
+                        println!("Couldn't write TCP message: {:?}", e)
+                    }
+                }
+                Some(request) = flush.recv(), if waiting.is_none() => waiting = Some(request),
             }
         }
     });
+}
 
-    Ok(())
+async fn write_frame(stream: &TcpStream, payload: &[u8]) -> Result<(), Error> {
+    write_all(stream, &encode_frame(payload)?).await
 }
+
+/// Writes the whole buffer to a shared stream, retrying partial and spurious writes.
+async fn write_all(stream: &TcpStream, mut bytes: &[u8]) -> Result<(), Error> {
+    while !bytes.is_empty() {
+        stream.writable().await?;
+        match stream.try_write(bytes) {
+            Ok(0) => return Err(Error::from(ErrorKind::WriteZero)),
+            Ok(n) => bytes = &bytes[n..],
+            Err(e) if e.kind() == ErrorKind::WouldBlock => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(())
+}
\ No newline at end of file
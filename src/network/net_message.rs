@@ -3,8 +3,15 @@ use crate::components::common::Id;
 use crate::components::player::Player;
 use bevy::prelude::Component;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 use std::collections::HashMap;
 use bevy::math::Vec2;
+use crate::network::net_quantize::{packed_mouse_delta, packed_players};
+use crate::network::net_snapshot::SnapshotDelta;
+
+/// Bumped whenever the layout of any message enum changes.
+pub const PROTOCOL_VERSION: u32 = 9;
+pub const BUILD_ID: &str = env!("CARGO_PKG_VERSION");
 
 pub trait NetworkMessageType {}
 
@@ -13,6 +20,22 @@ pub struct NetworkMessage<T: NetworkMessageType>(pub T);
 
 pub type SequenceNumber = u16;
 pub type BitMask = u16;
+
+/// Most earlier inputs one `Input` carries.
+pub const INPUT_HISTORY_LEN: usize = 8;
+
+/// Earlier inputs sent with an `Input`, kept inline so building one every tick doesn't allocate.
+pub type InputHistory = SmallVec<[InputFrame; INPUT_HISTORY_LEN]>;
+
+/// Input of one earlier tick, repeated so a single lost datagram doesn't cost the server that tick.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct InputFrame {
+    pub sequence_number: SequenceNumber,
+    pub keymask: BitMask,
+    #[serde(with = "packed_mouse_delta")]
+    pub mouse_delta: Vec2,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CUdpType {
     Sequence {
@@ -20,13 +43,27 @@ pub enum CUdpType {
     },
     Input {
         keymask: BitMask,
+        #[serde(with = "packed_mouse_delta")]
         mouse_delta: Vec2,
-        player_id: Id,
+        /// Inputs of the ticks before this one, newest first.
+        history: InputHistory,
     },
     Ping {
         intitiation_time: u32,
         last_rtt: u32,
     },
+    /// A `CTcpType` message carried over the reliable-ordered UDP channel.
+    Control {
+        message: CTcpType,
+    },
+    /// Proves the session token issued over TCP, binding this UDP endpoint to our player.
+    Connect {
+        token: u64,
+    },
+    /// Newest snapshot we hold, usable by the server as a delta baseline.
+    SnapshotAck {
+        snapshot: SequenceNumber,
+    },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -35,12 +72,34 @@ pub enum SUdpType {
         sequence_number: SequenceNumber,
     },
     Players {
+        snapshot: SequenceNumber,
+        #[serde(with = "packed_players")]
         players: HashMap<Id, Player>,
     },
+    /// Snapshot `snapshot` encoded against the acknowledged snapshot `baseline`.
+    PlayersDelta {
+        snapshot: SequenceNumber,
+        baseline: SequenceNumber,
+        delta: SnapshotDelta,
+    },
     Pong {
         initiation_time: u32,
         server_received_time: u32,
-    }
+        /// Server fixed tick at `server_received_time`.
+        server_tick: u32,
+    },
+    /// A `STcpType` message carried over the reliable-ordered UDP channel.
+    Control {
+        message: STcpType,
+    },
+    /// The server accepted our `Connect` token.
+    SessionBound,
+    /// How many ticks the input for `sequence_number` waited in the server's buffer before it was
+    /// simulated. Negative means it arrived too late and was dropped.
+    InputFeedback {
+        sequence_number: SequenceNumber,
+        buffered: i16,
+    },
 }
 
 impl NetworkMessageType for CUdpType {}
@@ -48,6 +107,12 @@ impl NetworkMessageType for SUdpType {}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum CTcpType {
+    /// Must stay the first variant so every protocol version can decode it.
+    Hello {
+        protocol_version: u32,
+        build_id: String,
+        client_name: String,
+    },
     ChatMessage {
         player_id: Id,
         message: ChatMessage,
@@ -55,17 +120,52 @@ pub enum CTcpType {
     Join {
         lobby_id: Id,
     },
-    
+    /// We are going away, sent on exit so the server can drop our player right away.
+    Leave,
+    /// Sent right after `Hello` in secure mode. Everything after the server's reply is encrypted.
+    KeyExchange {
+        public_key: [u8; 32],
+    },
+}
+
+impl CTcpType {
+    /// Sent in the clear while secure mode waits for the server's key.
+    pub fn is_handshake(&self) -> bool {
+        matches!(self, CTcpType::Hello { .. } | CTcpType::KeyExchange { .. })
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum STcpType {
+    /// `Welcome` and `Rejected` must stay the first variants so every protocol version can decode them.
+    Welcome {
+        protocol_version: u32,
+    },
+    Rejected {
+        reason: String,
+    },
     PlayerId {
         player_uid: Id,
     },
     Chat {
         messages: Vec<(Id, ChatMessage)>
     },
+    /// Token to present in `CUdpType::Connect` before any input is accepted over UDP.
+    SessionToken {
+        token: u64,
+    },
+    /// The server removed us, reconnecting won't be attempted.
+    Kicked {
+        reason: String,
+    },
+    /// The server is going down, we may try again later.
+    ServerShutdown,
+    /// Answer to our `KeyExchange`, sent after `Welcome`. `proof` shows the server holds the
+    /// pre-shared key, see `net_crypto::server_proof`.
+    KeyExchange {
+        public_key: [u8; 32],
+        proof: [u8; 32],
+    },
 }
 
 impl NetworkMessageType for CTcpType {}
@@ -0,0 +1,147 @@
+use crate::network::net_message::{CTcpType, CUdpType};
+use std::mem::discriminant;
+
+/// Messages kept waiting per queue before the least urgent ones are dropped.
+pub const UDP_QUEUE_CAPACITY: usize = 32;
+pub const TCP_QUEUE_CAPACITY: usize = 64;
+
+/// How urgent a queued message is. More urgent messages go out first and are dropped last.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Chat.
+    Low,
+    /// Pings and acks.
+    Normal,
+    /// Input and everything the session depends on.
+    High,
+}
+
+/// What happens to a message while it can't go out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueuePolicy {
+    /// Only the newest one is worth sending, it replaces a queued message of the same kind.
+    Coalesce,
+    /// Waits its turn, but is dropped oldest first once the queue is full.
+    DropOldest,
+    /// Never dropped, the session breaks without it.
+    Keep,
+}
+
+pub trait Scheduled {
+    fn priority(&self) -> Priority;
+    fn policy(&self) -> QueuePolicy;
+}
+
+impl Scheduled for CUdpType {
+    fn priority(&self) -> Priority {
+        match self {
+            CUdpType::Sequence { .. } | CUdpType::Input { .. } | CUdpType::Connect { .. } => Priority::High,
+            CUdpType::Ping { .. } | CUdpType::SnapshotAck { .. } => Priority::Normal,
+            CUdpType::Control { message } => message.priority(),
+        }
+    }
+
+    fn policy(&self) -> QueuePolicy {
+        match self {
+            // The sequence only advances once a packet went out, so a queued input is for the
+            // same tick as the new one and only the newer one still matches the prediction
+            CUdpType::Sequence { .. } | CUdpType::Input { .. } => QueuePolicy::Coalesce,
+            // A ping that waited measures the queue, not the link
+            CUdpType::Ping { .. } | CUdpType::SnapshotAck { .. } | CUdpType::Connect { .. } => QueuePolicy::Coalesce,
+            CUdpType::Control { message } => message.policy(),
+        }
+    }
+}
+
+impl Scheduled for CTcpType {
+    fn priority(&self) -> Priority {
+        match self {
+            CTcpType::ChatMessage { .. } => Priority::Low,
+            _ => Priority::High,
+        }
+    }
+
+    fn policy(&self) -> QueuePolicy {
+        match self {
+            CTcpType::ChatMessage { .. } => QueuePolicy::DropOldest,
+            _ => QueuePolicy::Keep,
+        }
+    }
+}
+
+/// Messages waiting to be sent, held to their [`QueuePolicy`] and handed out by [`Priority`].
+#[derive(Debug)]
+pub struct OutboundQueue<T> {
+    /// In the order they were queued.
+    messages: Vec<T>,
+    capacity: usize,
+    dropped: u64,
+    coalesced: u64,
+}
+
+impl<T: Scheduled + Clone> OutboundQueue<T> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: Vec::new(),
+            capacity,
+            dropped: 0,
+            coalesced: 0,
+        }
+    }
+
+    pub fn push(&mut self, message: T) {
+        if message.policy() == QueuePolicy::Coalesce
+            && let Some(queued) = self.messages.iter_mut().find(|m| discriminant(*m) == discriminant(&message))
+        {
+            *queued = message;
+            self.coalesced += 1;
+            return;
+        }
+
+        self.messages.push(message);
+        if self.messages.len() > self.capacity {
+            self.drop_oldest();
+        }
+    }
+
+    /// Drops the oldest of the least urgent messages that may be dropped at all.
+    fn drop_oldest(&mut self) {
+        let victim = self
+            .messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| m.policy() != QueuePolicy::Keep)
+            .min_by_key(|(index, m)| (m.priority(), *index))
+            .map(|(index, _)| index);
+
+        if let Some(index) = victim {
+            self.messages.remove(index);
+            self.dropped += 1;
+        }
+    }
+
+    /// Everything queued, most urgent first and in queued order within a priority.
+    pub fn scheduled(&self) -> Vec<T> {
+        let mut messages = self.messages.clone();
+        messages.sort_by_key(|m| std::cmp::Reverse(m.priority()));
+        messages
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    /// Keeps only the queued messages `keep` returns true for.
+    pub fn retain(&mut self, keep: impl FnMut(&T) -> bool) {
+        self.messages.retain(keep);
+    }
+
+    /// Messages dropped and coalesced since the last call.
+    pub fn take_counts(&mut self) -> (u64, u64) {
+        (std::mem::take(&mut self.dropped), std::mem::take(&mut self.coalesced))
+    }
+}
@@ -0,0 +1,314 @@
+use crate::components::common::{Id, Vec3};
+use crate::components::player::animation::AnimationState;
+use crate::components::player::Player;
+use crate::network::net_snapshot::PlayerDelta;
+use bevy::math::Vec2;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+use std::io::{Error, ErrorKind};
+
+/// Wire precision of player state. Client and server must agree on every value.
+#[derive(Clone, Copy, Debug)]
+pub struct Quantization {
+    /// Positions are clamped to the box between `map_min` and `map_max`.
+    pub map_min: [f32; 3],
+    pub map_max: [f32; 3],
+    /// Bits per position axis, spread evenly over the map bounds.
+    pub position_bits: u8,
+    /// Smallest velocity step.
+    pub velocity_precision: f32,
+    /// Velocities are clamped to `±max_velocity` on each axis.
+    pub max_velocity: f32,
+    /// Smallest mouse delta step, in pixels.
+    pub mouse_precision: f32,
+}
+
+pub const QUANTIZATION: Quantization = Quantization {
+    map_min: [-256.0, -64.0, -256.0],
+    map_max: [256.0, 192.0, 256.0],
+    position_bits: 20,
+    velocity_precision: 1.0 / 64.0,
+    max_velocity: 128.0,
+    mouse_precision: 1.0 / 16.0,
+};
+
+const ANGLE_BITS: u8 = 16;
+const ANIMATION_BITS: u8 = 2;
+const PLAYER_COUNT_BITS: u8 = 16;
+/// Optional [`PlayerDelta`] fields, one presence bit each.
+const DELTA_FIELDS: u8 = 5;
+
+/// Appends values of arbitrary bit width, least significant bit first.
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    bit: usize,
+}
+
+impl BitWriter {
+    pub fn write(&mut self, value: u32, bits: u8) {
+        for i in 0..bits {
+            if self.bit.is_multiple_of(8) {
+                self.bytes.push(0);
+            }
+            if (value >> i) & 1 != 0 {
+                *self.bytes.last_mut().unwrap() |= 1 << (self.bit % 8);
+            }
+            self.bit += 1;
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[derive(Debug)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, bit: 0 }
+    }
+
+    pub fn read(&mut self, bits: u8) -> Result<u32, Error> {
+        let mut value = 0;
+        for i in 0..bits {
+            let Some(byte) = self.bytes.get(self.bit / 8) else {
+                return Err(Error::new(ErrorKind::UnexpectedEof, "packed value ends early"));
+            };
+            if (byte >> (self.bit % 8)) & 1 != 0 {
+                value |= 1 << i;
+            }
+            self.bit += 1;
+        }
+        Ok(value)
+    }
+}
+
+fn max_steps(bits: u8) -> u32 {
+    ((1u64 << bits) - 1) as u32
+}
+
+impl Quantization {
+    fn velocity_bits(&self) -> u8 {
+        let steps = (2.0 * self.max_velocity / self.velocity_precision).ceil() as u64;
+        (u64::BITS - steps.leading_zeros()) as u8
+    }
+
+    fn position_step(&self, axis: usize) -> f32 {
+        (self.map_max[axis] - self.map_min[axis]) / max_steps(self.position_bits) as f32
+    }
+
+    fn write_position(&self, writer: &mut BitWriter, position: Vec3) {
+        for (axis, value) in [position.x, position.y, position.z].into_iter().enumerate() {
+            let clamped = value.clamp(self.map_min[axis], self.map_max[axis]);
+            let steps = ((clamped - self.map_min[axis]) / self.position_step(axis)).round() as u32;
+            writer.write(steps.min(max_steps(self.position_bits)), self.position_bits);
+        }
+    }
+
+    fn read_position(&self, reader: &mut BitReader) -> Result<Vec3, Error> {
+        let mut axes = [0.0; 3];
+        for (axis, value) in axes.iter_mut().enumerate() {
+            *value = self.map_min[axis] + reader.read(self.position_bits)? as f32 * self.position_step(axis);
+        }
+        Ok(Vec3::new(axes[0], axes[1], axes[2]))
+    }
+
+    fn write_velocity(&self, writer: &mut BitWriter, velocity: Vec3) {
+        let bits = self.velocity_bits();
+        for value in [velocity.x, velocity.y, velocity.z] {
+            let clamped = value.clamp(-self.max_velocity, self.max_velocity);
+            let steps = ((clamped + self.max_velocity) / self.velocity_precision).round() as u32;
+            writer.write(steps.min(max_steps(bits)), bits);
+        }
+    }
+
+    fn read_velocity(&self, reader: &mut BitReader) -> Result<Vec3, Error> {
+        let bits = self.velocity_bits();
+        let mut axes = [0.0; 3];
+        for value in axes.iter_mut() {
+            *value = reader.read(bits)? as f32 * self.velocity_precision - self.max_velocity;
+        }
+        Ok(Vec3::new(axes[0], axes[1], axes[2]))
+    }
+
+    pub fn quantize_mouse_delta(&self, delta: Vec2) -> Vec2 {
+        let (x, y) = self.mouse_steps(delta);
+        Vec2::new(x as f32, y as f32) * self.mouse_precision
+    }
+
+    fn mouse_steps(&self, delta: Vec2) -> (i16, i16) {
+        let step = |v: f32| (v / self.mouse_precision).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        (step(delta.x), step(delta.y))
+    }
+}
+
+fn write_angle(writer: &mut BitWriter, angle: f32) {
+    let turns = angle.rem_euclid(TAU) / TAU;
+    let steps = (turns * (1u32 << ANGLE_BITS) as f32).round() as u32;
+    // A full turn rounds back to zero
+    writer.write(steps & max_steps(ANGLE_BITS), ANGLE_BITS);
+}
+
+fn read_angle(reader: &mut BitReader) -> Result<f32, Error> {
+    let angle = reader.read(ANGLE_BITS)? as f32 / (1u32 << ANGLE_BITS) as f32 * TAU;
+    // Keep the `-PI..PI` range so small negative pitches stay small
+    Ok(if angle >= PI { angle - TAU } else { angle })
+}
+
+fn write_animation(writer: &mut BitWriter, state: AnimationState) {
+    let index = match state {
+        AnimationState::Idle => 0,
+        AnimationState::Walking => 1,
+    };
+    writer.write(index, ANIMATION_BITS);
+}
+
+fn read_animation(reader: &mut BitReader) -> Result<AnimationState, Error> {
+    match reader.read(ANIMATION_BITS)? {
+        0 => Ok(AnimationState::Idle),
+        1 => Ok(AnimationState::Walking),
+        other => Err(Error::new(ErrorKind::InvalidData, format!("unknown animation state {}", other))),
+    }
+}
+
+pub fn write_player(writer: &mut BitWriter, player: &Player) {
+    QUANTIZATION.write_position(writer, player.position);
+    QUANTIZATION.write_velocity(writer, player.linear_velocity);
+    write_angle(writer, player.yaw);
+    write_angle(writer, player.pitch);
+    write_animation(writer, player.animation_state);
+}
+
+pub fn read_player(reader: &mut BitReader) -> Result<Player, Error> {
+    Ok(Player {
+        position: QUANTIZATION.read_position(reader)?,
+        linear_velocity: QUANTIZATION.read_velocity(reader)?,
+        yaw: read_angle(reader)?,
+        pitch: read_angle(reader)?,
+        animation_state: read_animation(reader)?,
+    })
+}
+
+/// The player exactly as the other side will see it after a round trip through the codec.
+pub fn quantize_player(player: &Player) -> Player {
+    let mut writer = BitWriter::default();
+    write_player(&mut writer, player);
+    let bytes = writer.finish();
+    read_player(&mut BitReader::new(&bytes)).expect("a freshly packed player always decodes")
+}
+
+fn write_delta(writer: &mut BitWriter, delta: &PlayerDelta) {
+    let present = [
+        delta.position.is_some(),
+        delta.linear_velocity.is_some(),
+        delta.yaw.is_some(),
+        delta.pitch.is_some(),
+        delta.animation_state.is_some(),
+    ];
+    let mask = present.iter().enumerate().fold(0, |mask, (i, p)| mask | ((*p as u32) << i));
+    writer.write(mask, DELTA_FIELDS);
+
+    if let Some(position) = delta.position {
+        QUANTIZATION.write_position(writer, position);
+    }
+    if let Some(linear_velocity) = delta.linear_velocity {
+        QUANTIZATION.write_velocity(writer, linear_velocity);
+    }
+    if let Some(yaw) = delta.yaw {
+        write_angle(writer, yaw);
+    }
+    if let Some(pitch) = delta.pitch {
+        write_angle(writer, pitch);
+    }
+    if let Some(animation_state) = delta.animation_state {
+        write_animation(writer, animation_state);
+    }
+}
+
+fn read_delta(reader: &mut BitReader) -> Result<PlayerDelta, Error> {
+    let mask = reader.read(DELTA_FIELDS)?;
+    let has = |field: u32| (mask >> field) & 1 != 0;
+
+    Ok(PlayerDelta {
+        position: if has(0) { Some(QUANTIZATION.read_position(reader)?) } else { None },
+        linear_velocity: if has(1) { Some(QUANTIZATION.read_velocity(reader)?) } else { None },
+        yaw: if has(2) { Some(read_angle(reader)?) } else { None },
+        pitch: if has(3) { Some(read_angle(reader)?) } else { None },
+        animation_state: if has(4) { Some(read_animation(reader)?) } else { None },
+    })
+}
+
+fn write_map<T>(map: &HashMap<Id, T>, write: fn(&mut BitWriter, &T)) -> Result<Vec<u8>, Error> {
+    if map.len() > max_steps(PLAYER_COUNT_BITS) as usize {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} players don't fit in a snapshot", map.len())));
+    }
+
+    let mut writer = BitWriter::default();
+    writer.write(map.len() as u32, PLAYER_COUNT_BITS);
+    for (id, value) in map {
+        writer.write(id.0, u32::BITS as u8);
+        write(&mut writer, value);
+    }
+    Ok(writer.finish())
+}
+
+fn read_map<T>(bytes: &[u8], read: fn(&mut BitReader) -> Result<T, Error>) -> Result<HashMap<Id, T>, Error> {
+    let mut reader = BitReader::new(bytes);
+    let count = reader.read(PLAYER_COUNT_BITS)?;
+    (0..count)
+        .map(|_| Ok((Id(reader.read(u32::BITS as u8)?), read(&mut reader)?)))
+        .collect()
+}
+
+/// `#[serde(with)]` codec for a snapshot's player map.
+pub mod packed_players {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(players: &HashMap<Id, Player>, serializer: S) -> Result<S::Ok, S::Error> {
+        write_map(players, write_player)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Id, Player>, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        read_map(&bytes, read_player).map_err(serde::de::Error::custom)
+    }
+}
+
+/// `#[serde(with)]` codec for the changed players of a delta snapshot.
+pub mod packed_deltas {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(deltas: &HashMap<Id, PlayerDelta>, serializer: S) -> Result<S::Ok, S::Error> {
+        write_map(deltas, write_delta)
+            .map_err(serde::ser::Error::custom)?
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<Id, PlayerDelta>, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        read_map(&bytes, read_delta).map_err(serde::de::Error::custom)
+    }
+}
+
+/// `#[serde(with)]` codec sending a mouse delta as two fixed-point `i16`s.
+pub mod packed_mouse_delta {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(delta: &Vec2, serializer: S) -> Result<S::Ok, S::Error> {
+        QUANTIZATION.mouse_steps(*delta).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec2, D::Error> {
+        let (x, y) = <(i16, i16)>::deserialize(deserializer)?;
+        Ok(Vec2::new(x as f32, y as f32) * QUANTIZATION.mouse_precision)
+    }
+}
@@ -1,31 +1,133 @@
 use crate::components::player::Player;
-use crate::network::net_message::{BitMask, NetworkMessage, SequenceNumber, CUdpType};
-use bevy::prelude::{info, Commands, Component, Entity, Query, ResMut, Resource, Vec2};
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::network::net_message::{BitMask, InputFrame, InputHistory, NetworkMessage, SequenceNumber, CUdpType, INPUT_HISTORY_LEN};
+use bevy::prelude::{Reflect, ReflectResource, Resource, Vec2};
+use std::f32::consts::{PI, TAU};
 use crate::network::net_manage::UdpConnection;
 
 pub const BUFFER_SIZE: u16 = 1024;
+/// Mispredictions in a row tolerated by default before resimulating.
 pub const MISS_PREDICT_LIMIT: u16 = 50;
 
-#[derive(Component, Serialize, Deserialize, Clone, Debug)]
-pub struct ObjectState(pub StateType);
+/// Everything the client kept about one predicted tick.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TickRecord {
+    pub keymask: BitMask,
+    pub mouse_delta: Vec2,
+    /// Player state predicted for the tick, quantized like the server's.
+    pub player: Player,
+    /// The server's state for the tick matched, or replaced the prediction.
+    pub confirmed: bool,
+}
+
+/// Number of earlier inputs repeated in every `Input` message, at most `INPUT_HISTORY_LEN`.
+#[derive(Reflect, Resource, Clone, Copy, Debug)]
+#[reflect(Resource)]
+pub struct InputRedundancy(pub u16);
+
+impl Default for InputRedundancy {
+    fn default() -> Self {
+        Self(3)
+    }
+}
+
+/// When a mispredicted tick makes the client resimulate.
+#[derive(Reflect, Clone, Copy, Debug, PartialEq)]
+pub enum ReconcileTrigger {
+    /// On the first misprediction.
+    Immediate,
+    /// Once this many ticks in a row were mispredicted.
+    AfterMisses(u16),
+}
+
+/// How far a prediction may be off before it counts as a misprediction, and what it takes to
+/// resimulate. Tolerances are in world units and radians.
+#[derive(Reflect, Resource, Clone, Copy, Debug)]
+#[reflect(Resource)]
+pub struct ReconcilePolicy {
+    pub position_tolerance: f32,
+    pub velocity_tolerance: f32,
+    /// For both yaw and pitch.
+    pub angle_tolerance: f32,
+    pub trigger: ReconcileTrigger,
+}
+
+impl Default for ReconcilePolicy {
+    fn default() -> Self {
+        Self {
+            position_tolerance: 0.01,
+            velocity_tolerance: 0.01,
+            angle_tolerance: 0.001,
+            trigger: ReconcileTrigger::AfterMisses(MISS_PREDICT_LIMIT),
+        }
+    }
+}
+
+/// What a prediction got wrong, each field holding the error where it was past its tolerance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Divergence {
+    pub position: Option<f32>,
+    pub linear_velocity: Option<f32>,
+    pub yaw: Option<f32>,
+    pub pitch: Option<f32>,
+}
+
+impl Divergence {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl ReconcilePolicy {
+    /// Compares a prediction with the server's state. The animation state is left out, it
+    /// follows from the input and doesn't move anything.
+    pub fn divergence(&self, server: &Player, predicted: &Player) -> Divergence {
+        let past = |error: f32, tolerance: f32| (error > tolerance).then_some(error);
+        let angle = |a: f32, b: f32| ((a - b + PI).rem_euclid(TAU) - PI).abs();
+
+        Divergence {
+            position: past(server.position.distance(&predicted.position), self.position_tolerance),
+            linear_velocity: past(server.linear_velocity.distance(&predicted.linear_velocity), self.velocity_tolerance),
+            yaw: past(angle(server.yaw, predicted.yaw), self.angle_tolerance),
+            pitch: past(angle(server.pitch, predicted.pitch), self.angle_tolerance),
+        }
+    }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub enum StateType {
-    PlayerState { player: Player },
-    InputState { encoded_input: BitMask, mouse_delta: Vec2 }
+    /// Mispredictions in a row it takes to resimulate.
+    pub fn misses_to_reconcile(&self) -> u16 {
+        match self.trigger {
+            ReconcileTrigger::Immediate => 1,
+            ReconcileTrigger::AfterMisses(misses) => misses.max(1),
+        }
+    }
 }
 
+/// Predicted ticks indexed by sequence, allocated once so recording, rollback and
+/// resimulation don't allocate.
 #[derive(Resource)]
 pub struct ReconcileBuffer {
-    pub buffer: HashMap<SequenceNumber, Vec<ObjectState>>,
+    /// Every record is kept with the tick it was recorded on, so a slot still holding a tick
+    /// from an earlier lap reads as empty.
+    ticks: Box<[Option<(u32, TickRecord)>]>,
+    /// Ticks counted since startup, `sequence_counter` is this modulo `BUFFER_SIZE`.
+    tick: u32,
     pub sequence_counter: SequenceNumber,
     pub miss_predict_counter: u16
 }
 
+impl Default for ReconcileBuffer {
+    fn default() -> Self {
+        Self {
+            ticks: vec![None; BUFFER_SIZE as usize].into_boxed_slice(),
+            tick: 0,
+            sequence_counter: 0,
+            miss_predict_counter: 0,
+        }
+    }
+}
+
 impl ReconcileBuffer {
     pub fn increment_sequence_num(self: &mut Self) {
+        self.tick = self.tick.wrapping_add(1);
         if self.sequence_counter >= BUFFER_SIZE - 1 {
             self.sequence_counter = 0;
         } else {
@@ -33,25 +135,63 @@ impl ReconcileBuffer {
         }
     }
 
+    /// The tick `sequence` was last on, at most a lap before the current one.
+    pub fn tick_of(&self, sequence: SequenceNumber) -> u32 {
+        let back = (self.sequence_counter + BUFFER_SIZE - sequence % BUFFER_SIZE) % BUFFER_SIZE;
+        self.tick.wrapping_sub(back as u32)
+    }
+
+    fn slot(sequence: SequenceNumber) -> usize {
+        (sequence % BUFFER_SIZE) as usize
+    }
+
+    pub fn get(&self, sequence: SequenceNumber) -> Option<&TickRecord> {
+        let tick = self.tick_of(sequence);
+        match &self.ticks[Self::slot(sequence)] {
+            Some((recorded, record)) if *recorded == tick => Some(record),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, sequence: SequenceNumber) -> Option<&mut TickRecord> {
+        let tick = self.tick_of(sequence);
+        match &mut self.ticks[Self::slot(sequence)] {
+            Some((recorded, record)) if *recorded == tick => Some(record),
+            _ => None,
+        }
+    }
+
+    /// Stores `record` for `sequence`, replacing whatever the slot held a lap ago.
+    pub fn record(&mut self, sequence: SequenceNumber, record: TickRecord) {
+        self.ticks[Self::slot(sequence)] = Some((self.tick_of(sequence), record));
+    }
+
+    /// Forgets every tick, keeping the allocation.
+    pub fn clear(&mut self) {
+        self.ticks.fill(None);
+    }
+
+    /// Stored inputs of up to `count` ticks before the current one, newest first, stopping at the
+    /// first tick that wasn't recorded this lap. Capped at `INPUT_HISTORY_LEN`, so it never allocates.
+    pub fn recent_inputs(&self, count: u16) -> InputHistory {
+        (1..=count.min(INPUT_HISTORY_LEN as u16))
+            .map(|back| (self.sequence_counter + BUFFER_SIZE - back) % BUFFER_SIZE)
+            .map_while(|sequence_number| {
+                self.get(sequence_number).map(|tick| InputFrame {
+                    sequence_number,
+                    keymask: tick.keymask,
+                    mouse_delta: tick.mouse_delta,
+                })
+            })
+            .collect()
+    }
+
     pub fn seq_is_newer(self: &Self, rhs: SequenceNumber) -> bool {
         let diff = (self.sequence_counter.wrapping_sub(rhs)) % BUFFER_SIZE;
         diff == 0 || diff < BUFFER_SIZE / 2
     }
 }
 
-pub fn build_game_state(
-    object_states: &mut Query<(Entity, &ObjectState)>,
-    commands: &mut Commands,
-) -> Vec<ObjectState> {
-    let mut game_state = Vec::new();
-    for n in object_states.iter_mut() {
-        game_state.push(n.1.clone());
-        commands.entity(n.0).despawn();
-    }
-    
-    game_state
-}
-
 pub fn sequence_message(
     connection: &mut UdpConnection,
     reconcile_buffer: &ReconcileBuffer,
@@ -62,29 +202,3 @@ pub fn sequence_message(
         sequence_number: current_sequence,
     }));
 }
-
-pub fn store_game_state(
-    game_state: Vec<ObjectState>,
-    reconcile_buffer: &mut ResMut<ReconcileBuffer>,
-) {
-    info!("STORING GAME STATE: {:?}", reconcile_buffer.sequence_counter);
-    let current_sequence = reconcile_buffer.sequence_counter;
-    
-    reconcile_buffer
-        .buffer
-        .insert(current_sequence, game_state);
-}
-
-pub fn game_state_system(
-    mut object_states: Query<(Entity, &ObjectState)>,
-    mut reconcile_buffer: ResMut<ReconcileBuffer>,
-    mut commands: Commands
-) {
-    let game_state = build_game_state(&mut object_states, &mut commands);
-
-
-    store_game_state(
-        game_state,
-        &mut reconcile_buffer
-    );
-}
\ No newline at end of file
@@ -0,0 +1,129 @@
+use crate::network::net_message::SequenceNumber;
+use crate::network::net_reconciliation::{ReconcileBuffer, BUFFER_SIZE};
+use bevy::app::App;
+use bevy::ecs::schedule::ScheduleLabel;
+use bevy::prelude::{Component, Entity, Mut, Resource, With, World};
+
+/// Entities whose registered components are rolled back and resimulated along with the local
+/// player. Entities spawned or despawned since the rolled back tick aren't brought back or removed.
+#[derive(Component)]
+pub struct Rollback;
+
+/// Runs once per resimulated tick, before physics. Per-tick logic of rolled back components
+/// that isn't physics goes here as well as in `FixedUpdate`.
+#[derive(ScheduleLabel, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ResimulateSchedule;
+
+/// The tick a slot was saved on and the values saved then.
+type SavedTick<T> = (u32, Vec<(Entity, T)>);
+
+/// Values of `T` on every [`Rollback`] entity, per tick, indexed like the `ReconcileBuffer`.
+/// Every tick's list keeps its allocation once it grew to size, and is kept with the tick it
+/// was saved on so a slot from an earlier lap isn't restored.
+#[derive(Resource)]
+pub struct RollbackHistory<T> {
+    ticks: Box<[SavedTick<T>]>,
+}
+
+impl<T: Component + Clone> RollbackHistory<T> {
+    fn new() -> Self {
+        Self {
+            ticks: (0..BUFFER_SIZE).map(|_| (0, Vec::new())).collect(),
+        }
+    }
+
+    /// Values saved for `sequence` on `tick`, `None` if the slot holds another lap's.
+    pub fn get(&self, sequence: SequenceNumber, tick: u32) -> Option<&[(Entity, T)]> {
+        let (saved, values) = &self.ticks[(sequence % BUFFER_SIZE) as usize];
+        (*saved == tick).then_some(values.as_slice())
+    }
+
+    fn save(world: &mut World, sequence: SequenceNumber, tick: u32) {
+        let mut query = world.query_filtered::<(Entity, &T), With<Rollback>>();
+        world.resource_scope(|world, mut history: Mut<Self>| {
+            let (saved, values) = &mut history.ticks[(sequence % BUFFER_SIZE) as usize];
+            *saved = tick;
+            values.clear();
+            values.extend(query.iter(world).map(|(entity, component)| (entity, component.clone())));
+        });
+    }
+
+    fn restore(world: &mut World, sequence: SequenceNumber, tick: u32) {
+        world.resource_scope(|world, history: Mut<Self>| {
+            let Some(values) = history.get(sequence, tick) else {
+                return;
+            };
+            for (entity, component) in values {
+                if let Ok(mut entity) = world.get_entity_mut(*entity) {
+                    entity.insert(component.clone());
+                }
+            }
+        });
+    }
+}
+
+#[derive(Clone, Copy)]
+struct RollbackComponent {
+    save: fn(&mut World, SequenceNumber, u32),
+    restore: fn(&mut World, SequenceNumber, u32),
+}
+
+/// Component types registered with [`RollbackAppExt::register_rollback_component`].
+#[derive(Resource, Default)]
+pub struct RollbackRegistry {
+    components: Vec<RollbackComponent>,
+}
+
+pub trait RollbackAppExt {
+    /// Snapshots `T` on every [`Rollback`] entity each tick and restores it when the client
+    /// resimulates from a tick.
+    fn register_rollback_component<T: Component + Clone>(&mut self) -> &mut Self;
+}
+
+impl RollbackAppExt for App {
+    fn register_rollback_component<T: Component + Clone>(&mut self) -> &mut Self {
+        let world = self.world_mut();
+        if world.contains_resource::<RollbackHistory<T>>() {
+            return self;
+        }
+        world.insert_resource(RollbackHistory::<T>::new());
+        world.get_resource_or_init::<RollbackRegistry>().components.push(RollbackComponent {
+            save: RollbackHistory::<T>::save,
+            restore: RollbackHistory::<T>::restore,
+        });
+        self
+    }
+}
+
+/// Snapshots every registered component under `sequence`.
+pub fn save_rollback_components_at(world: &mut World, sequence: SequenceNumber) {
+    let Some(tick) = world.get_resource::<ReconcileBuffer>().map(|b| b.tick_of(sequence)) else {
+        return;
+    };
+    world.try_resource_scope(|world, registry: Mut<RollbackRegistry>| {
+        for component in registry.components.iter() {
+            (component.save)(world, sequence, tick);
+        }
+    });
+}
+
+/// Puts every registered component back the way it was on `sequence`, skipping whatever wasn't
+/// saved this lap.
+pub fn restore_rollback_components(world: &mut World, sequence: SequenceNumber) {
+    let Some(tick) = world.get_resource::<ReconcileBuffer>().map(|b| b.tick_of(sequence)) else {
+        return;
+    };
+    world.try_resource_scope(|world, registry: Mut<RollbackRegistry>| {
+        for component in registry.components.iter() {
+            (component.restore)(world, sequence, tick);
+        }
+    });
+}
+
+/// Snapshots the registered components under the tick being predicted, next to its `TickRecord`.
+pub fn save_rollback_components(world: &mut World) {
+    let Some(sequence) = world.get_resource::<ReconcileBuffer>().map(|b| b.sequence_counter) else {
+        return;
+    };
+    save_rollback_components_at(world, sequence);
+}
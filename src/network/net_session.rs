@@ -0,0 +1,40 @@
+use crate::network::net_manage::UdpConnection;
+use crate::network::net_message::{CUdpType, NetworkMessage};
+use bevy::prelude::{Real, Res, ResMut, Time};
+use std::time::Duration;
+
+/// How often `Connect` is repeated until the server confirms the UDP session.
+pub const CONNECT_RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Binding of our UDP endpoint to the TCP session, which is how the server tells whose inputs it receives.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UdpSession {
+    /// No token issued yet.
+    #[default]
+    Unbound,
+    /// Token received over TCP, proving it on UDP.
+    Proving {
+        token: u64,
+        last_sent: Option<Duration>,
+    },
+    /// The server accepted our token, inputs may be sent.
+    Bound,
+}
+
+impl UdpSession {
+    pub fn is_bound(&self) -> bool {
+        *self == UdpSession::Bound
+    }
+}
+
+pub fn prove_udp_session(mut connection: ResMut<UdpConnection>, time: Res<Time<Real>>) {
+    let now = time.elapsed();
+    let UdpSession::Proving { token, last_sent } = connection.session else {
+        return;
+    };
+
+    if last_sent.is_none_or(|sent| now.saturating_sub(sent) >= CONNECT_RESEND_INTERVAL) {
+        connection.add_message(NetworkMessage(CUdpType::Connect { token }));
+        connection.session = UdpSession::Proving { token, last_sent: Some(now) };
+    }
+}
@@ -0,0 +1,124 @@
+use crate::components::common::{Id, Vec3};
+use crate::components::player::animation::AnimationState;
+use crate::components::player::Player;
+use crate::network::net_channel::sequence_greater_than;
+use crate::network::net_manage::UdpConnection;
+use crate::network::net_quantize::packed_deltas;
+use crate::network::net_message::{CUdpType, NetworkMessage, SequenceNumber};
+use bevy::prelude::{Res, ResMut, Resource};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Error, ErrorKind};
+
+/// Number of received snapshots kept around as possible delta baselines.
+pub const SNAPSHOT_RING: usize = 32;
+
+/// Fields of a [`Player`] that changed since the baseline, `None` meaning unchanged.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct PlayerDelta {
+    pub position: Option<Vec3>,
+    pub linear_velocity: Option<Vec3>,
+    pub yaw: Option<f32>,
+    pub pitch: Option<f32>,
+    pub animation_state: Option<AnimationState>,
+}
+
+/// Snapshot encoded against an earlier snapshot the client acknowledged.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SnapshotDelta {
+    /// Players that are new or changed. New players carry every field.
+    #[serde(with = "packed_deltas")]
+    pub changed: HashMap<Id, PlayerDelta>,
+    pub removed: Vec<Id>,
+}
+
+impl PlayerDelta {
+    pub fn apply(&self, player: &mut Player) {
+        if let Some(position) = self.position {
+            player.position = position;
+        }
+        if let Some(linear_velocity) = self.linear_velocity {
+            player.linear_velocity = linear_velocity;
+        }
+        if let Some(yaw) = self.yaw {
+            player.yaw = yaw;
+        }
+        if let Some(pitch) = self.pitch {
+            player.pitch = pitch;
+        }
+        if let Some(animation_state) = self.animation_state {
+            player.animation_state = animation_state;
+        }
+    }
+}
+
+impl SnapshotDelta {
+    pub fn apply(&self, baseline: &HashMap<Id, Player>) -> HashMap<Id, Player> {
+        let mut players = baseline.clone();
+        for id in &self.removed {
+            players.remove(id);
+        }
+        for (id, delta) in &self.changed {
+            delta.apply(players.entry(*id).or_default());
+        }
+        players
+    }
+}
+
+/// Ring of the most recent full snapshots, indexed by snapshot sequence.
+#[derive(Resource, Debug)]
+pub struct SnapshotBuffer {
+    ring: Vec<Option<(SequenceNumber, HashMap<Id, Player>)>>,
+    /// Newest snapshot received, acknowledged in every outgoing packet.
+    pub latest: Option<SequenceNumber>,
+}
+
+impl Default for SnapshotBuffer {
+    fn default() -> Self {
+        Self {
+            ring: vec![None; SNAPSHOT_RING],
+            latest: None,
+        }
+    }
+}
+
+impl SnapshotBuffer {
+    pub fn insert(&mut self, snapshot: SequenceNumber, players: HashMap<Id, Player>) {
+        self.ring[snapshot as usize % SNAPSHOT_RING] = Some((snapshot, players));
+        if self.latest.is_none_or(|latest| sequence_greater_than(snapshot, latest)) {
+            self.latest = Some(snapshot);
+        }
+    }
+
+    pub fn get(&self, snapshot: SequenceNumber) -> Option<&HashMap<Id, Player>> {
+        match &self.ring[snapshot as usize % SNAPSHOT_RING] {
+            Some((stored, players)) if *stored == snapshot => Some(players),
+            _ => None,
+        }
+    }
+
+    /// Rebuilds snapshot `snapshot` from `delta` against `baseline` and stores it as a future baseline.
+    pub fn rebuild(
+        &mut self,
+        snapshot: SequenceNumber,
+        baseline: SequenceNumber,
+        delta: &SnapshotDelta,
+    ) -> Result<HashMap<Id, Player>, Error> {
+        let Some(base) = self.get(baseline) else {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("baseline snapshot {} is no longer available", baseline),
+            ));
+        };
+
+        let players = delta.apply(base);
+        self.insert(snapshot, players.clone());
+        Ok(players)
+    }
+}
+
+pub fn add_snapshot_ack(mut connection: ResMut<UdpConnection>, snapshots: Res<SnapshotBuffer>) {
+    if let Some(snapshot) = snapshots.latest {
+        connection.add_message(NetworkMessage(CUdpType::SnapshotAck { snapshot }));
+    }
+}
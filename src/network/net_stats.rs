@@ -0,0 +1,211 @@
+use crate::network::net_clock::{now_ms, ClockSync};
+use crate::network::net_dilation::InputLead;
+use crate::network::net_manage::UdpConnection;
+use bevy::prelude::{Real, Res, ResMut, Resource, Time};
+use bevy_inspector_egui::bevy_egui::EguiContexts;
+use bevy_inspector_egui::egui;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Number of samples kept for the net graph.
+pub const HISTORY_LEN: usize = 120;
+pub const SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+/// Weight of a new measurement in the smoothed RTT and jitter.
+const SMOOTHING: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChannelStats {
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+    /// Messages dropped from the outbound queue before they could be sent.
+    pub dropped: u64,
+    /// Messages replaced in the outbound queue by a newer one of the same kind.
+    pub coalesced: u64,
+    /// Sends the transport refused, everything in them stayed queued.
+    pub blocked: u64,
+}
+
+impl ChannelStats {
+    pub fn record_in(&mut self, bytes: usize) {
+        self.bytes_in += bytes as u64;
+        self.packets_in += 1;
+    }
+
+    pub fn record_out(&mut self, bytes: usize) {
+        self.bytes_out += bytes as u64;
+        self.packets_out += 1;
+    }
+
+    pub fn record_queue(&mut self, (dropped, coalesced): (u64, u64)) {
+        self.dropped += dropped;
+        self.coalesced += coalesced;
+    }
+}
+
+/// One point on the net graph, rates are per second over the last [`SAMPLE_INTERVAL`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NetSample {
+    pub udp_in_bytes: f32,
+    pub udp_out_bytes: f32,
+    pub tcp_in_bytes: f32,
+    pub tcp_out_bytes: f32,
+    pub loss: f32,
+    pub rtt_ms: f32,
+    pub jitter_ms: f32,
+    pub snapshot_rate: f32,
+}
+
+#[derive(Resource, Debug, Default)]
+pub struct NetStats {
+    pub udp: ChannelStats,
+    pub tcp: ChannelStats,
+    /// Estimated fraction of incoming UDP packets lost over the last sample.
+    pub loss: f32,
+    pub smoothed_rtt_ms: f32,
+    /// Smoothed difference between consecutive RTT measurements.
+    pub jitter_ms: f32,
+    pub snapshots_received: u64,
+    pub decode_failures: u64,
+    pub history: VecDeque<NetSample>,
+    last_rtt_ms: Option<f32>,
+    last_sample: Option<(Duration, NetTotals)>,
+}
+
+/// Cumulative counters at the time of the previous sample.
+#[derive(Clone, Copy, Debug, Default)]
+struct NetTotals {
+    udp: ChannelStats,
+    tcp: ChannelStats,
+    snapshots: u64,
+    packets_received: u64,
+    packets_missing: u64,
+}
+
+impl NetStats {
+    pub fn record_rtt(&mut self, rtt_ms: f32) {
+        match self.last_rtt_ms {
+            Some(last) => {
+                self.smoothed_rtt_ms += SMOOTHING * (rtt_ms - self.smoothed_rtt_ms);
+                self.jitter_ms += SMOOTHING * ((rtt_ms - last).abs() - self.jitter_ms);
+            }
+            None => self.smoothed_rtt_ms = rtt_ms,
+        }
+        self.last_rtt_ms = Some(rtt_ms);
+    }
+}
+
+pub fn sample_net_stats(
+    mut stats: ResMut<NetStats>,
+    udp_connection: Res<UdpConnection>,
+    time: Res<Time<Real>>,
+) {
+    let now = time.elapsed();
+    let totals = NetTotals {
+        udp: stats.udp,
+        tcp: stats.tcp,
+        snapshots: stats.snapshots_received,
+        packets_received: udp_connection.channel.packets_received,
+        packets_missing: udp_connection.channel.packets_missing,
+    };
+
+    let Some((last_time, last)) = stats.last_sample else {
+        stats.last_sample = Some((now, totals));
+        return;
+    };
+
+    let elapsed = now.saturating_sub(last_time);
+    if elapsed < SAMPLE_INTERVAL {
+        return;
+    }
+    let seconds = elapsed.as_secs_f32();
+
+    // The channel restarts its counters on every reconnect
+    let received = totals.packets_received.saturating_sub(last.packets_received);
+    let missing = totals.packets_missing.saturating_sub(last.packets_missing);
+    if received + missing > 0 {
+        stats.loss = missing as f32 / (received + missing) as f32;
+    }
+
+    let sample = NetSample {
+        udp_in_bytes: (totals.udp.bytes_in - last.udp.bytes_in) as f32 / seconds,
+        udp_out_bytes: (totals.udp.bytes_out - last.udp.bytes_out) as f32 / seconds,
+        tcp_in_bytes: (totals.tcp.bytes_in - last.tcp.bytes_in) as f32 / seconds,
+        tcp_out_bytes: (totals.tcp.bytes_out - last.tcp.bytes_out) as f32 / seconds,
+        loss: stats.loss,
+        rtt_ms: stats.smoothed_rtt_ms,
+        jitter_ms: stats.jitter_ms,
+        snapshot_rate: (totals.snapshots - last.snapshots) as f32 / seconds,
+    };
+
+    if stats.history.len() >= HISTORY_LEN {
+        stats.history.pop_front();
+    }
+    stats.history.push_back(sample);
+    stats.last_sample = Some((now, totals));
+}
+
+pub fn net_graph_ui(mut contexts: EguiContexts, stats: Res<NetStats>, clock: Res<ClockSync>, input_lead: Res<InputLead>) {
+    let Ok(ctx) = contexts.ctx_mut() else {
+        return;
+    };
+
+    egui::Window::new("Net Graph").default_open(false).show(ctx, |ui| {
+        ui.label(format!(
+            "UDP  in {} pkts / {} B   out {} pkts / {} B",
+            stats.udp.packets_in, stats.udp.bytes_in, stats.udp.packets_out, stats.udp.bytes_out
+        ));
+        ui.label(format!(
+            "TCP  in {} pkts / {} B   out {} pkts / {} B",
+            stats.tcp.packets_in, stats.tcp.bytes_in, stats.tcp.packets_out, stats.tcp.bytes_out
+        ));
+        ui.label(format!(
+            "Queued out  UDP dropped {} coalesced {} blocked {}   TCP dropped {} blocked {}",
+            stats.udp.dropped, stats.udp.coalesced, stats.udp.blocked, stats.tcp.dropped, stats.tcp.blocked
+        ));
+        ui.label(format!("Decode failures: {}", stats.decode_failures));
+        if clock.is_synchronized() {
+            ui.label(format!(
+                "Clock offset {:.0} ms   RTT {:.0} ± {:.0} ms   tick offset {:+}   server tick ~{:.0}",
+                clock.offset_ms,
+                clock.smoothed_rtt_ms,
+                clock.rtt_variance_ms,
+                clock.tick_offset,
+                clock.server_tick_at(now_ms()).unwrap_or_default()
+            ));
+        }
+        ui.label(format!(
+            "Input lead {:.2} / {:.2} ticks   speed {:.3}   starved {}",
+            input_lead.buffered, input_lead.target, input_lead.speed, input_lead.starved
+        ));
+        ui.separator();
+
+        plot(ui, "RTT (ms)", &stats.history, |s| s.rtt_ms);
+        plot(ui, "Jitter (ms)", &stats.history, |s| s.jitter_ms);
+        plot(ui, "Loss (%)", &stats.history, |s| s.loss * 100.0);
+        plot(ui, "Snapshots / s", &stats.history, |s| s.snapshot_rate);
+        plot(ui, "UDP in (B/s)", &stats.history, |s| s.udp_in_bytes);
+        plot(ui, "UDP out (B/s)", &stats.history, |s| s.udp_out_bytes);
+        plot(ui, "TCP in (B/s)", &stats.history, |s| s.tcp_in_bytes);
+        plot(ui, "TCP out (B/s)", &stats.history, |s| s.tcp_out_bytes);
+    });
+}
+
+fn plot(ui: &mut egui::Ui, label: &str, history: &VecDeque<NetSample>, value: fn(&NetSample) -> f32) {
+    let latest = history.back().map(value).unwrap_or_default();
+    ui.label(format!("{}: {:.1}", label, latest));
+
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(240.0, 32.0), egui::Sense::hover());
+    let max = history.iter().map(value).fold(0.0, f32::max).max(f32::EPSILON);
+    let step = rect.width() / (HISTORY_LEN - 1) as f32;
+
+    let points = history
+        .iter()
+        .enumerate()
+        .map(|(i, s)| egui::pos2(rect.left() + i as f32 * step, rect.bottom() - value(s) / max * rect.height()))
+        .collect();
+
+    ui.painter().rect_filled(rect, 0.0, egui::Color32::from_black_alpha(96));
+    ui.painter().add(egui::Shape::line(points, egui::Stroke::new(1.0, egui::Color32::LIGHT_GREEN)));
+}
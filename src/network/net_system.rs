@@ -1,39 +1,68 @@
-use crate::Communication;
-use crate::network::net_manage::{Packet, TcpConnection, UdpConnection};
-use crate::network::net_reconciliation::{ReconcileBuffer, ObjectState, build_game_state, sequence_message, store_game_state};
-use bevy::prelude::{Commands, Entity, Query, ResMut};
+use crate::network::net_manage::{Communication, Packet, TcpConnection, UdpConnection};
+use crate::network::net_reconciliation::{ReconcileBuffer, sequence_message};
+use crate::network::net_capture::{CaptureChannel, CaptureDirection, PacketCapture};
+use crate::network::net_channel::{ControlRoute, Delivery};
+use crate::network::net_clock::ClockSync;
+use crate::network::net_connection::ConnectionLifecycle;
+use crate::network::net_crypto::TransportSecurity;
+use crate::network::net_fragment::FragmentSettings;
+use crate::network::net_message::{CUdpType, NetworkMessage};
+use crate::network::net_stats::NetStats;
+use bevy::prelude::{Commands, Entity, Query, Real, Res, ResMut, Time};
 use bincode::config;
-use tokio::net::TcpStream;
-use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
 
 pub fn udp_client_net_receive(
     mut comm: ResMut<Communication>,
     mut connection: ResMut<UdpConnection>,
+    mut lifecycle: ResMut<ConnectionLifecycle>,
+    mut stats: ResMut<NetStats>,
+    fragment_settings: Res<FragmentSettings>,
+    time: Res<Time<Real>>,
 ) {
-    while !comm.udp_rx.is_empty() {
-        match comm.udp_rx.try_recv() {
-            Ok((bytes, addr)) => {
-                match connection.remote_socket {
-                    Some(_) => {
-                        connection.input_packet_buffer.push_back(Packet { bytes });
-                    }
-                    None => {
-                        connection.remote_socket = Some(addr);
-                    }
-                }
+    let now = time.elapsed();
+
+    while let Some(bytes) = comm.transport.receive_unreliable() {
+        lifecycle.last_heard = now;
+        stats.udp.record_in(bytes.len());
+        match connection.reassembler.push(&bytes, now) {
+            Ok(Some(bytes)) => connection.input_packet_buffer.push_back(Packet { bytes }),
+            Ok(None) => {}
+            Err(e) => {
+                stats.decode_failures += 1;
+                println!("Couldn't reassemble UDP datagram: {:?}", e);
             }
-            Err(_) => {}
         }
     }
+
+    let expired = connection.reassembler.expire(now, fragment_settings.reassembly_timeout);
+    if expired > 0 {
+        println!("Dropped {} incomplete UDP messages", expired);
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn udp_client_net_send(
-    comm: ResMut<Communication>,
+    mut comm: ResMut<Communication>,
     mut connection: ResMut<UdpConnection>,
     mut reconcile_buffer: ResMut<ReconcileBuffer>,
+    fragment_settings: Res<FragmentSettings>,
+    mut security: ResMut<TransportSecurity>,
+    mut stats: ResMut<NetStats>,
     mut commands: Commands,
+    mut capture: Option<ResMut<PacketCapture>>,
+    clock: Res<ClockSync>,
+    time: Res<Time<Real>>,
 ) {
-    if !connection.is_empty_messages() {
+    stats.udp.record_queue(connection.take_queue_counts());
+
+    // Until the transport is up, and in secure mode the keys are in, everything waits in the
+    // queue, bounded by its policies
+    if !connection.ready || !security.is_ready() {
+        return;
+    }
+
+    let now = time.elapsed();
+    if !connection.is_empty_messages() || connection.channel.has_reliable_due(now) {
         sequence_message(
             &mut connection,
             &reconcile_buffer,
@@ -41,24 +70,55 @@ pub fn udp_client_net_send(
         
         
         
-        let encoded_message = match bincode::serde::encode_to_vec(connection.get_current_messages(), config::standard()) {
+        let packet = connection.build_packet(now);
+        let encoded_message = match bincode::serde::encode_to_vec(&packet, config::standard()) {
             Ok(m) => m,
             Err(e) => {
                 println!("Couldn't encode UDP message: {:?}", e);
+                connection.retract_packet(&packet);
+                return;
+            }
+        };
+        let plaintext = capture.is_some().then(|| encoded_message.clone());
+        let encoded_message = match security.seal_udp(encoded_message) {
+            Ok(m) => m,
+            Err(e) => {
+                println!("Couldn't encrypt UDP message: {:?}", e);
+                connection.retract_packet(&packet);
                 return;
             }
         };
 
-        if let Some(remote_socket) = &connection.remote_socket {
-            match comm.udp_tx.try_send(( encoded_message, *remote_socket )) {
-                Ok(()) => {
-                    connection.clear_messages();
-                }
-                Err(TrySendError::Full(_)) => {}
-                Err(TrySendError::Closed(_)) => {}
+        let datagrams = match connection.fragment(&encoded_message, fragment_settings.mtu) {
+            Ok(d) => d,
+            Err(e) => {
+                println!("Couldn't fragment UDP message: {:?}", e);
+                connection.retract_packet(&packet);
+                return;
             }
+        };
+
+        for datagram in datagrams {
+            let len = datagram.len();
+            if comm.transport.send_unreliable(datagram).is_err() {
+                // Neither the packet nor the tick count as sent, the messages go out with the next one
+                stats.udp.blocked += 1;
+                connection.retract_packet(&packet);
+                return;
+            }
+            stats.udp.record_out(len);
+        }
+
+        connection.clear_messages();
+        if let (Some(capture), Some(plaintext)) = (&mut capture, plaintext) {
+            // The prediction goes first, so a replay has it when the packet's inputs come by
+            if let Some(tick) = reconcile_buffer.get(reconcile_buffer.sequence_counter)
+                && let Ok(prediction) = bincode::serde::encode_to_vec(tick.player, config::standard())
+            {
+                capture.record(clock.local_tick, CaptureDirection::Outbound, CaptureChannel::Prediction, &prediction);
+            }
+            capture.record(clock.local_tick, CaptureDirection::Outbound, CaptureChannel::Udp, &plaintext);
         }
-        
         reconcile_buffer.increment_sequence_num();
     }
 }
@@ -66,45 +126,84 @@ pub fn udp_client_net_send(
 pub fn tcp_client_net_receive(
     mut connection: ResMut<TcpConnection>,
     mut comm: ResMut<Communication>,
+    mut lifecycle: ResMut<ConnectionLifecycle>,
+    mut stats: ResMut<NetStats>,
+    time: Res<Time<Real>>,
 ) {
-    while !comm.tcp_rx.is_empty() {
-        match comm.tcp_rx.try_recv() {
-            Ok((bytes, stream)) => match connection.stream {
-                Some(_) => {
-                    connection.input_packet_buffer.push_back(Packet { bytes });
-                }
-                None => {
-                    connection.stream = Some(stream);
-                }
-            },
-            Err(TryRecvError::Empty) => break,
-            Err(TryRecvError::Disconnected) => break,
-        }
+    while let Some(bytes) = comm.transport.receive_reliable() {
+        lifecycle.last_heard = time.elapsed();
+        stats.tcp.record_in(bytes.len());
+        connection.input_packet_buffer.push_back(Packet { bytes });
     }
 }
 
-pub fn tcp_client_net_send(comm: ResMut<Communication>, mut connection: ResMut<TcpConnection>) {
-    if !connection.is_empty_messages() {
-        let encoded_message = match bincode::serde::encode_to_vec(connection.get_current_messages(), config::standard()) {
+#[allow(clippy::too_many_arguments)]
+pub fn tcp_client_net_send(
+    mut comm: ResMut<Communication>,
+    mut connection: ResMut<TcpConnection>,
+    mut udp_connection: ResMut<UdpConnection>,
+    mut security: ResMut<TransportSecurity>,
+    mut stats: ResMut<NetStats>,
+    route: Res<ControlRoute>,
+    mut capture: Option<ResMut<PacketCapture>>,
+    clock: Res<ClockSync>,
+) {
+    stats.tcp.record_queue(connection.take_queue_counts());
+
+    // The handshake goes over TCP, UDP carries nothing but sealed payloads in secure mode
+    if *route == ControlRoute::Udp && udp_connection.ready && security.is_ready() {
+        for message in connection.get_current_messages() {
+            udp_connection.add_message_with(
+                NetworkMessage(CUdpType::Control { message }),
+                Delivery::ReliableOrdered,
+            );
+        }
+        connection.clear_messages();
+        return;
+    }
+
+    if !connection.ready || connection.is_empty_messages() {
+        return;
+    }
+
+    // In secure mode everything but the handshake waits for the keys, it would go out in the clear
+    let ready = security.is_ready();
+    let messages = if ready {
+        connection.get_current_messages()
+    } else {
+        connection.get_handshake_messages()
+    };
+
+    if !messages.is_empty() {
+        let encoded_message = match bincode::serde::encode_to_vec(messages, config::standard()) {
             Ok(m) => m,
             Err(e) => {
                 println!("Couldn't encode TCP message: {:?}", e);
                 return;
             }
         };
+        let plaintext = capture.is_some().then(|| encoded_message.clone());
+        let encoded_message = match security.seal_tcp(encoded_message) {
+            Ok(m) => m,
+            Err(e) => {
+                println!("Couldn't encrypt TCP message: {:?}", e);
+                return;
+            }
+        };
 
-        if let Some(s) = &connection.stream {
-            match comm.tcp_tx.try_send((encoded_message, s.clone())) {
-                Ok(()) => {
-                    connection.clear_messages();
-                }
-                Err(TrySendError::Full(_)) => return,
-                Err(TrySendError::Closed(_)) => return,
-            };
+        let len = encoded_message.len();
+        if comm.transport.send_reliable(encoded_message).is_ok() {
+            stats.tcp.record_out(len);
+            if ready {
+                connection.clear_messages();
+            } else {
+                connection.clear_handshake_messages();
+            }
+            if let (Some(capture), Some(plaintext)) = (&mut capture, plaintext) {
+                capture.record(clock.local_tick, CaptureDirection::Outbound, CaptureChannel::Tcp, &plaintext);
+            }
+        } else {
+            stats.tcp.blocked += 1;
         }
     }
 }
-
-fn same_stream(a: &TcpStream, b: &TcpStream) -> bool {
-    a.peer_addr().ok() == b.peer_addr().ok() && a.local_addr().ok() == b.local_addr().ok()
-}
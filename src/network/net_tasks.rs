@@ -1,86 +1,163 @@
-use std::time::SystemTime;
 use crate::components::chat::{Chat, add_chat_message};
 use crate::components::common::Id;
-use crate::components::player::{PlayerInfo, reconcile_player, set_player_id, update_players, PlayerMarker};
+use crate::components::player::{Player, PlayerInfo, PredictionCheck, reconcile_player, set_player_id, update_players, PlayerMarker};
+use crate::network::net_capture::{CaptureChannel, CaptureDirection, PacketCapture};
+use crate::network::net_channel::UdpPacket;
+use crate::network::net_clock::{now_ms, ClockSync};
+use crate::network::net_crypto::TransportSecurity;
+use crate::network::net_dilation::InputLead;
+use crate::network::net_connection::{close_link, ConnectionLifecycle, ConnectionState, ConnectionStateChanged};
 use crate::network::net_manage::{TcpConnection, UdpConnection};
-use crate::network::net_message::{NetworkMessage, STcpType, SUdpType};
-use crate::network::net_reconciliation::ReconcileBuffer;
-use bevy::asset::{AssetServer, Assets};
-use bevy::pbr::StandardMaterial;
-use bevy::prelude::{AnimationGraph, Commands, Entity, Gizmos, Mesh, Query, Res, ResMut, Transform, With};
+use crate::network::net_message::{NetworkMessage, SequenceNumber, STcpType, SUdpType, PROTOCOL_VERSION};
+use crate::network::net_reconciliation::{ReconcileBuffer, ReconcilePolicy};
+use crate::network::net_session::UdpSession;
+use crate::network::net_snapshot::SnapshotBuffer;
+use crate::network::net_stats::NetStats;
+use bevy::prelude::{Commands, Entity, EventWriter, Query, Real, Res, ResMut, Time, Transform, With};
 use bincode::config;
+use std::collections::HashMap;
 use crate::components::camera::CameraInfo;
 use crate::components::player::animation::PlayerAnimationState;
-use crate::DefaultFont;
 use crate::network::net_message::CUdpType::Ping;
 
+/// Reconciles the local player against a full or rebuilt snapshot and moves everyone else.
+#[allow(clippy::too_many_arguments)]
+fn apply_players(
+    commands: &mut Commands,
+    prediction_check: &mut PredictionCheck,
+    policy: &ReconcilePolicy,
+    seq_num: SequenceNumber,
+    players: &HashMap<Id, Player>,
+    client_players: &mut Query<(&mut Transform, &Id, Entity, &CameraInfo, &mut PlayerAnimationState), With<PlayerMarker>>,
+    player_info: &Res<PlayerInfo>,
+    reconcile_buffer: &mut ReconcileBuffer,
+) {
+    reconcile_player(commands, prediction_check, policy, seq_num, players, client_players, player_info, reconcile_buffer);
+    update_players(commands, players, client_players, player_info);
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn handle_udp_message(
-    mut gizmos: Gizmos,
     mut connection: ResMut<UdpConnection>,
     mut client_players: Query<(&mut Transform, &Id, Entity, &CameraInfo, &mut PlayerAnimationState), With<PlayerMarker>>,
     mut commands: Commands, 
-    asset_server: Res<AssetServer>,
-    mut animation_graphs: ResMut<Assets<AnimationGraph>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
     mut reconcile_buffer: ResMut<ReconcileBuffer>,
-    default_font: Res<DefaultFont>,
+    mut prediction_check: ResMut<PredictionCheck>,
+    policy: Res<ReconcilePolicy>,
     player_info: Res<PlayerInfo>,
+    mut tcp_connection: ResMut<TcpConnection>,
+    mut stats: ResMut<NetStats>,
+    mut clock: ResMut<ClockSync>,
+    mut input_lead: ResMut<InputLead>,
+    mut snapshots: ResMut<SnapshotBuffer>,
+    mut security: ResMut<TransportSecurity>,
+    mut capture: Option<ResMut<PacketCapture>>,
+    time: Res<Time<Real>>,
 ) {
     while let Some(p) = connection.input_packet_buffer.pop_front() {
-        let decoded_message: (Vec<SUdpType>, usize) = match bincode::serde::decode_from_slice(&p.bytes, config::standard()) {
+        let bytes = match security.open_udp(p.bytes) {
+            Ok(b) => b,
+            Err(e) => {
+                stats.decode_failures += 1;
+                println!("Dropped UDP message: {:?}", e);
+                continue;
+            }
+        };
+
+        if let Some(capture) = &mut capture {
+            capture.record(clock.local_tick, CaptureDirection::Inbound, CaptureChannel::Udp, &bytes);
+        }
+
+        let decoded_packet: (UdpPacket<SUdpType>, usize) = match bincode::serde::decode_from_slice(&bytes, config::standard()) {
             Ok(m) => m,
             Err(e) => {
+                stats.decode_failures += 1;
                 println!("Couldn't decode UDP message: {:?}", e);
                 continue;
             }
         };
 
+        let Some(messages) = connection.channel.receive_packet(decoded_packet.0, time.elapsed()) else {
+            continue;
+        };
+
         let mut seq_num = None;
 
-        for m in decoded_message.0.iter() {
+        for m in messages.iter() {
             match m {
                 SUdpType::Sequence { sequence_number } => {
-                    seq_num = Some(sequence_number);
+                    seq_num = Some(*sequence_number);
                 }
                 _ => {}
             }
         }
 
-        if seq_num.is_none() {
-            println!("No sequence number given");
-            continue;
-        }
-        
-        for m in decoded_message.0.iter() {
+        for m in messages {
             match m {
-                SUdpType::Players { players } => {
-                    reconcile_player(
+                SUdpType::Players { snapshot, players } => {
+                    let Some(seq_num) = seq_num else {
+                        println!("No sequence number given");
+                        continue;
+                    };
+
+                    snapshots.insert(snapshot, players.clone());
+                    stats.snapshots_received += 1;
+                    apply_players(
                         &mut commands,
-                        &mut gizmos,
-                        *seq_num.unwrap(),
+                        &mut prediction_check,
+                        &policy,
+                        seq_num,
                         &players,
                         &mut client_players,
                         &player_info,
                         &mut reconcile_buffer,
                     );
-                    update_players(
+                }
+                SUdpType::PlayersDelta { snapshot, baseline, delta } => {
+                    let Some(seq_num) = seq_num else {
+                        println!("No sequence number given");
+                        continue;
+                    };
+
+                    let players = match snapshots.rebuild(snapshot, baseline, &delta) {
+                        Ok(players) => players,
+                        Err(e) => {
+                            stats.decode_failures += 1;
+                            println!("Couldn't rebuild snapshot {}: {:?}", snapshot, e);
+                            continue;
+                        }
+                    };
+                    stats.snapshots_received += 1;
+                    apply_players(
                         &mut commands,
-                        &default_font,
-                        &asset_server,
-                        &mut animation_graphs,
-                        // &mut meshes,
-                        // &mut materials,
+                        &mut prediction_check,
+                        &policy,
+                        seq_num,
                         &players,
                         &mut client_players,
                         &player_info,
+                        &mut reconcile_buffer,
                     );
-                },
-                SUdpType::Pong { initiation_time, server_received_time } => {
-                    let time_now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u32;
-                    let rtt = time_now - *initiation_time;
+                }
+                SUdpType::Pong { initiation_time, server_received_time, server_tick } => {
+                    let time_now = now_ms();
+                    let rtt = time_now.wrapping_sub(initiation_time);
                     
                     connection.ping = rtt;
+                    stats.record_rtt(rtt as f32);
+                    clock.record(initiation_time, server_received_time, server_tick, time_now);
+                }
+                SUdpType::Control { message } => {
+                    tcp_connection.relayed_messages.push_back(message);
+                }
+                SUdpType::SessionBound => {
+                    if matches!(connection.session, UdpSession::Proving { .. }) {
+                        println!("UDP session bound");
+                        connection.session = UdpSession::Bound;
+                    }
+                }
+                SUdpType::InputFeedback { buffered, .. } => {
+                    input_lead.record(buffered);
                 }
                 SUdpType::Sequence { .. } => {}
             }
@@ -88,38 +165,138 @@ pub fn handle_udp_message(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn handle_tcp_message(
+    mut commands: Commands,
     mut player_info: ResMut<PlayerInfo>,
     mut chat: Query<&mut Chat>,
     mut connection: ResMut<TcpConnection>,
-    mut reconcile_buffer: ResMut<ReconcileBuffer>
+    mut reconcile_buffer: ResMut<ReconcileBuffer>,
+    mut udp_connection: ResMut<UdpConnection>,
+    mut lifecycle: ResMut<ConnectionLifecycle>,
+    mut state_events: EventWriter<ConnectionStateChanged>,
+    mut stats: ResMut<NetStats>,
+    mut security: ResMut<TransportSecurity>,
+    mut capture: Option<ResMut<PacketCapture>>,
+    clock: Res<ClockSync>,
+    time: Res<Time<Real>>,
 ) {
-    while let Some(p) = connection.input_packet_buffer.pop_front() {
-        let mut decoded_message: (Vec<STcpType>, usize) = match bincode::serde::decode_from_slice(&p.bytes, config::standard()) {
-            Ok(m) => m,
-            Err(e) => {
-                println!("Couldn't decode TCP message: {:?}", e);
-                continue;
-            }
-        };
-        
-        for m in decoded_message.0.iter_mut() {
+    let mut messages: Vec<STcpType> = connection.relayed_messages.drain(..).collect();
+
+    // A frame's messages are handled before the next frame is opened, the frames after the
+    // server's key are sealed with it
+    loop {
+        for m in messages.iter_mut() {
             match m {
+                STcpType::Welcome { protocol_version } => {
+                    if *protocol_version == PROTOCOL_VERSION {
+                        lifecycle.attempts = 0;
+                        lifecycle.last_heard = time.elapsed();
+                        // In secure mode the server's `KeyExchange` completes the handshake
+                        if security.is_ready() {
+                            lifecycle.transition(ConnectionState::Connected, time.elapsed(), &mut state_events);
+                        }
+                    } else {
+                        let reason = format!("Server protocol v{} does not match client v{}", protocol_version, PROTOCOL_VERSION);
+                        lifecycle.reject(reason, time.elapsed(), &mut state_events);
+                        return;
+                    }
+                }
+                STcpType::Rejected { reason } => {
+                    lifecycle.reject(reason.clone(), time.elapsed(), &mut state_events);
+                    return;
+                }
                 STcpType::Chat { messages } => {
                     add_chat_message(messages, &mut chat);
                 },
                 STcpType::PlayerId { player_uid } => {
                     set_player_id(&mut player_info, *player_uid, &mut reconcile_buffer);
+                    lifecycle.transition(ConnectionState::Joined, time.elapsed(), &mut state_events);
+                }
+                STcpType::SessionToken { token } => {
+                    udp_connection.session = UdpSession::Proving { token: *token, last_sent: None };
+                }
+                STcpType::Kicked { reason } => {
+                    lifecycle.reject(format!("Kicked: {}", reason), time.elapsed(), &mut state_events);
+                    close_link(&mut commands);
+                    return;
+                }
+                STcpType::ServerShutdown => {
+                    println!("Server is shutting down");
+                    lifecycle.last_error = Some("server shut down".to_string());
+                    lifecycle.transition(ConnectionState::Disconnected, time.elapsed(), &mut state_events);
+                    lifecycle.schedule_retry(time.elapsed());
+                    close_link(&mut commands);
+                    return;
+                }
+                STcpType::KeyExchange { public_key, proof } => {
+                    if let Err(e) = security.complete(*public_key, *proof) {
+                        lifecycle.reject(format!("Key exchange failed: {}", e), time.elapsed(), &mut state_events);
+                        connection.input_packet_buffer.clear();
+                        return;
+                    }
+                    if lifecycle.state == ConnectionState::Handshaking {
+                        lifecycle.transition(ConnectionState::Connected, time.elapsed(), &mut state_events);
+                    }
+                }
+            }
+        }
+        messages.clear();
+
+        let Some(p) = connection.input_packet_buffer.pop_front() else {
+            break;
+        };
+        let handshake = !security.is_ready();
+        let bytes = match security.open_tcp(p.bytes) {
+            Ok(b) => b,
+            Err(e) => {
+                stats.decode_failures += 1;
+                println!("Dropped TCP message: {:?}", e);
+                continue;
+            }
+        };
+
+        if let Some(capture) = &mut capture {
+            capture.record(clock.local_tick, CaptureDirection::Inbound, CaptureChannel::Tcp, &bytes);
+        }
+
+        let decoded_message: (Vec<STcpType>, usize) = match bincode::serde::decode_from_slice(&bytes, config::standard()) {
+            Ok(m) => m,
+            Err(e) => {
+                if lifecycle.state == ConnectionState::Handshaking {
+                    // Anything but Welcome/Rejected before the handshake means the layouts disagree
+                    lifecycle.reject(
+                        "Server speaks an incompatible protocol".to_string(),
+                        time.elapsed(),
+                        &mut state_events,
+                    );
+                    connection.input_packet_buffer.clear();
+                    return;
                 }
+                stats.decode_failures += 1;
+                println!("Couldn't decode TCP message: {:?}", e);
+                continue;
             }
+        };
+
+        // Until the keys are in, the handshake is all that may come in the clear
+        let is_handshake = |m: &STcpType| {
+            matches!(m, STcpType::Welcome { .. } | STcpType::Rejected { .. } | STcpType::KeyExchange { .. })
+        };
+        if handshake && !decoded_message.0.iter().all(is_handshake) {
+            stats.decode_failures += 1;
+            println!("Dropped plaintext TCP message in secure mode");
+            continue;
         }
+
+        messages = decoded_message.0;
     }
 }
 
 pub fn add_ping_message(
     mut connection: ResMut<UdpConnection>
 ) {
-    let time_now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis() as u32;
+    let time_now = now_ms();
     let last_rtt = connection.ping;
     connection.add_message(NetworkMessage(Ping{ intitiation_time: time_now, last_rtt }))
 }
@@ -0,0 +1,272 @@
+use crate::network::net_manage::LinkEvent;
+use bevy::prelude::Resource;
+use std::collections::VecDeque;
+use std::io::{Error, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+
+/// Moves encoded payloads to and from the server. The reliable side is ordered and lossless,
+/// the unreliable side may drop, duplicate or reorder.
+pub trait Transport: Send + Sync {
+    /// Queues a payload, failing with [`ErrorKind::WouldBlock`] while the transport is backed up.
+    fn send_unreliable(&mut self, payload: Vec<u8>) -> Result<(), Error>;
+    fn send_reliable(&mut self, payload: Vec<u8>) -> Result<(), Error>;
+    fn receive_unreliable(&mut self) -> Option<Vec<u8>>;
+    fn receive_reliable(&mut self) -> Option<Vec<u8>>;
+    fn poll_event(&mut self) -> Option<LinkEvent>;
+    /// Writes a reliable payload before returning, for when there won't be another frame to flush it.
+    fn send_reliable_now(&mut self, payload: Vec<u8>) -> Result<(), Error>;
+}
+
+/// How long [`Transport::send_reliable_now`] waits for the payload to be written.
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// A payload for a writer task to write once the `after`th flush marker came through, and where
+/// to report when it did. The marker is an empty payload queued behind everything sent before the
+/// flush, so it reaches the writer through the link conditioner like they do.
+pub struct FlushRequest {
+    pub after: u64,
+    pub payload: Vec<u8>,
+    pub ack: std::sync::mpsc::Sender<Result<(), Error>>,
+}
+
+/// Hands `payload` to the writer task behind `flush` and blocks until it was written, for at
+/// most [`FLUSH_TIMEOUT`].
+pub fn flush_through(flush: &Sender<FlushRequest>, after: u64, payload: Vec<u8>) -> Result<(), Error> {
+    let (ack, written) = std::sync::mpsc::channel();
+    flush.try_send(FlushRequest { after, payload, ack }).map_err(queue_error)?;
+    match written.recv_timeout(FLUSH_TIMEOUT) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(Error::new(ErrorKind::TimedOut, "payload wasn't written in time")),
+        Err(RecvTimeoutError::Disconnected) => Err(Error::new(ErrorKind::NotConnected, "writer is gone")),
+    }
+}
+
+/// Maps a failed `try_send` onto the errors [`Transport`] reports.
+pub fn queue_error<T>(e: TrySendError<T>) -> Error {
+    match e {
+        TrySendError::Full(_) => Error::new(ErrorKind::WouldBlock, "transport is backed up"),
+        TrySendError::Closed(_) => Error::new(ErrorKind::NotConnected, "transport is closed"),
+    }
+}
+
+/// UDP datagrams and a framed TCP stream, fed by the socket tasks in `net_manage`.
+pub struct SocketTransport {
+    udp_tx: Sender<(Vec<u8>, SocketAddr)>,
+    udp_rx: Receiver<(Vec<u8>, SocketAddr)>,
+    tcp_tx: Sender<(Vec<u8>, Arc<TcpStream>)>,
+    tcp_rx: Receiver<(Vec<u8>, Arc<TcpStream>)>,
+    tcp_flush_tx: Sender<FlushRequest>,
+    /// Flush markers queued so far.
+    tcp_flushes: u64,
+    status_rx: Receiver<LinkEvent>,
+    /// Learned from the empty payload the socket tasks send once they are up.
+    remote_socket: Option<SocketAddr>,
+    stream: Option<Arc<TcpStream>>,
+    events: VecDeque<LinkEvent>,
+}
+
+impl SocketTransport {
+    pub fn new(
+        udp_tx: Sender<(Vec<u8>, SocketAddr)>,
+        udp_rx: Receiver<(Vec<u8>, SocketAddr)>,
+        tcp_tx: Sender<(Vec<u8>, Arc<TcpStream>)>,
+        tcp_rx: Receiver<(Vec<u8>, Arc<TcpStream>)>,
+        tcp_flush_tx: Sender<FlushRequest>,
+        status_rx: Receiver<LinkEvent>,
+    ) -> Self {
+        Self {
+            udp_tx,
+            udp_rx,
+            tcp_tx,
+            tcp_rx,
+            tcp_flush_tx,
+            tcp_flushes: 0,
+            status_rx,
+            remote_socket: None,
+            stream: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    fn set_remote_socket(&mut self, addr: SocketAddr) {
+        self.remote_socket = Some(addr);
+        self.events.push_back(LinkEvent::UnreliableReady);
+    }
+
+    fn set_stream(&mut self, stream: Arc<TcpStream>) {
+        self.stream = Some(stream);
+        self.events.push_back(LinkEvent::ReliableReady);
+    }
+}
+
+impl Transport for SocketTransport {
+    fn send_unreliable(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        let Some(remote_socket) = self.remote_socket else {
+            return Err(Error::new(ErrorKind::NotConnected, "UDP socket isn't bound yet"));
+        };
+        self.udp_tx.try_send((payload, remote_socket)).map_err(queue_error)
+    }
+
+    fn send_reliable(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        let Some(stream) = &self.stream else {
+            return Err(Error::new(ErrorKind::NotConnected, "TCP stream isn't connected yet"));
+        };
+        self.tcp_tx.try_send((payload, stream.clone())).map_err(queue_error)
+    }
+
+    fn receive_unreliable(&mut self) -> Option<Vec<u8>> {
+        while let Ok((bytes, addr)) = self.udp_rx.try_recv() {
+            match self.remote_socket {
+                Some(remote) if remote == addr => return Some(bytes),
+                None => self.set_remote_socket(addr),
+                // Someone other than the server
+                Some(_) => {}
+            }
+        }
+        None
+    }
+
+    fn receive_reliable(&mut self) -> Option<Vec<u8>> {
+        while let Ok((bytes, stream)) = self.tcp_rx.try_recv() {
+            match self.stream {
+                Some(_) => return Some(bytes),
+                None => self.set_stream(stream),
+            }
+        }
+        None
+    }
+
+    fn poll_event(&mut self) -> Option<LinkEvent> {
+        // The link-up payloads are always the first thing on their channel
+        if self.remote_socket.is_none() && let Ok((_, addr)) = self.udp_rx.try_recv() {
+            self.set_remote_socket(addr);
+        }
+        if self.stream.is_none() && let Ok((_, stream)) = self.tcp_rx.try_recv() {
+            self.set_stream(stream);
+        }
+        while let Ok(event) = self.status_rx.try_recv() {
+            self.events.push_back(event);
+        }
+        self.events.pop_front()
+    }
+
+    /// Goes through the writer task, so it can't cut into a frame that is halfway out or get
+    /// ahead of one still on its way.
+    fn send_reliable_now(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        let Some(stream) = &self.stream else {
+            return Err(Error::new(ErrorKind::NotConnected, "TCP stream isn't connected"));
+        };
+        self.tcp_tx.try_send((vec![], stream.clone())).map_err(queue_error)?;
+        self.tcp_flushes += 1;
+        flush_through(&self.tcp_flush_tx, self.tcp_flushes, payload)
+    }
+}
+
+/// In-memory transport, one end for the client and one for whatever plays the server.
+/// Nothing is ever lost or reordered, and both ends are up from the start.
+pub struct LoopbackTransport {
+    reliable_tx: UnboundedSender<Vec<u8>>,
+    reliable_rx: UnboundedReceiver<Vec<u8>>,
+    unreliable_tx: UnboundedSender<Vec<u8>>,
+    unreliable_rx: UnboundedReceiver<Vec<u8>>,
+    events: VecDeque<LinkEvent>,
+    closed: bool,
+}
+
+impl LoopbackTransport {
+    pub fn pair() -> (Self, Self) {
+        let (a_reliable_tx, b_reliable_rx) = mpsc::unbounded_channel();
+        let (b_reliable_tx, a_reliable_rx) = mpsc::unbounded_channel();
+        let (a_unreliable_tx, b_unreliable_rx) = mpsc::unbounded_channel();
+        let (b_unreliable_tx, a_unreliable_rx) = mpsc::unbounded_channel();
+
+        let end = |reliable_tx, reliable_rx, unreliable_tx, unreliable_rx| Self {
+            reliable_tx,
+            reliable_rx,
+            unreliable_tx,
+            unreliable_rx,
+            events: VecDeque::from([LinkEvent::ReliableReady, LinkEvent::UnreliableReady]),
+            closed: false,
+        };
+
+        (
+            end(a_reliable_tx, a_reliable_rx, a_unreliable_tx, a_unreliable_rx),
+            end(b_reliable_tx, b_reliable_rx, b_unreliable_tx, b_unreliable_rx),
+        )
+    }
+}
+
+impl LoopbackTransport {
+    /// An end whose other side is already gone, standing in for a link that was torn down.
+    pub fn closed() -> Self {
+        let (mut end, _) = Self::pair();
+        end.events.clear();
+        end.closed = true;
+        end
+    }
+}
+
+/// Server address that connects through a [`LoopbackListener`] instead of the network.
+pub const LOOPBACK_ADDRESS: &str = "loopback";
+
+/// Hands the server end of every new loopback connection to whatever runs in-process as the server.
+#[derive(Resource, Clone)]
+pub struct LoopbackListener(UnboundedSender<LoopbackTransport>);
+
+impl LoopbackListener {
+    pub fn new() -> (Self, UnboundedReceiver<LoopbackTransport>) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        (Self(tx), rx)
+    }
+
+    /// Returns the client end, which reports a failed connect if the server is gone.
+    pub fn connect(&self) -> LoopbackTransport {
+        let (mut client, server) = LoopbackTransport::pair();
+        if self.0.send(server).is_err() {
+            client.events = VecDeque::from([LinkEvent::ConnectFailed("in-process server isn't running".to_string())]);
+            client.closed = true;
+        }
+        client
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn send_unreliable(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        self.unreliable_tx
+            .send(payload)
+            .map_err(|_| Error::new(ErrorKind::NotConnected, "other end was dropped"))
+    }
+
+    fn send_reliable(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        self.reliable_tx
+            .send(payload)
+            .map_err(|_| Error::new(ErrorKind::NotConnected, "other end was dropped"))
+    }
+
+    fn receive_unreliable(&mut self) -> Option<Vec<u8>> {
+        self.unreliable_rx.try_recv().ok()
+    }
+
+    fn receive_reliable(&mut self) -> Option<Vec<u8>> {
+        self.reliable_rx.try_recv().ok()
+    }
+
+    fn poll_event(&mut self) -> Option<LinkEvent> {
+        // Only once everything the other end sent before going away was read
+        if !self.closed && self.reliable_rx.is_closed() && self.reliable_rx.is_empty() {
+            self.closed = true;
+            self.events.push_back(LinkEvent::ReliableClosed);
+        }
+        self.events.pop_front()
+    }
+
+    fn send_reliable_now(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        self.send_reliable(payload)
+    }
+}
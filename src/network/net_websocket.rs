@@ -0,0 +1,200 @@
+use crate::network::net_conditioner::{run_conditioner, LinkChannel, LinkConditioner};
+use crate::network::net_manage::LinkEvent;
+use crate::network::net_transport::{flush_through, queue_error, FlushRequest, Transport};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use futures_util::{SinkExt, StreamExt};
+use std::io::{Error, ErrorKind};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::watch;
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+
+/// First byte of every WebSocket message, telling which side of the transport it belongs to.
+pub const RELIABLE_TAG: u8 = 0;
+pub const UNRELIABLE_TAG: u8 = 1;
+
+/// `wss://` needs TLS, which this build doesn't include. A TLS-terminating proxy in front of
+/// the server can be reached over `ws://` instead.
+pub fn check_websocket_url(url: &str) -> Result<(), Error> {
+    if url.starts_with("wss://") {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            format!("{} needs TLS, which this build doesn't support, use ws:// behind a TLS proxy", url),
+        ));
+    }
+    Ok(())
+}
+
+/// Both sides of the transport share one WebSocket, for deployments where only HTTP gets through.
+/// Unreliable payloads are therefore delivered reliably too, just without the guarantee.
+pub struct WebSocketTransport {
+    reliable_tx: Sender<Vec<u8>>,
+    flush_tx: Sender<FlushRequest>,
+    /// Flush markers queued so far, see [`FlushRequest`].
+    reliable_flushes: u64,
+    reliable_rx: Receiver<Vec<u8>>,
+    unreliable_tx: Sender<Vec<u8>>,
+    unreliable_rx: Receiver<Vec<u8>>,
+    status_rx: Receiver<LinkEvent>,
+}
+
+impl WebSocketTransport {
+    /// Spawns the tasks that connect to `url` and shuttle messages, dropping the transport stops them.
+    pub fn connect(
+        url: String,
+        runtime: &TokioTasksRuntime,
+        conditioner: watch::Receiver<LinkConditioner>,
+    ) -> Self {
+        let (reliable_tx, reliable_send_rx) = mpsc::channel::<Vec<u8>>(1_000);
+        let (unreliable_tx, unreliable_send_rx) = mpsc::channel::<Vec<u8>>(1_000);
+        let (reliable_receive_tx, reliable_rx) = mpsc::channel::<Vec<u8>>(1_000);
+        let (unreliable_receive_tx, unreliable_rx) = mpsc::channel::<Vec<u8>>(1_000);
+        let (flush_tx, mut flush_rx) = mpsc::channel::<FlushRequest>(1);
+        let (status_tx, status_rx) = mpsc::channel::<LinkEvent>(16);
+
+        // Socket side of the link conditioner hops
+        let (socket_send_tx, mut socket_send_rx) = mpsc::channel::<Vec<u8>>(1_000);
+        let (reliable_socket_tx, reliable_socket_rx) = mpsc::channel::<Vec<u8>>(1_000);
+        let (unreliable_socket_tx, unreliable_socket_rx) = mpsc::channel::<Vec<u8>>(1_000);
+
+        runtime.spawn_background_task(|_| async move {
+            println!("starting communication");
+            println!("remote address: {}", url);
+
+            tokio::spawn(run_conditioner(reliable_send_rx, socket_send_tx.clone(), conditioner.clone(), LinkChannel::Tcp, |m| m.is_empty()));
+            tokio::spawn(run_conditioner(unreliable_send_rx, socket_send_tx, conditioner.clone(), LinkChannel::Udp, |_| false));
+            tokio::spawn(run_conditioner(reliable_socket_rx, reliable_receive_tx, conditioner.clone(), LinkChannel::Tcp, |_| false));
+            tokio::spawn(run_conditioner(unreliable_socket_rx, unreliable_receive_tx, conditioner, LinkChannel::Udp, |_| false));
+
+            if let Err(e) = check_websocket_url(&url) {
+                let _ = status_tx.send(LinkEvent::ConnectFailed(e.to_string())).await;
+                return;
+            }
+            let (mut sink, mut stream) = match connect_async(url.as_str()).await {
+                Ok((socket, _)) => socket.split(),
+                Err(e) => {
+                    let _ = status_tx.send(LinkEvent::ConnectFailed(format!("couldn't connect to {}: {}", url, e))).await;
+                    return;
+                }
+            };
+            println!("Connected to server via WebSocket: {}", url);
+            let _ = status_tx.send(LinkEvent::ReliableReady).await;
+            let _ = status_tx.send(LinkEvent::UnreliableReady).await;
+
+            // Task responsible for sending queued messages
+            tokio::spawn(async move {
+                let mut markers = 0u64;
+                let mut waiting: Option<FlushRequest> = None;
+                loop {
+                    // A flush goes out right behind what was queued before it, however long that took to get here
+                    if let Some(request) = waiting.take_if(|r| r.after <= markers) {
+                        let sent = sink.send(Message::binary(request.payload)).await.map_err(Error::other);
+                        let failed = sent.is_err();
+                        let _ = request.ack.send(sent);
+                        if failed {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    tokio::select! {
+                        queued = socket_send_rx.recv() => {
+                            let Some(bytes) = queued else {
+                                break;
+                            };
+                            if bytes.is_empty() {
+                                markers += 1;
+                                continue;
+                            }
+                            if let Err(e) = sink.send(Message::binary(bytes)).await {
+                                println!("Couldn't write: {:?}", e);
+                                break;
+                            }
+                        }
+                        Some(request) = flush_rx.recv(), if waiting.is_none() => waiting = Some(request),
+                    }
+                }
+                let _ = sink.close().await;
+            });
+
+            loop {
+                let received = tokio::select! {
+                    // The client dropped this connection attempt, the conditioner in between closes with it
+                    _ = reliable_socket_tx.closed() => break,
+                    received = stream.next() => received,
+                };
+
+                let bytes = match received {
+                    Some(Ok(Message::Binary(bytes))) => bytes,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        println!("Couldn't read: {:?}", e);
+                        break;
+                    }
+                };
+
+                let inbound = match bytes.first() {
+                    Some(&RELIABLE_TAG) => &reliable_socket_tx,
+                    Some(&UNRELIABLE_TAG) => &unreliable_socket_tx,
+                    _ => {
+                        println!("Dropping WebSocket message without a known tag");
+                        continue;
+                    }
+                };
+                if inbound.send(bytes[1..].to_vec()).await.is_err() {
+                    break;
+                }
+            }
+
+            println!("WebSocket connection to server closed");
+            let _ = status_tx.send(LinkEvent::ReliableClosed).await;
+        });
+
+        Self {
+            reliable_tx,
+            flush_tx,
+            reliable_flushes: 0,
+            reliable_rx,
+            unreliable_tx,
+            unreliable_rx,
+            status_rx,
+        }
+    }
+}
+
+fn tagged(tag: u8, payload: Vec<u8>) -> Vec<u8> {
+    let mut message = Vec::with_capacity(payload.len() + 1);
+    message.push(tag);
+    message.extend_from_slice(&payload);
+    message
+}
+
+impl Transport for WebSocketTransport {
+    fn send_unreliable(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        self.unreliable_tx.try_send(tagged(UNRELIABLE_TAG, payload)).map_err(queue_error)
+    }
+
+    fn send_reliable(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        self.reliable_tx.try_send(tagged(RELIABLE_TAG, payload)).map_err(queue_error)
+    }
+
+    fn receive_unreliable(&mut self) -> Option<Vec<u8>> {
+        self.unreliable_rx.try_recv().ok()
+    }
+
+    fn receive_reliable(&mut self) -> Option<Vec<u8>> {
+        self.reliable_rx.try_recv().ok()
+    }
+
+    fn poll_event(&mut self) -> Option<LinkEvent> {
+        self.status_rx.try_recv().ok()
+    }
+
+    /// The socket is owned by the writer task, so this waits for it to send the payload.
+    fn send_reliable_now(&mut self, payload: Vec<u8>) -> Result<(), Error> {
+        self.reliable_tx.try_send(vec![]).map_err(queue_error)?;
+        self.reliable_flushes += 1;
+        flush_through(&self.flush_tx, self.reliable_flushes, tagged(RELIABLE_TAG, payload))
+    }
+}
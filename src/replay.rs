@@ -0,0 +1,219 @@
+use crate::components::lobby::reset_player_on_disconnect;
+use crate::components::player::{Player, PlayerInfo, PredictionCheck};
+use crate::headless::spawn_ground;
+use crate::network::net_capture::{read_capture, CaptureChannel, CaptureDirection, CaptureRecord};
+use crate::network::net_channel::UdpPacket;
+use crate::network::net_clock::{advance_clock_tick, ClockSync};
+use crate::network::net_manage::{Packet, TcpConnection, UdpConnection};
+use crate::network::net_message::{CTcpType, CUdpType, STcpType, SUdpType, SequenceNumber};
+use crate::network::net_reconciliation::{ReconcileBuffer, TickRecord, BUFFER_SIZE};
+use crate::network::net_tasks::{handle_tcp_message, handle_udp_message};
+use crate::network::NetworkStatePlugin;
+use avian3d::PhysicsPlugins;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::scene::ScenePlugin;
+use bevy::time::TimeUpdateStrategy;
+use bincode::config;
+use serde::de::DeserializeOwned;
+use std::fmt::Debug;
+use std::io::Error;
+use std::path::Path;
+use std::time::Duration;
+
+/// A capture being fed back to the client, and how far along it is.
+#[derive(Resource)]
+pub struct CaptureReplay {
+    records: Vec<CaptureRecord>,
+    next: usize,
+    /// Waits for Enter before every tick that has records, printing what they contain.
+    pub step: bool,
+}
+
+impl CaptureReplay {
+    pub fn new(records: Vec<CaptureRecord>, step: bool) -> Self {
+        Self { records, next: 0, step }
+    }
+
+    pub fn open(path: impl AsRef<Path>, step: bool) -> Result<Self, Error> {
+        Ok(Self::new(read_capture(&std::fs::read(path)?)?, step))
+    }
+
+    /// Tick the first record was captured on.
+    pub fn first_tick(&self) -> Option<u32> {
+        self.records.first().map(|r| r.tick)
+    }
+
+    /// Records captured up to and including `tick` that weren't fed yet, in captured order.
+    pub fn due(&mut self, tick: u32) -> &[CaptureRecord] {
+        let start = self.next;
+        while self.next < self.records.len() && self.records[self.next].tick <= tick {
+            self.next += 1;
+        }
+        &self.records[start..self.next]
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next == self.records.len()
+    }
+}
+
+/// Builds an app that runs a capture through `handle_udp_message` and `handle_tcp_message`,
+/// with the client's own inputs and predictions put back into the `ReconcileBuffer`. Every
+/// update is exactly one fixed tick, so records land on the tick they were captured on however
+/// fast the replay runs.
+pub fn replay_app(replay: CaptureReplay) -> App {
+    let mut app = App::new();
+    app.add_plugins((
+        MinimalPlugins,
+        TransformPlugin,
+        AssetPlugin::default(),
+        MeshPlugin,
+        ScenePlugin,
+        PhysicsPlugins::default().with_length_unit(10.0),
+        NetworkStatePlugin,
+    ));
+    app.insert_resource(Time::<Fixed>::from_hz(60.0));
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / 60.0)));
+    app.init_resource::<PlayerInfo>();
+    app.init_resource::<PredictionCheck>();
+    app.insert_resource(replay);
+    app.add_systems(Startup, (spawn_ground, align_clock));
+    app.add_systems(FixedFirst, advance_clock_tick);
+    app.add_systems(FixedPreUpdate, feed_capture);
+    app.add_systems(FixedPostUpdate, (handle_udp_message, handle_tcp_message).chain());
+    app.add_systems(Update, (reset_player_on_disconnect, finish_replay));
+    app
+}
+
+/// Starts the local tick just before the capture's, so replayed ticks match the captured ones.
+fn align_clock(replay: Res<CaptureReplay>, mut clock: ResMut<ClockSync>) {
+    if let Some(tick) = replay.first_tick() {
+        clock.local_tick = tick.wrapping_sub(1);
+    }
+}
+
+fn feed_capture(
+    mut replay: ResMut<CaptureReplay>,
+    clock: Res<ClockSync>,
+    mut udp_connection: ResMut<UdpConnection>,
+    mut tcp_connection: ResMut<TcpConnection>,
+    mut reconcile_buffer: ResMut<ReconcileBuffer>,
+) {
+    let step = replay.step;
+    let due = replay.due(clock.local_tick);
+    if step && !due.is_empty() {
+        for record in due {
+            println!("{}", describe(record));
+        }
+        println!("-- tick {}, Enter to continue", clock.local_tick);
+        let _ = std::io::stdin().read_line(&mut String::new());
+    }
+
+    let mut prediction = None;
+    for record in due {
+        match (record.direction, record.channel) {
+            (CaptureDirection::Inbound, CaptureChannel::Udp) => {
+                udp_connection.input_packet_buffer.push_back(Packet { bytes: record.payload.clone() });
+            }
+            (CaptureDirection::Inbound, CaptureChannel::Tcp) => {
+                if let Some(bytes) = without_key_exchange(&record.payload) {
+                    tcp_connection.input_packet_buffer.push_back(Packet { bytes });
+                }
+            }
+            (CaptureDirection::Outbound, CaptureChannel::Prediction) => {
+                prediction = decode::<Player>(&record.payload).ok();
+            }
+            (CaptureDirection::Outbound, CaptureChannel::Udp) => {
+                replay_sent_tick(&mut reconcile_buffer, &record.payload, prediction.take());
+            }
+            // What the client sent over TCP is only there to read along
+            _ => {}
+        }
+    }
+}
+
+/// Payloads were captured decrypted, so the replay runs in the clear and leaves out the key
+/// exchange that set the keys up. `None` when nothing else is left.
+fn without_key_exchange(payload: &[u8]) -> Option<Vec<u8>> {
+    let Ok(messages) = decode::<Vec<STcpType>>(payload) else {
+        // Left for `handle_tcp_message` to complain about
+        return Some(payload.to_vec());
+    };
+    let count = messages.len();
+    let messages: Vec<STcpType> = messages
+        .into_iter()
+        .filter(|m| !matches!(m, STcpType::KeyExchange { .. }))
+        .collect();
+    if messages.len() == count {
+        return Some(payload.to_vec());
+    }
+    if messages.is_empty() {
+        return None;
+    }
+    bincode::serde::encode_to_vec(messages, config::standard()).ok()
+}
+
+/// Records a sent packet's inputs with the prediction captured next to it, and counts the tick
+/// as sent, like `player_controller` and `udp_client_net_send` did.
+fn replay_sent_tick(reconcile_buffer: &mut ReconcileBuffer, payload: &[u8], player: Option<Player>) {
+    let Ok(packet) = decode::<UdpPacket<CUdpType>>(payload) else {
+        return;
+    };
+
+    let mut sequence: Option<SequenceNumber> = None;
+    let mut input = None;
+    let reliable = packet.reliable.iter().map(|(_, m)| m);
+    for message in packet.unreliable.iter().chain(&packet.sequenced).chain(reliable) {
+        match message {
+            CUdpType::Sequence { sequence_number } => sequence = Some(*sequence_number),
+            CUdpType::Input { keymask, mouse_delta, .. } => input = Some((*keymask, *mouse_delta)),
+            _ => {}
+        }
+    }
+    let Some(sequence) = sequence else {
+        return;
+    };
+
+    // A capture started mid-session, so the counter catches up to the client's first
+    for _ in 0..BUFFER_SIZE {
+        if reconcile_buffer.sequence_counter == sequence % BUFFER_SIZE {
+            break;
+        }
+        reconcile_buffer.increment_sequence_num();
+    }
+    if let (Some((keymask, mouse_delta)), Some(player)) = (input, player) {
+        reconcile_buffer.record(sequence, TickRecord { keymask, mouse_delta, player, confirmed: false });
+    }
+    reconcile_buffer.increment_sequence_num();
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, bincode::error::DecodeError> {
+    bincode::serde::decode_from_slice(bytes, config::standard()).map(|(value, _)| value)
+}
+
+fn finish_replay(replay: Res<CaptureReplay>, mut exit: EventWriter<AppExit>) {
+    if replay.is_finished() {
+        println!("Replayed {} records", replay.records.len());
+        exit.write(AppExit::Success);
+    }
+}
+
+/// One line per record with the messages decoded.
+pub fn describe(record: &CaptureRecord) -> String {
+    fn decoded<T: DeserializeOwned + Debug>(bytes: &[u8]) -> String {
+        match bincode::serde::decode_from_slice::<T, _>(bytes, config::standard()) {
+            Ok((messages, _)) => format!("{:?}", messages),
+            Err(e) => format!("undecodable ({})", e),
+        }
+    }
+
+    let messages = match (record.direction, record.channel) {
+        (CaptureDirection::Inbound, CaptureChannel::Udp) => decoded::<UdpPacket<SUdpType>>(&record.payload),
+        (CaptureDirection::Inbound, CaptureChannel::Tcp) => decoded::<Vec<STcpType>>(&record.payload),
+        (CaptureDirection::Outbound, CaptureChannel::Udp) => decoded::<UdpPacket<CUdpType>>(&record.payload),
+        (CaptureDirection::Outbound, CaptureChannel::Tcp) => decoded::<Vec<CTcpType>>(&record.payload),
+        (_, CaptureChannel::Prediction) => decoded::<Player>(&record.payload),
+    };
+    format!("[{} @ {}ms] {:?} {:?}: {}", record.tick, record.time_ms, record.direction, record.channel, messages)
+}
@@ -0,0 +1,31 @@
+use crate::network::net_address::{resolve_server_address, split_host_port, unspecified_for, DEFAULT_PORT};
+
+#[test]
+fn host_and_port_forms() {
+    assert_eq!(split_host_port("127.0.0.1").unwrap(), ("127.0.0.1".to_string(), DEFAULT_PORT));
+    assert_eq!(split_host_port("127.0.0.1:5000").unwrap(), ("127.0.0.1".to_string(), 5000));
+    assert_eq!(split_host_port("game.example.com").unwrap(), ("game.example.com".to_string(), DEFAULT_PORT));
+    assert_eq!(split_host_port(" game.example.com:7000 ").unwrap(), ("game.example.com".to_string(), 7000));
+    assert_eq!(split_host_port("::1").unwrap(), ("::1".to_string(), DEFAULT_PORT));
+    assert_eq!(split_host_port("[::1]").unwrap(), ("::1".to_string(), DEFAULT_PORT));
+    assert_eq!(split_host_port("[fe80::1]:9000").unwrap(), ("fe80::1".to_string(), 9000));
+}
+
+#[test]
+fn malformed_addresses_are_rejected() {
+    assert!(split_host_port("").is_err());
+    assert!(split_host_port("host:").is_err());
+    assert!(split_host_port("host:70000").is_err());
+    assert!(split_host_port(":4444").is_err());
+    assert!(split_host_port("[nonsense]").is_err());
+}
+
+#[tokio::test]
+async fn localhost_resolves() {
+    let addrs = resolve_server_address("localhost:1234").await.unwrap();
+    assert!(addrs.iter().all(|a| a.port() == 1234 && a.ip().is_loopback()));
+
+    let v6 = resolve_server_address("[::1]:80").await.unwrap();
+    assert!(v6[0].is_ipv6());
+    assert!(unspecified_for(&v6[0]).is_ipv6());
+}
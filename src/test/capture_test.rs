@@ -0,0 +1,134 @@
+use crate::components::common::{Id, Vec3};
+use crate::components::player::{Player, PlayerInfo};
+use crate::network::net_channel::{PacketHeader, UdpPacket};
+use crate::network::net_capture::{read_capture, CaptureChannel, CaptureDirection, CaptureRecord, PacketCapture};
+use crate::network::net_connection::{ConnectionLifecycle, ConnectionState};
+use crate::network::net_message::{CUdpType, InputHistory, STcpType, PROTOCOL_VERSION};
+use crate::network::net_reconciliation::{ReconcileBuffer, TickRecord};
+use crate::replay::{replay_app, CaptureReplay};
+use bevy::prelude::Vec2;
+use bincode::config;
+
+fn inbound_tcp(tick: u32, messages: Vec<STcpType>) -> CaptureRecord {
+    CaptureRecord {
+        time_ms: tick as u64 * 16,
+        tick,
+        direction: CaptureDirection::Inbound,
+        channel: CaptureChannel::Tcp,
+        payload: bincode::serde::encode_to_vec(messages, config::standard()).unwrap(),
+    }
+}
+
+fn outbound(tick: u32, channel: CaptureChannel, payload: Vec<u8>) -> CaptureRecord {
+    CaptureRecord {
+        time_ms: tick as u64 * 16,
+        tick,
+        direction: CaptureDirection::Outbound,
+        channel,
+        payload,
+    }
+}
+
+#[test]
+fn capture_file_round_trips_and_survives_a_torn_write() {
+    let path = std::env::temp_dir().join(format!("capture_test_{}.bin", std::process::id()));
+    let mut capture = PacketCapture::create(&path).unwrap();
+    capture.record(3, CaptureDirection::Outbound, CaptureChannel::Udp, &[1, 2, 3]);
+    capture.record(4, CaptureDirection::Inbound, CaptureChannel::Tcp, &[4, 5]);
+    capture.flush();
+
+    let mut bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let records = read_capture(&bytes).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!((records[0].tick, records[0].direction, records[0].channel), (3, CaptureDirection::Outbound, CaptureChannel::Udp));
+    assert_eq!(records[0].payload, vec![1, 2, 3]);
+    assert_eq!((records[1].tick, records[1].direction, records[1].channel), (4, CaptureDirection::Inbound, CaptureChannel::Tcp));
+    assert_eq!(records[1].payload, vec![4, 5]);
+
+    // Half a record at the end, as a crash mid-write would leave it
+    bytes.extend_from_slice(&[0, 0, 0, 9, 1]);
+    assert_eq!(read_capture(&bytes).unwrap().len(), 2);
+
+    assert!(read_capture(&bytes[1..]).is_err());
+}
+
+#[test]
+fn capture_keeps_payloads_larger_than_a_network_frame() {
+    let path = std::env::temp_dir().join(format!("capture_large_test_{}.bin", std::process::id()));
+    let large: Vec<u8> = (0..255 * 1200).map(|i| i as u8).collect();
+    let mut capture = PacketCapture::create(&path).unwrap();
+    capture.record(1, CaptureDirection::Inbound, CaptureChannel::Udp, &large);
+    capture.record(2, CaptureDirection::Inbound, CaptureChannel::Tcp, &[6]);
+    capture.flush();
+    assert_eq!(capture.records, 2);
+
+    let bytes = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let records = read_capture(&bytes).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].payload, large);
+    assert_eq!(records[1].payload, vec![6]);
+}
+
+#[test]
+fn replay_feeds_records_on_their_tick() {
+    let records = vec![
+        inbound_tcp(40, vec![STcpType::Welcome { protocol_version: PROTOCOL_VERSION }]),
+        inbound_tcp(42, vec![STcpType::PlayerId { player_uid: Id(7) }]),
+    ];
+    let mut app = replay_app(CaptureReplay::new(records, false));
+    app.finish();
+    app.cleanup();
+
+    // The first update only starts the clock, every one after it runs a single tick
+    app.update();
+    app.update();
+    assert_eq!(app.world().resource::<ConnectionLifecycle>().state, ConnectionState::Connected);
+    assert_eq!(app.world().resource::<PlayerInfo>().current_player_id, Id(0));
+
+    app.update();
+    app.update();
+    assert_eq!(app.world().resource::<ConnectionLifecycle>().state, ConnectionState::Joined);
+    assert_eq!(app.world().resource::<PlayerInfo>().current_player_id, Id(7));
+    assert!(app.world().resource::<CaptureReplay>().is_finished());
+}
+
+#[test]
+fn replay_skips_the_key_exchange_and_rebuilds_the_reconcile_buffer() {
+    let player = Player { position: Vec3::new(1.0, 2.0, 3.0), ..Default::default() };
+    let sent = UdpPacket {
+        header: PacketHeader { sequence: 0, ack: None, ack_bits: 0 },
+        unreliable: vec![],
+        sequenced: vec![
+            CUdpType::Sequence { sequence_number: 5 },
+            CUdpType::Input { keymask: 0b101, mouse_delta: Vec2::new(0.5, -0.25), history: InputHistory::new() },
+        ],
+        reliable: vec![],
+    };
+    let records = vec![
+        inbound_tcp(40, vec![
+            STcpType::Welcome { protocol_version: PROTOCOL_VERSION },
+            STcpType::KeyExchange { public_key: [1; 32], proof: [2; 32] },
+        ]),
+        outbound(41, CaptureChannel::Prediction, bincode::serde::encode_to_vec(player, config::standard()).unwrap()),
+        outbound(41, CaptureChannel::Udp, bincode::serde::encode_to_vec(&sent, config::standard()).unwrap()),
+    ];
+    let mut app = replay_app(CaptureReplay::new(records, false));
+    app.finish();
+    app.cleanup();
+
+    app.update();
+    app.update();
+    assert_eq!(app.world().resource::<ConnectionLifecycle>().state, ConnectionState::Connected);
+
+    app.update();
+    let buffer = app.world().resource::<ReconcileBuffer>();
+    assert_eq!(buffer.sequence_counter, 6);
+    assert_eq!(buffer.get(5), Some(&TickRecord {
+        keymask: 0b101,
+        mouse_delta: Vec2::new(0.5, -0.25),
+        player,
+        confirmed: false,
+    }));
+}
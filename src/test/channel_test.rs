@@ -0,0 +1,69 @@
+use crate::network::net_channel::{sequence_greater_than, UdpChannel, INITIAL_RTT};
+use std::time::Duration;
+
+#[test]
+fn sequence_comparison_wraps() {
+    assert!(sequence_greater_than(1, 0));
+    assert!(sequence_greater_than(0, u16::MAX));
+    assert!(!sequence_greater_than(u16::MAX, 0));
+    assert!(!sequence_greater_than(5, 5));
+}
+
+#[test]
+fn reliable_messages_survive_loss_and_reordering() {
+    let mut client: UdpChannel<u32, u32> = UdpChannel::new();
+    let mut server: UdpChannel<u32, u32> = UdpChannel::new();
+    let resend = client.resend_timeout();
+
+    client.queue_reliable(1);
+    let lost = client.build_packet(vec![], vec![], Duration::ZERO);
+    client.queue_reliable(2);
+    let second = client.build_packet(vec![], vec![], Duration::ZERO);
+    let third = client.build_packet(vec![], vec![], resend);
+    drop(lost);
+
+    // The third packet overtakes the second one, by then both messages were due again
+    assert_eq!(server.receive_packet(third, resend), Some(vec![1, 2]));
+    assert_eq!(server.receive_packet(second, resend), Some(vec![]));
+
+    // Once the server acks, the client stops resending
+    let ack = server.build_packet(vec![], vec![], resend);
+    assert!(client.receive_packet(ack, resend).is_some());
+    assert!(!client.has_reliable_due(resend * 10));
+}
+
+#[test]
+fn unacked_messages_wait_for_the_resend_timeout() {
+    let mut client: UdpChannel<u32, u32> = UdpChannel::new();
+    let mut server: UdpChannel<u32, u32> = UdpChannel::new();
+
+    client.queue_reliable(1);
+    assert_eq!(client.build_packet(vec![], vec![], Duration::ZERO).reliable, vec![(0, 1)]);
+    let resend = client.resend_timeout();
+    assert!(!client.has_reliable_due(resend - Duration::from_millis(1)));
+    assert!(client.build_packet(vec![], vec![], resend - Duration::from_millis(1)).reliable.is_empty());
+    assert_eq!(client.build_packet(vec![], vec![], resend).reliable, vec![(0, 1)]);
+
+    // A quick ack brings the round trip, and with it the timeout, down
+    let sent_at = Duration::from_secs(1);
+    client.queue_reliable(2);
+    let packet = client.build_packet(vec![], vec![], sent_at);
+    server.receive_packet(packet, sent_at);
+    client.receive_packet(server.build_packet(vec![], vec![], sent_at), sent_at + Duration::from_millis(20));
+    assert!(client.rtt < INITIAL_RTT);
+    assert!(client.resend_timeout() < resend);
+    assert!(!client.has_reliable_due(sent_at + resend));
+}
+
+#[test]
+fn duplicates_and_stale_sequenced_messages_are_dropped() {
+    let mut client: UdpChannel<u32, u32> = UdpChannel::new();
+    let mut server: UdpChannel<u32, u32> = UdpChannel::new();
+
+    let older = client.build_packet(vec![10], vec![100], Duration::ZERO);
+    let newer = client.build_packet(vec![11], vec![101], Duration::ZERO);
+
+    assert_eq!(server.receive_packet(newer.clone(), Duration::ZERO), Some(vec![11, 101]));
+    assert_eq!(server.receive_packet(newer, Duration::ZERO), None);
+    assert_eq!(server.receive_packet(older, Duration::ZERO), Some(vec![10]));
+}
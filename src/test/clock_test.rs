@@ -0,0 +1,50 @@
+use crate::network::net_clock::ClockSync;
+
+#[test]
+fn offset_and_ticks_follow_the_server_clock() {
+    let mut clock = ClockSync::new(60.0);
+    assert!(!clock.is_synchronized());
+
+    // Server runs 5 seconds ahead, 20ms each way, server tick 600 at server time 10_020
+    let offset = 5_000u32;
+    for i in 0..10u32 {
+        let sent = 5_000 + i * 100;
+        let server_received = sent + 20 + offset;
+        let server_tick = 600 + i * 6;
+        clock.record(sent, server_received, server_tick, sent + 40);
+    }
+
+    assert!(clock.is_synchronized());
+    assert!((clock.offset_ms - offset as f64).abs() < 1.0);
+    assert!((clock.smoothed_rtt_ms - 40.0).abs() < 1.0);
+    assert!(clock.rtt_variance_ms < 20.0);
+
+    // One second after the first exchange's midpoint the server is 60 ticks further
+    let tick = clock.server_tick_at(6_020).unwrap();
+    assert!((tick - 660.0).abs() < 0.5);
+}
+
+#[test]
+fn timestamps_wrap_around() {
+    let mut clock = ClockSync::new(60.0);
+    let sent = u32::MAX - 10;
+    clock.record(sent, sent.wrapping_add(15), 0, sent.wrapping_add(30));
+
+    assert!((clock.smoothed_rtt_ms - 30.0).abs() < f64::EPSILON);
+    assert!(clock.offset_ms.abs() < 1.0);
+}
+
+#[test]
+fn tick_offset_comes_from_ticks_not_the_wall_clock() {
+    let mut clock = ClockSync::new(60.0);
+    clock.local_tick = 100;
+    // 100ms round trip is 3 ticks each way, the server was on 500 when we were on 97
+    clock.record(1_000, 1_050, 500, 1_100);
+    assert_eq!(clock.tick_offset, 403);
+
+    // However many ticks a frame runs, the offset holds until a better exchange comes in
+    for _ in 0..10 {
+        clock.local_tick += 1;
+    }
+    assert_eq!(clock.tick_offset, 403);
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+use crate::network::net_conditioner::{run_conditioner, LinkChannel, LinkConditioner, LinkProfile};
+
+fn conditioner(udp: LinkProfile) -> LinkConditioner {
+    LinkConditioner { enabled: true, udp, tcp: LinkProfile::default() }
+}
+
+#[tokio::test]
+async fn lossy_link_only_delivers_exempt_messages() {
+    let (_settings_tx, settings_rx) = watch::channel(conditioner(LinkProfile { loss: 1.0, ..Default::default() }));
+    let (in_tx, in_rx) = mpsc::channel::<Vec<u8>>(16);
+    let (out_tx, mut out_rx) = mpsc::channel::<Vec<u8>>(16);
+    tokio::spawn(run_conditioner(in_rx, out_tx, settings_rx, LinkChannel::Udp, |m| m.is_empty()));
+
+    in_tx.send(vec![1]).await.unwrap();
+    in_tx.send(vec![]).await.unwrap();
+    in_tx.send(vec![2]).await.unwrap();
+    drop(in_tx);
+
+    assert_eq!(out_rx.recv().await, Some(vec![]));
+    assert_eq!(out_rx.recv().await, None);
+}
+
+#[tokio::test]
+async fn latency_delays_but_keeps_order() {
+    let (_settings_tx, settings_rx) = watch::channel(conditioner(LinkProfile { latency_ms: 50.0, jitter_ms: 20.0, ..Default::default() }));
+    let (in_tx, in_rx) = mpsc::channel::<u32>(16);
+    let (out_tx, mut out_rx) = mpsc::channel::<u32>(16);
+    tokio::spawn(run_conditioner(in_rx, out_tx, settings_rx, LinkChannel::Udp, |_| false));
+
+    let start = Instant::now();
+    for i in 0..5 {
+        in_tx.send(i).await.unwrap();
+    }
+
+    for i in 0..5 {
+        assert_eq!(out_rx.recv().await, Some(i));
+    }
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[tokio::test]
+async fn closing_the_consumer_closes_the_producer() {
+    let (_settings_tx, settings_rx) = watch::channel(conditioner(LinkProfile::default()));
+    let (in_tx, in_rx) = mpsc::channel::<u32>(16);
+    let (out_tx, out_rx) = mpsc::channel::<u32>(16);
+    tokio::spawn(run_conditioner(in_rx, out_tx, settings_rx, LinkChannel::Udp, |_| false));
+
+    // The socket task watches its sender the same way to notice the client dropped the link
+    drop(out_rx);
+    tokio::time::timeout(Duration::from_secs(1), in_tx.closed()).await.expect("conditioner kept running");
+}
@@ -0,0 +1,27 @@
+use crate::network::net_connection::{backoff_delay, ConnectionLifecycle, INITIAL_BACKOFF, MAX_BACKOFF};
+use std::time::Duration;
+
+#[test]
+fn backoff_doubles_until_capped() {
+    assert_eq!(backoff_delay(0), INITIAL_BACKOFF);
+    assert_eq!(backoff_delay(1), INITIAL_BACKOFF);
+    assert_eq!(backoff_delay(2), INITIAL_BACKOFF * 2);
+    assert_eq!(backoff_delay(3), INITIAL_BACKOFF * 4);
+    assert_eq!(backoff_delay(12), MAX_BACKOFF);
+    assert_eq!(backoff_delay(u32::MAX), MAX_BACKOFF);
+}
+
+#[test]
+fn retry_now_forgets_rejection_and_backoff() {
+    let mut lifecycle = ConnectionLifecycle {
+        attempts: 5,
+        rejection: Some("wrong version".to_string()),
+        ..Default::default()
+    };
+    lifecycle.schedule_retry(Duration::from_secs(10));
+    lifecycle.retry_now(Duration::from_secs(11));
+
+    assert!(lifecycle.rejection.is_none());
+    assert_eq!(lifecycle.attempts, 0);
+    assert_eq!(lifecycle.next_attempt, Duration::from_secs(11));
+}
@@ -0,0 +1,341 @@
+use crate::components::chat::ChatMessage;
+use crate::components::common::Id;
+use crate::components::player::PlayerInfo;
+use crate::network::net_channel::ControlRoute;
+use crate::network::net_clock::ClockSync;
+use crate::network::net_connection::{ConnectionLifecycle, ConnectionState, ConnectionStateChanged};
+use crate::network::net_crypto::{load_psk, server_proof, KeyExchange, ReplayWindow, Role, SessionKeys, TransportSecurity};
+use crate::network::net_framing::{encode_frame, FrameDecoder};
+use crate::network::net_manage::{Communication, TcpConnection, UdpConnection};
+use crate::network::net_message::{CTcpType, NetworkMessage, STcpType, PROTOCOL_VERSION};
+use crate::network::net_reconciliation::ReconcileBuffer;
+use crate::network::net_stats::NetStats;
+use crate::network::net_system::{tcp_client_net_receive, tcp_client_net_send};
+use crate::network::net_tasks::handle_tcp_message;
+use crate::network::net_transport::{LoopbackTransport, Transport};
+use bevy::prelude::{App, IntoScheduleConfigs, Real, Time, Update};
+use bincode::config;
+use std::io::ErrorKind;
+use tokio::net::{TcpListener, TcpStream};
+
+const PSK: &[u8] = b"correct horse battery staple";
+
+fn handshake() -> (TransportSecurity, SessionKeys) {
+    let mut client = TransportSecurity::new(Some(PSK.to_vec()));
+    let client_key = client.begin().unwrap();
+
+    let server = KeyExchange::new();
+    let server_key = server.public_key;
+    let server_keys = server.finish(client_key, Role::Server).unwrap();
+
+    client.complete(server_key, server_proof(PSK, &client_key, &server_key)).unwrap();
+    (client, server_keys)
+}
+
+#[test]
+fn plaintext_until_established() {
+    let mut security = TransportSecurity::new(None);
+    assert_eq!(security.begin(), None);
+    assert!(security.is_ready());
+    assert_eq!(security.seal_udp(b"ping".to_vec()).unwrap(), b"ping");
+    assert!(security.complete([9; 32], [0; 32]).is_err());
+}
+
+#[test]
+fn secure_mode_only_lets_the_handshake_through_in_the_clear() {
+    let mut security = TransportSecurity::new(Some(PSK.to_vec()));
+    assert!(!security.is_ready());
+    assert!(security.open_tcp(b"welcome".to_vec()).is_err());
+
+    let client_key = security.begin().unwrap();
+    assert_eq!(security.seal_tcp(b"hello".to_vec()).unwrap(), b"hello");
+    assert_eq!(security.open_tcp(b"welcome".to_vec()).unwrap(), b"welcome");
+    assert!(security.seal_udp(b"ping".to_vec()).is_err());
+    assert!(security.open_udp(b"players".to_vec()).is_err());
+
+    let server = KeyExchange::new();
+    security.complete(server.public_key, server_proof(PSK, &client_key, &server.public_key)).unwrap();
+    assert!(security.is_ready());
+    assert!(security.open_tcp(b"chat".to_vec()).is_err());
+}
+
+#[test]
+fn server_key_needs_the_pre_shared_key() {
+    let mut security = TransportSecurity::new(Some(PSK.to_vec()));
+    let client_key = security.begin().unwrap();
+
+    // Someone in the middle with their own key but not ours
+    let attacker = KeyExchange::new();
+    let forged = server_proof(b"guessed", &client_key, &attacker.public_key);
+    let e = security.complete(attacker.public_key, forged).unwrap_err();
+    assert_eq!(e.kind(), ErrorKind::PermissionDenied);
+    assert!(!security.is_established());
+
+    // A genuine proof doesn't carry over to another client key
+    let client_key = security.begin().unwrap();
+    let server = KeyExchange::new();
+    let other_client = KeyExchange::new();
+    let proof = server_proof(PSK, &other_client.public_key, &server.public_key);
+    assert!(security.complete(server.public_key, proof).is_err());
+
+    security.begin().unwrap();
+    assert!(security.complete(server.public_key, server_proof(PSK, &client_key, &server.public_key)).is_err());
+}
+
+#[test]
+fn both_directions_round_trip() {
+    let (mut client, mut server) = handshake();
+
+    let sealed = client.seal_udp(b"input".to_vec()).unwrap();
+    assert_ne!(sealed, b"input");
+    assert_eq!(server.udp.open(&sealed).unwrap(), b"input");
+
+    let sealed = server.tcp.seal(b"chat").unwrap();
+    assert_eq!(client.open_tcp(sealed).unwrap(), b"chat");
+
+    // Keys are bound to their socket and direction
+    let sealed = server.udp.seal(b"players").unwrap();
+    assert!(client.open_tcp(sealed.clone()).is_err());
+    assert_eq!(client.open_udp(sealed).unwrap(), b"players");
+}
+
+#[test]
+fn replays_and_forgeries_are_rejected() {
+    let (mut client, mut server) = handshake();
+
+    let first = client.seal_udp(b"one".to_vec()).unwrap();
+    let second = client.seal_udp(b"two".to_vec()).unwrap();
+    assert!(server.udp.open(&second).is_ok());
+    // Late but not yet seen is fine
+    assert!(server.udp.open(&first).is_ok());
+    assert!(server.udp.open(&first).is_err());
+    assert!(server.udp.open(&second).is_err());
+
+    let mut tampered = client.seal_udp(b"three".to_vec()).unwrap();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert!(server.udp.open(&tampered).is_err());
+}
+
+#[test]
+fn replay_window_slides() {
+    let mut window = ReplayWindow::default();
+    assert!(window.accept(0));
+    assert!(window.accept(100));
+    assert!(!window.accept(0));
+    assert!(window.accept(99));
+    assert!(!window.accept(99));
+    assert!(window.accept(37));
+}
+
+async fn read_frame(stream: &TcpStream, decoder: &mut FrameDecoder) -> Vec<u8> {
+    let mut buf = [0u8; 1024];
+    loop {
+        if let Some(frame) = decoder.next_frame().unwrap() {
+            return frame;
+        }
+        stream.readable().await.unwrap();
+        match stream.try_read(&mut buf) {
+            Ok(0) => panic!("peer closed the stream"),
+            Ok(n) => decoder.push(&buf[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+async fn write_frame(stream: &TcpStream, payload: &[u8]) {
+    let frame = encode_frame(payload).unwrap();
+    let mut bytes = &frame[..];
+    while !bytes.is_empty() {
+        stream.writable().await.unwrap();
+        match stream.try_write(bytes) {
+            Ok(n) => bytes = &bytes[n..],
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+#[tokio::test]
+async fn handshake_over_a_local_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    // Minimal server: answers the key exchange, then echoes one encrypted chat frame back
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut decoder = FrameDecoder::new();
+
+        let frame = read_frame(&stream, &mut decoder).await;
+        let (messages, _): (Vec<CTcpType>, usize) = bincode::serde::decode_from_slice(&frame, config::standard()).unwrap();
+        let Some(CTcpType::KeyExchange { public_key }) = messages.into_iter().next() else {
+            panic!("expected a key exchange");
+        };
+
+        let exchange = KeyExchange::new();
+        let proof = server_proof(PSK, &public_key, &exchange.public_key);
+        let reply = vec![STcpType::KeyExchange { public_key: exchange.public_key, proof }];
+        let mut keys = exchange.finish(public_key, Role::Server).unwrap();
+        let bytes = bincode::serde::encode_to_vec(&reply, config::standard()).unwrap();
+        write_frame(&stream, &bytes).await;
+
+        let frame = read_frame(&stream, &mut decoder).await;
+        let plaintext = keys.tcp.open(&frame).unwrap();
+        write_frame(&stream, &keys.tcp.seal(&plaintext).unwrap()).await;
+        plaintext
+    });
+
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let mut decoder = FrameDecoder::new();
+    let mut security = TransportSecurity::new(Some(PSK.to_vec()));
+
+    let hello = vec![CTcpType::KeyExchange { public_key: security.begin().unwrap() }];
+    let bytes = bincode::serde::encode_to_vec(&hello, config::standard()).unwrap();
+    write_frame(&stream, &bytes).await;
+
+    let frame = read_frame(&stream, &mut decoder).await;
+    let (messages, _): (Vec<STcpType>, usize) = bincode::serde::decode_from_slice(&frame, config::standard()).unwrap();
+    let Some(STcpType::KeyExchange { public_key, proof }) = messages.into_iter().next() else {
+        panic!("expected a key exchange");
+    };
+    security.complete(public_key, proof).unwrap();
+    assert!(security.is_established());
+
+    let sealed = security.seal_tcp(b"secret chat".to_vec()).unwrap();
+    write_frame(&stream, &sealed).await;
+
+    let echoed = read_frame(&stream, &mut decoder).await;
+    assert_eq!(security.open_tcp(echoed).unwrap(), b"secret chat");
+    assert_eq!(server.await.unwrap(), b"secret chat");
+}
+
+/// The client's TCP receive path, talking to whatever holds the returned end of the transport.
+/// Our half of the exchange is already on its way.
+fn handshaking_client() -> (App, LoopbackTransport, [u8; 32]) {
+    let (client, server) = LoopbackTransport::pair();
+    let mut security = TransportSecurity::new(Some(PSK.to_vec()));
+    let client_key = security.begin().unwrap();
+
+    let mut app = App::new();
+    app.add_event::<ConnectionStateChanged>();
+    app.init_resource::<PlayerInfo>();
+    app.init_resource::<ReconcileBuffer>();
+    app.init_resource::<NetStats>();
+    app.init_resource::<Time<Real>>();
+    app.insert_resource(ClockSync::new(60.0));
+    app.insert_resource(TcpConnection::new());
+    app.insert_resource(UdpConnection::new());
+    app.insert_resource(Communication::new(client));
+    app.insert_resource(security);
+    app.insert_resource(ConnectionLifecycle { state: ConnectionState::Handshaking, ..Default::default() });
+    app.insert_resource(ControlRoute::default());
+    app.add_systems(Update, (tcp_client_net_receive, handle_tcp_message).chain());
+    (app, server, client_key)
+}
+
+fn send(server: &mut LoopbackTransport, messages: &[STcpType], keys: Option<&mut SessionKeys>) -> Vec<u8> {
+    let bytes = bincode::serde::encode_to_vec(messages, config::standard()).unwrap();
+    let bytes = match keys {
+        Some(keys) => keys.tcp.seal(&bytes).unwrap(),
+        None => bytes,
+    };
+    server.send_reliable(bytes.clone()).unwrap();
+    bytes
+}
+
+#[test]
+fn transport_refuses_plaintext_and_forged_keys_in_secure_mode() {
+    let (mut app, mut server, client_key) = handshaking_client();
+    let welcome = STcpType::Welcome { protocol_version: PROTOCOL_VERSION };
+
+    // Stripping the key exchange leaves the client waiting, not joined in the clear
+    send(&mut server, &[welcome.clone(), STcpType::PlayerId { player_uid: Id(5) }], None);
+    app.update();
+    assert_eq!(app.world().resource::<PlayerInfo>().current_player_id, Id(0));
+    assert_eq!(app.world().resource::<ConnectionLifecycle>().state, ConnectionState::Handshaking);
+    assert_eq!(app.world().resource::<NetStats>().decode_failures, 1);
+
+    let attacker = KeyExchange::new();
+    let proof = server_proof(b"guessed", &client_key, &attacker.public_key);
+    send(&mut server, &[welcome, STcpType::KeyExchange { public_key: attacker.public_key, proof }], None);
+    app.update();
+    let lifecycle = app.world().resource::<ConnectionLifecycle>();
+    assert!(lifecycle.rejection.as_ref().is_some_and(|r| r.starts_with("Key exchange failed")));
+    assert!(!app.world().resource::<TransportSecurity>().is_established());
+}
+
+#[test]
+fn transport_drops_tampered_and_plaintext_frames_once_keyed() {
+    let (mut app, mut server, client_key) = handshaking_client();
+    let exchange = KeyExchange::new();
+    let proof = server_proof(PSK, &client_key, &exchange.public_key);
+    let public_key = exchange.public_key;
+    let mut keys = exchange.finish(client_key, Role::Server).unwrap();
+
+    // Like the server, the answer and the first sealed frame back to back
+    send(&mut server, &[STcpType::Welcome { protocol_version: PROTOCOL_VERSION }, STcpType::KeyExchange { public_key, proof }], None);
+    let mut tampered = keys.tcp.seal(&bincode::serde::encode_to_vec([STcpType::PlayerId { player_uid: Id(7) }], config::standard()).unwrap()).unwrap();
+    *tampered.last_mut().unwrap() ^= 1;
+    server.send_reliable(tampered).unwrap();
+    send(&mut server, &[STcpType::PlayerId { player_uid: Id(8) }], None);
+    app.update();
+
+    assert!(app.world().resource::<TransportSecurity>().is_established());
+    assert_eq!(app.world().resource::<ConnectionLifecycle>().state, ConnectionState::Connected);
+    assert_eq!(app.world().resource::<PlayerInfo>().current_player_id, Id(0));
+    assert_eq!(app.world().resource::<NetStats>().decode_failures, 2);
+
+    send(&mut server, &[STcpType::PlayerId { player_uid: Id(9) }], Some(&mut keys));
+    app.update();
+    assert_eq!(app.world().resource::<PlayerInfo>().current_player_id, Id(9));
+    assert_eq!(app.world().resource::<ConnectionLifecycle>().state, ConnectionState::Joined);
+}
+
+#[test]
+fn only_the_handshake_leaves_before_the_keys_are_in() {
+    let (mut app, mut server, client_key) = handshaking_client();
+    app.add_systems(Update, tcp_client_net_send.after(handle_tcp_message));
+    let mut connection = app.world_mut().resource_mut::<TcpConnection>();
+    connection.ready = true;
+    connection.add_message(NetworkMessage(CTcpType::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        build_id: String::new(),
+        client_name: "test".to_string(),
+    }));
+    connection.add_message(NetworkMessage(CTcpType::KeyExchange { public_key: client_key }));
+    connection.add_message(NetworkMessage(CTcpType::ChatMessage {
+        player_id: Id(0),
+        message: ChatMessage { message: "secret chat".to_string() },
+    }));
+    app.update();
+
+    let frame = server.receive_reliable().unwrap();
+    let (sent, _): (Vec<CTcpType>, _) = bincode::serde::decode_from_slice(&frame, config::standard()).unwrap();
+    assert_eq!(sent.len(), 2);
+    assert!(sent.iter().all(CTcpType::is_handshake));
+    assert!(server.receive_reliable().is_none());
+
+    // The chat waits and goes out sealed once the server answered
+    let exchange = KeyExchange::new();
+    let proof = server_proof(PSK, &client_key, &exchange.public_key);
+    let public_key = exchange.public_key;
+    let mut keys = exchange.finish(client_key, Role::Server).unwrap();
+    send(&mut server, &[STcpType::Welcome { protocol_version: PROTOCOL_VERSION }, STcpType::KeyExchange { public_key, proof }], None);
+    app.update();
+
+    let frame = server.receive_reliable().unwrap();
+    assert!(!frame.windows(b"secret chat".len()).any(|w| w == b"secret chat"));
+    let (sent, _): (Vec<CTcpType>, _) = bincode::serde::decode_from_slice(&keys.tcp.open(&frame).unwrap(), config::standard()).unwrap();
+    assert!(matches!(&sent[..], [CTcpType::ChatMessage { message, .. }] if message.message == "secret chat"));
+}
+
+#[test]
+fn pre_shared_key_comes_from_a_file_and_may_not_be_empty() {
+    let path = std::env::temp_dir().join(format!("psk_test_{}", std::process::id()));
+    std::fs::write(&path, b"correct horse\n").unwrap();
+    assert_eq!(load_psk(Some(&path)).unwrap(), b"correct horse");
+
+    std::fs::write(&path, b"\n").unwrap();
+    assert_eq!(load_psk(Some(&path)).unwrap_err().kind(), ErrorKind::InvalidInput);
+    std::fs::remove_file(&path).unwrap();
+}
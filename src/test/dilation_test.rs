@@ -0,0 +1,67 @@
+use crate::network::net_dilation::{InputLead, TickAdjustment, BASE_LEAD, MAX_DILATION};
+use std::time::Duration;
+
+#[test]
+fn no_feedback_keeps_normal_speed() {
+    let mut lead = InputLead::default();
+    assert_eq!(lead.update(16.6, 40.0), 1.0);
+}
+
+#[test]
+fn starving_speeds_up_and_surplus_slows_down() {
+    let mut lead = InputLead::default();
+    lead.record(-2);
+    let speed = lead.update(16.6, 0.0);
+    assert!(speed > 1.0);
+    assert!(speed <= 1.0 + MAX_DILATION);
+    assert_eq!(lead.starved, 1);
+
+    let mut lead = InputLead::default();
+    lead.record(20);
+    let speed = lead.update(16.6, 0.0);
+    assert!(speed < 1.0);
+    assert!(speed >= 1.0 - MAX_DILATION);
+}
+
+#[test]
+fn on_target_lead_is_left_alone() {
+    let mut lead = InputLead::default();
+    for _ in 0..50 {
+        lead.record(BASE_LEAD as i16);
+    }
+    assert_eq!(lead.update(16.6, 0.0), 1.0);
+}
+
+#[test]
+fn jitter_raises_the_target() {
+    let mut lead = InputLead::default();
+    lead.record(1);
+    lead.update(10.0, 30.0);
+    assert!((lead.target - (BASE_LEAD + 3.0)).abs() < 1e-4);
+    assert!(lead.speed > 1.0);
+}
+
+#[test]
+fn speed_changes_how_many_ticks_run_not_their_step() {
+    let timestep = Duration::from_secs_f64(1.0 / 60.0);
+
+    let mut lead = InputLead::default();
+    lead.record(-2);
+    lead.update(16.6, 0.0);
+    // Every frame gains `speed - 1` of a tick, made up one whole tick at a time
+    let extra = (0..110)
+        .filter(|_| lead.adjust(timestep.as_secs_f64(), Duration::ZERO, timestep) == TickAdjustment::ExtraTick)
+        .count();
+    assert!(extra > 0);
+    assert_eq!(extra, (110.0 * (lead.speed - 1.0)) as usize);
+
+    let mut lead = InputLead::default();
+    lead.record(20);
+    lead.update(16.6, 0.0);
+    // Running slow holds accumulated time back, as much as there is
+    let TickAdjustment::Discard(discarded) = lead.adjust(1.0, Duration::from_millis(5), timestep) else {
+        panic!("nothing held back");
+    };
+    assert_eq!(discarded, Duration::from_millis(5));
+    assert_eq!(lead.adjust(0.0, Duration::ZERO, timestep), TickAdjustment::None);
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+use crate::network::net_fragment::{fragment_payload, Reassembler, MAX_FRAGMENTS, MAX_PENDING_MESSAGES};
+
+#[test]
+fn large_payloads_are_reassembled_out_of_order() {
+    let payload: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+    let mut datagrams = fragment_payload(&payload, 1200, 7).unwrap();
+    assert!(datagrams.len() > 1);
+    assert!(datagrams.iter().all(|d| d.len() <= 1200));
+
+    datagrams.reverse();
+    let mut reassembler = Reassembler::default();
+    let mut completed = Vec::new();
+    for d in datagrams.iter().chain(datagrams.first()) {
+        if let Some(bytes) = reassembler.push(d, Duration::ZERO).unwrap() {
+            completed.push(bytes);
+        }
+    }
+
+    assert_eq!(completed, vec![payload]);
+}
+
+#[test]
+fn small_payloads_are_not_fragmented() {
+    let datagrams = fragment_payload(b"ping", 1200, 0).unwrap();
+    assert_eq!(datagrams.len(), 1);
+
+    let mut reassembler = Reassembler::default();
+    assert_eq!(reassembler.push(&datagrams[0], Duration::ZERO).unwrap(), Some(b"ping".to_vec()));
+}
+
+#[test]
+fn incomplete_messages_expire() {
+    let datagrams = fragment_payload(&[1u8; 3000], 1200, 1).unwrap();
+    let mut reassembler = Reassembler::default();
+    assert_eq!(reassembler.push(&datagrams[0], Duration::ZERO).unwrap(), None);
+
+    assert_eq!(reassembler.expire(Duration::from_millis(100), Duration::from_millis(500)), 0);
+    assert_eq!(reassembler.expire(Duration::from_secs(1), Duration::from_millis(500)), 1);
+
+    assert!(fragment_payload(&vec![0u8; 100 * (MAX_FRAGMENTS + 1)], 116, 2).is_err());
+}
+
+#[test]
+fn only_so_many_partial_messages_are_kept() {
+    let mut reassembler = Reassembler::default();
+    let messages: Vec<Vec<Vec<u8>>> = (0..=MAX_PENDING_MESSAGES as u16)
+        .map(|id| fragment_payload(&[id as u8; 3000], 1200, id).unwrap())
+        .collect();
+    for (at, datagrams) in messages.iter().enumerate() {
+        assert_eq!(reassembler.push(&datagrams[0], Duration::from_millis(at as u64)).unwrap(), None);
+    }
+
+    // The oldest made room for the last one, the rest still complete
+    let complete = |reassembler: &mut Reassembler, datagrams: &[Vec<u8>]| {
+        datagrams[1..].iter().filter_map(|d| reassembler.push(d, Duration::ZERO).unwrap()).next()
+    };
+    assert_eq!(complete(&mut reassembler, &messages[1]), Some(vec![1u8; 3000]));
+    let last = MAX_PENDING_MESSAGES;
+    assert_eq!(complete(&mut reassembler, &messages[last]), Some(vec![last as u8; 3000]));
+    assert_eq!(complete(&mut reassembler, &messages[0]), None);
+}
@@ -0,0 +1,41 @@
+use crate::network::net_framing::{encode_frame, FrameDecoder, FRAME_HEADER_LEN, MAX_FRAME_SIZE};
+
+#[test]
+fn coalesced_frames_are_split() {
+    let mut bytes = encode_frame(b"hello").unwrap();
+    bytes.extend(encode_frame(b"world!").unwrap());
+
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&bytes);
+
+    assert_eq!(decoder.next_frame().unwrap(), Some(b"hello".to_vec()));
+    assert_eq!(decoder.next_frame().unwrap(), Some(b"world!".to_vec()));
+    assert_eq!(decoder.next_frame().unwrap(), None);
+}
+
+#[test]
+fn partial_frames_are_buffered() {
+    let payload: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+    let bytes = encode_frame(&payload).unwrap();
+
+    let mut decoder = FrameDecoder::new();
+    let mut frames = Vec::new();
+    for chunk in bytes.chunks(1024) {
+        decoder.push(chunk);
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame);
+        }
+    }
+
+    assert_eq!(frames, vec![payload]);
+}
+
+#[test]
+fn oversized_frames_are_rejected() {
+    assert!(encode_frame(&vec![0u8; MAX_FRAME_SIZE + 1]).is_err());
+
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&((MAX_FRAME_SIZE + 1) as u32).to_be_bytes());
+    decoder.push(&[0u8; FRAME_HEADER_LEN]);
+    assert!(decoder.next_frame().is_err());
+}
@@ -0,0 +1,166 @@
+use crate::components::common::Id;
+use crate::components::player::{PlayerInfo, PlayerMarker};
+use crate::headless::{HeadlessPlugin, InputStep, ScriptedInput};
+use crate::mock_server::{mock_server_app, MockPlayer, MockServer};
+use crate::network::net_connection::{ConnectionLifecycle, ConnectionState};
+use crate::network::net_crypto::TransportSecurity;
+use crate::network::net_message::STcpType;
+use crate::network::net_transport::{LoopbackListener, LOOPBACK_ADDRESS};
+use crate::network::{ClientName, RemoteAddress, SERVER_TICK_RATE};
+use bevy::app::AppExit;
+use avian3d::prelude::Position;
+use bevy::math::{Vec2, Vec3};
+use bevy::prelude::{App, Fixed, Time, With};
+use bevy::time::TimeUpdateStrategy;
+use std::time::Duration;
+
+#[test]
+fn script_plays_steps_in_order_and_loops() {
+    let step = |keymask, millis| InputStep {
+        keymask,
+        mouse_delta: Vec2::ZERO,
+        duration: Duration::from_millis(millis),
+    };
+    let script = ScriptedInput { steps: vec![step(1, 100), step(2, 50)] };
+
+    assert_eq!(script.step_at(Duration::ZERO).unwrap().keymask, 1);
+    assert_eq!(script.step_at(Duration::from_millis(99)).unwrap().keymask, 1);
+    assert_eq!(script.step_at(Duration::from_millis(100)).unwrap().keymask, 2);
+    assert_eq!(script.step_at(Duration::from_millis(149)).unwrap().keymask, 2);
+    // Starts over after 150ms
+    assert_eq!(script.step_at(Duration::from_millis(160)).unwrap().keymask, 1);
+    assert_eq!(script.step_at(Duration::from_millis(1_020)).unwrap().keymask, 2);
+}
+
+#[test]
+fn empty_script_gives_no_input() {
+    let script = ScriptedInput { steps: Vec::new() };
+    assert!(script.step_at(Duration::from_secs(3)).is_none());
+}
+
+/// A headless client and a mock server on the loopback, both stepped one fixed tick per update.
+fn client_and_server() -> (App, App) {
+    let (listener, incoming) = LoopbackListener::new();
+    let mut server = mock_server_app(incoming, None, None);
+    server.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / SERVER_TICK_RATE)));
+    server.finish();
+    server.cleanup();
+
+    let mut client = App::new();
+    client.add_plugins(HeadlessPlugin);
+    client.insert_resource(Time::<Fixed>::from_hz(SERVER_TICK_RATE));
+    client.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / SERVER_TICK_RATE)));
+    client.insert_resource(RemoteAddress(LOOPBACK_ADDRESS.to_string()));
+    client.insert_resource(ClientName("tester".to_string()));
+    client.insert_resource(TransportSecurity::new(None));
+    client.insert_resource(listener);
+    client.finish();
+    client.cleanup();
+    (client, server)
+}
+
+/// Steps both apps until `done` holds for the client, for at most `ticks` ticks.
+fn run_until(client: &mut App, server: &mut App, ticks: usize, done: impl Fn(&mut App) -> bool) -> bool {
+    for _ in 0..ticks {
+        client.update();
+        server.update();
+        if done(client) {
+            return true;
+        }
+    }
+    false
+}
+
+fn state(client: &App) -> ConnectionState {
+    client.world().resource::<ConnectionLifecycle>().state
+}
+
+fn player_count(app: &mut App) -> usize {
+    app.world_mut().query_filtered::<(), With<PlayerMarker>>().iter(app.world()).count()
+}
+
+/// Runs until the client joined and sees its own player.
+fn join(client: &mut App, server: &mut App) -> Id {
+    assert!(run_until(client, server, 120, |c| state(c) == ConnectionState::Joined && player_count(c) > 0));
+    client.world().resource::<PlayerInfo>().current_player_id
+}
+
+fn send_to_client(server: &mut App, message: STcpType) {
+    server.world_mut().resource_mut::<MockServer>().clients[0].send_reliable(message);
+}
+
+#[test]
+fn leave_is_sent_on_exit() {
+    let (mut client, mut server) = client_and_server();
+    join(&mut client, &mut server);
+    assert_eq!(server.world().resource::<MockServer>().clients.len(), 1);
+
+    client.world_mut().send_event(AppExit::Success);
+    client.update();
+    server.update();
+
+    // The client is still around, so only the leave message can have taken it off the server
+    assert!(server.world().resource::<MockServer>().clients.is_empty());
+    assert_eq!(server.world_mut().query::<&MockPlayer>().iter(server.world()).count(), 0);
+}
+
+#[test]
+fn kick_tears_the_connection_down_for_good() {
+    let (mut client, mut server) = client_and_server();
+    join(&mut client, &mut server);
+
+    send_to_client(&mut server, STcpType::Kicked { reason: "test".to_string() });
+    assert!(run_until(&mut client, &mut server, 10, |c| state(c) == ConnectionState::Disconnected));
+    client.update();
+
+    assert_eq!(player_count(&mut client), 0);
+    assert_eq!(client.world().resource::<PlayerInfo>().current_player_id, Id(0));
+    let lifecycle = client.world().resource::<ConnectionLifecycle>();
+    assert_eq!(lifecycle.rejection.as_deref(), Some("Kicked: test"));
+
+    // Well past the first backoff, a kicked client stays away, and the server saw it go
+    assert!(!run_until(&mut client, &mut server, 120, |c| state(c) != ConnectionState::Disconnected));
+    assert!(server.world().resource::<MockServer>().clients.is_empty());
+}
+
+#[test]
+fn server_shutdown_leads_to_a_reconnect() {
+    let (mut client, mut server) = client_and_server();
+    let first_id = join(&mut client, &mut server);
+
+    send_to_client(&mut server, STcpType::ServerShutdown);
+    assert!(run_until(&mut client, &mut server, 10, |c| state(c) == ConnectionState::Disconnected));
+    client.update();
+
+    assert_eq!(player_count(&mut client), 0);
+    assert_eq!(client.world().resource::<PlayerInfo>().current_player_id, Id(0));
+    assert_eq!(client.world().resource::<ConnectionLifecycle>().last_error.as_deref(), Some("server shut down"));
+
+    // Back in after the backoff, as a new player
+    let second_id = join(&mut client, &mut server);
+    assert_ne!(second_id, first_id);
+    assert_eq!(client.world().resource::<ConnectionLifecycle>().attempts, 0);
+}
+
+fn server_position(server: &mut App, id: Id) -> Vec3 {
+    let mut players = server.world_mut().query::<(&MockPlayer, &Position)>();
+    players.iter(server.world()).find(|(player, _)| player.id == id).map(|(_, position)| position.0).unwrap()
+}
+
+#[test]
+fn scripted_input_moves_the_player_on_the_server() {
+    let (mut client, mut server) = client_and_server();
+    client.insert_resource(ScriptedInput {
+        steps: vec![InputStep { keymask: 1, mouse_delta: Vec2::ZERO, duration: Duration::from_secs(60) }],
+    });
+    let id = join(&mut client, &mut server);
+
+    // Let it land first, the walk is what's measured
+    run_until(&mut client, &mut server, 60, |_| false);
+    let start = server_position(&mut server, id);
+    run_until(&mut client, &mut server, 60, |_| false);
+    let walked = server_position(&mut server, id) - start;
+
+    assert!(walked.with_y(0.0).length() > 1.0, "walked {:?}", walked);
+    assert!(server.world().resource::<MockServer>().clients[0].next_input.is_some());
+}
@@ -0,0 +1,228 @@
+use crate::components::chat::ChatMessage;
+use crate::components::common::Id;
+use crate::mock_server::client::input_is_newer;
+use crate::mock_server::mock_server_app;
+use crate::network::net_channel::{UdpChannel, UdpPacket};
+use crate::network::net_fragment::{fragment_payload, Reassembler};
+use crate::network::net_message::{CTcpType, CUdpType, InputHistory, STcpType, SUdpType, PROTOCOL_VERSION};
+use crate::network::net_transport::{LoopbackListener, LoopbackTransport, Transport};
+use bevy::math::Vec2;
+use bevy::prelude::App;
+use bevy::time::TimeUpdateStrategy;
+use bincode::config;
+use std::time::Duration;
+
+struct TestClient {
+    transport: LoopbackTransport,
+    channel: UdpChannel<CUdpType, SUdpType>,
+    reassembler: Reassembler,
+}
+
+impl TestClient {
+    fn connect(listener: &LoopbackListener) -> Self {
+        Self {
+            transport: listener.connect(),
+            channel: UdpChannel::new(),
+            reassembler: Reassembler::default(),
+        }
+    }
+
+    fn send_tcp(&mut self, messages: Vec<CTcpType>) {
+        let bytes = bincode::serde::encode_to_vec(messages, config::standard()).unwrap();
+        self.transport.send_reliable(bytes).unwrap();
+    }
+
+    fn receive_tcp(&mut self) -> Vec<STcpType> {
+        let mut messages = Vec::new();
+        while let Some(bytes) = self.transport.receive_reliable() {
+            let (frame, _): (Vec<STcpType>, usize) = bincode::serde::decode_from_slice(&bytes, config::standard()).unwrap();
+            messages.extend(frame);
+        }
+        messages
+    }
+
+    fn send_udp(&mut self, messages: Vec<CUdpType>) {
+        let packet = self.channel.build_packet(messages, Vec::new(), Duration::ZERO);
+        let bytes = bincode::serde::encode_to_vec(&packet, config::standard()).unwrap();
+        for datagram in fragment_payload(&bytes, 1200, 0).unwrap() {
+            self.transport.send_unreliable(datagram).unwrap();
+        }
+    }
+
+    fn receive_udp(&mut self) -> Vec<SUdpType> {
+        let mut messages = Vec::new();
+        while let Some(datagram) = self.transport.receive_unreliable() {
+            let Some(bytes) = self.reassembler.push(&datagram, Duration::ZERO).unwrap() else {
+                continue;
+            };
+            let (packet, _): (UdpPacket<SUdpType>, usize) = bincode::serde::decode_from_slice(&bytes, config::standard()).unwrap();
+            messages.extend(self.channel.receive_packet(packet, Duration::ZERO).unwrap());
+        }
+        messages
+    }
+
+    /// Says hello and joins, returning the session token and player id.
+    fn join(&mut self, server: &mut App) -> (u64, Id) {
+        self.send_tcp(vec![CTcpType::Hello {
+            protocol_version: PROTOCOL_VERSION,
+            build_id: "test".to_string(),
+            client_name: "tester".to_string(),
+        }]);
+        tick(server, 2);
+        let token = self
+            .receive_tcp()
+            .into_iter()
+            .find_map(|m| match m {
+                STcpType::SessionToken { token } => Some(token),
+                _ => None,
+            })
+            .expect("no session token");
+
+        self.send_tcp(vec![CTcpType::Join { lobby_id: Id(1) }]);
+        tick(server, 2);
+        let id = self
+            .receive_tcp()
+            .into_iter()
+            .find_map(|m| match m {
+                STcpType::PlayerId { player_uid } => Some(player_uid),
+                _ => None,
+            })
+            .expect("no player id");
+        (token, id)
+    }
+}
+
+fn server() -> (App, LoopbackListener) {
+    let (listener, incoming) = LoopbackListener::new();
+    let mut app = mock_server_app(incoming, None, None);
+    app.insert_resource(TimeUpdateStrategy::ManualDuration(Duration::from_secs_f64(1.0 / 60.0)));
+    app.finish();
+    app.cleanup();
+    (app, listener)
+}
+
+fn tick(app: &mut App, ticks: usize) {
+    for _ in 0..ticks {
+        app.update();
+    }
+}
+
+#[test]
+fn input_sequences_wrap_like_the_client() {
+    assert!(input_is_newer(1, 0));
+    assert!(input_is_newer(0, 1023));
+    assert!(!input_is_newer(1023, 0));
+    assert!(!input_is_newer(5, 5));
+}
+
+#[test]
+fn welcomes_rejects_and_hands_out_ids() {
+    let (mut server, listener) = server();
+    let mut first = TestClient::connect(&listener);
+    let mut second = TestClient::connect(&listener);
+
+    let (_, first_id) = first.join(&mut server);
+    let (_, second_id) = second.join(&mut server);
+    assert_ne!(first_id, second_id);
+    assert_ne!(first_id, Id(0));
+
+    let mut outdated = TestClient::connect(&listener);
+    outdated.send_tcp(vec![CTcpType::Hello {
+        protocol_version: PROTOCOL_VERSION + 1,
+        build_id: "test".to_string(),
+        client_name: "old".to_string(),
+    }]);
+    tick(&mut server, 2);
+    assert!(matches!(outdated.receive_tcp()[..], [STcpType::Rejected { .. }]));
+}
+
+#[test]
+fn relays_chat_to_everyone_joined() {
+    let (mut server, listener) = server();
+    let mut first = TestClient::connect(&listener);
+    let mut second = TestClient::connect(&listener);
+    let (_, first_id) = first.join(&mut server);
+    second.join(&mut server);
+
+    first.send_tcp(vec![CTcpType::ChatMessage {
+        player_id: first_id,
+        message: ChatMessage { message: "hi".to_string() },
+    }]);
+    tick(&mut server, 2);
+
+    for client in [&mut first, &mut second] {
+        let relayed = client.receive_tcp().into_iter().find_map(|m| match m {
+            STcpType::Chat { messages } => Some(messages),
+            _ => None,
+        });
+        let relayed = relayed.expect("chat wasn't relayed");
+        assert_eq!(relayed[0].0, first_id);
+        assert_eq!(relayed[0].1.message, "hi");
+    }
+}
+
+#[test]
+fn stale_fragments_expire_before_their_id_comes_around_again() {
+    let (mut server, listener) = server();
+    let mut client = TestClient::connect(&listener);
+    let (_, id) = client.join(&mut server);
+
+    // Only the first part of a fragmented chat makes it
+    let chat = |text: &str| CUdpType::Control {
+        message: CTcpType::ChatMessage { player_id: id, message: ChatMessage { message: text.repeat(3000) } },
+    };
+    let packet = client.channel.build_packet(vec![chat("a")], Vec::new(), Duration::ZERO);
+    let bytes = bincode::serde::encode_to_vec(&packet, config::standard()).unwrap();
+    let datagrams = fragment_payload(&bytes, 1200, 0).unwrap();
+    assert!(datagrams.len() > 1);
+    client.transport.send_unreliable(datagrams[0].clone()).unwrap();
+    tick(&mut server, 60);
+
+    // A later message under the same id isn't mixed with what is left of the lost one
+    client.send_udp(vec![chat("b")]);
+    tick(&mut server, 2);
+    let relayed = client.receive_tcp().into_iter().find_map(|m| match m {
+        STcpType::Chat { messages } => Some(messages),
+        _ => None,
+    });
+    assert_eq!(relayed.expect("chat wasn't relayed")[0].1.message, "b".repeat(3000));
+}
+
+#[test]
+fn simulates_inputs_and_broadcasts_players() {
+    let (mut server, listener) = server();
+    let mut client = TestClient::connect(&listener);
+    let (token, id) = client.join(&mut server);
+
+    client.send_udp(vec![CUdpType::Connect { token }]);
+    tick(&mut server, 2);
+    assert!(client.receive_udp().iter().any(|m| matches!(m, SUdpType::SessionBound)));
+
+    let mut start = None;
+    let mut latest = None;
+    for sequence_number in 0..30 {
+        client.send_udp(vec![
+            CUdpType::Input { keymask: 1, mouse_delta: Vec2::ZERO, history: InputHistory::new() },
+            CUdpType::Sequence { sequence_number },
+        ]);
+        tick(&mut server, 1);
+
+        let messages = client.receive_udp();
+        let tagged = messages.iter().find_map(|m| match m {
+            SUdpType::Sequence { sequence_number } => Some(*sequence_number),
+            _ => None,
+        });
+        for m in messages {
+            if let SUdpType::Players { players, .. } = m {
+                let player = players[&id];
+                start.get_or_insert(player.position);
+                latest = Some((tagged, player.position));
+            }
+        }
+    }
+
+    let (tagged, position) = latest.expect("no snapshot received");
+    assert_eq!(tagged, Some(30));
+    // Forward is -z while facing the default yaw
+    assert!(position.z < start.unwrap().z - 0.1);
+}
@@ -1 +1,22 @@
-mod physics_test;
\ No newline at end of file
+mod physics_test;
+mod framing_test;
+mod connection_test;
+mod channel_test;
+mod fragment_test;
+mod conditioner_test;
+mod clock_test;
+mod dilation_test;
+mod reconciliation_test;
+mod snapshot_test;
+mod quantize_test;
+mod crypto_test;
+mod address_test;
+mod transport_test;
+mod mock_server_test;
+mod headless_test;
+mod capture_test;
+mod outbound_test;
+mod smoothing_test;
+mod rollback_test;
+mod stats_test;
+mod session_test;
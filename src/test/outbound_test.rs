@@ -0,0 +1,76 @@
+use crate::components::chat::ChatMessage;
+use crate::components::common::Id;
+use crate::network::net_channel::UdpChannel;
+use crate::network::net_message::{BitMask, CTcpType, CUdpType, InputHistory};
+use crate::network::net_outbound::OutboundQueue;
+use bevy::math::Vec2;
+use std::time::Duration;
+
+fn input(keymask: BitMask) -> CUdpType {
+    CUdpType::Input { keymask, mouse_delta: Vec2::ZERO, history: InputHistory::new() }
+}
+
+fn chat(text: &str) -> CTcpType {
+    CTcpType::ChatMessage { player_id: Id(1), message: ChatMessage { message: text.to_string() } }
+}
+
+#[test]
+fn stale_inputs_and_pings_are_coalesced() {
+    let mut queue = OutboundQueue::new(8);
+    queue.push(input(1));
+    queue.push(CUdpType::Ping { intitiation_time: 1, last_rtt: 0 });
+    queue.push(input(2));
+    queue.push(CUdpType::Ping { intitiation_time: 2, last_rtt: 0 });
+
+    let scheduled = queue.scheduled();
+    assert_eq!(scheduled.len(), 2);
+    assert!(matches!(scheduled[0], CUdpType::Input { keymask: 2, .. }));
+    assert!(matches!(scheduled[1], CUdpType::Ping { intitiation_time: 2, .. }));
+    assert_eq!(queue.take_counts(), (0, 2));
+    assert_eq!(queue.take_counts(), (0, 0));
+}
+
+#[test]
+fn input_goes_out_before_pings_and_chat() {
+    let mut queue = OutboundQueue::new(8);
+    queue.push(CUdpType::Control { message: chat("hi") });
+    queue.push(CUdpType::Ping { intitiation_time: 1, last_rtt: 0 });
+    queue.push(input(1));
+
+    let scheduled = queue.scheduled();
+    assert!(matches!(scheduled[0], CUdpType::Input { .. }));
+    assert!(matches!(scheduled[1], CUdpType::Ping { .. }));
+    assert!(matches!(scheduled[2], CUdpType::Control { .. }));
+}
+
+#[test]
+fn full_queue_drops_oldest_chat_and_keeps_the_rest() {
+    let mut queue = OutboundQueue::new(3);
+    queue.push(chat("first"));
+    queue.push(CTcpType::Join { lobby_id: Id(1) });
+    queue.push(chat("second"));
+    queue.push(chat("third"));
+    queue.push(CTcpType::Leave);
+
+    let scheduled = queue.scheduled();
+    assert_eq!(scheduled.len(), 3);
+    assert!(matches!(scheduled[0], CTcpType::Join { .. }));
+    assert!(matches!(scheduled[1], CTcpType::Leave));
+    assert!(matches!(&scheduled[2], CTcpType::ChatMessage { message, .. } if message.message == "third"));
+    assert_eq!(queue.take_counts(), (2, 0));
+}
+
+#[test]
+fn retracted_packet_reuses_its_sequence() {
+    let mut channel: UdpChannel<u32, u32> = UdpChannel::new();
+    let sent = channel.build_packet(vec![], vec![], Duration::ZERO);
+    let unsent = channel.build_packet(vec![], vec![], Duration::ZERO);
+    channel.retract_packet(unsent.header.sequence);
+
+    let next = channel.build_packet(vec![], vec![], Duration::ZERO);
+    assert_eq!(next.header.sequence, unsent.header.sequence);
+
+    // Only the packet built last can be taken back
+    channel.retract_packet(sent.header.sequence);
+    assert_eq!(channel.build_packet(vec![], vec![], Duration::ZERO).header.sequence, next.header.sequence.wrapping_add(1));
+}
@@ -0,0 +1,110 @@
+use crate::components::common::{Id, Vec3};
+use crate::components::player::animation::AnimationState;
+use crate::components::player::{Player, PlayerInfo};
+use crate::network::net_message::{SUdpType, CUdpType, InputFrame, InputHistory};
+use crate::network::net_quantize::{quantize_player, BitReader, BitWriter, QUANTIZATION};
+use crate::network::net_snapshot::{PlayerDelta, SnapshotDelta};
+use bevy::math::Vec2;
+use bincode::config;
+use std::collections::HashMap;
+
+fn player() -> Player {
+    Player {
+        position: Vec3::new(12.345, 1.5, -7.25),
+        linear_velocity: Vec3::new(3.3, -9.81, 0.01),
+        yaw: -2.5,
+        pitch: 0.3,
+        animation_state: AnimationState::Walking,
+    }
+}
+
+fn round_trip<T: serde::Serialize + serde::de::DeserializeOwned>(value: &T) -> (T, usize) {
+    let bytes = bincode::serde::encode_to_vec(value, config::standard()).unwrap();
+    let (decoded, _) = bincode::serde::decode_from_slice(&bytes, config::standard()).unwrap();
+    (decoded, bytes.len())
+}
+
+#[test]
+fn bits_round_trip() {
+    let mut writer = BitWriter::default();
+    writer.write(5, 3);
+    writer.write(0xABCD, 16);
+    writer.write(1, 1);
+    let bytes = writer.finish();
+    assert_eq!(bytes.len(), 3);
+
+    let mut reader = BitReader::new(&bytes);
+    assert_eq!(reader.read(3).unwrap(), 5);
+    assert_eq!(reader.read(16).unwrap(), 0xABCD);
+    assert_eq!(reader.read(1).unwrap(), 1);
+    assert!(reader.read(8).is_err());
+}
+
+#[test]
+fn quantized_player_is_close_and_stable() {
+    let original = player();
+    let quantized = quantize_player(&original);
+
+    assert!((quantized.position.x - original.position.x).abs() < 0.001);
+    assert!((quantized.linear_velocity.y - original.linear_velocity.y).abs() <= QUANTIZATION.velocity_precision);
+    assert!((quantized.yaw - original.yaw).abs() < 0.001);
+    assert!((quantized.pitch - original.pitch).abs() < 0.001);
+    assert_eq!(quantized.animation_state, original.animation_state);
+
+    // Quantizing twice changes nothing, so client and server agree exactly
+    let again = quantize_player(&quantized);
+    assert_eq!(again.position.x.to_bits(), quantized.position.x.to_bits());
+    assert_eq!(again.yaw.to_bits(), quantized.yaw.to_bits());
+}
+
+#[test]
+fn snapshots_decode_to_quantized_players() {
+    let players = HashMap::from([(Id(1), player()), (Id(2), Player::default())]);
+    let (decoded, len) = round_trip(&SUdpType::Players { snapshot: 4, players: players.clone() });
+    let SUdpType::Players { players: decoded, .. } = decoded else {
+        panic!("decoded the wrong variant");
+    };
+
+    assert_eq!(decoded[&Id(1)], quantize_player(&players[&Id(1)]));
+    assert!(len < 2 * (4 + 3 * 4 * 2 + 4 * 2 + 1));
+
+    let delta = SnapshotDelta {
+        changed: HashMap::from([(Id(1), PlayerDelta { yaw: Some(1.0), ..Default::default() })]),
+        removed: vec![Id(2)],
+    };
+    let (decoded, _) = round_trip(&delta);
+    assert!(decoded.changed[&Id(1)].position.is_none());
+    assert!((decoded.changed[&Id(1)].yaw.unwrap() - 1.0).abs() < 0.001);
+    assert_eq!(decoded.removed, vec![Id(2)]);
+}
+
+#[test]
+fn input_mouse_delta_is_fixed_point() {
+    let mouse_delta = QUANTIZATION.quantize_mouse_delta(Vec2::new(1.23, -4.56));
+    let input = CUdpType::Input {
+        keymask: 5,
+        mouse_delta,
+        history: InputHistory::from_slice(&[InputFrame { sequence_number: 3, keymask: 1, mouse_delta }]),
+    };
+
+    let (CUdpType::Input { mouse_delta: decoded, history, .. }, _) = round_trip(&input) else {
+        panic!("decoded the wrong variant");
+    };
+    assert_eq!(decoded, mouse_delta);
+    assert_eq!(history[0].mouse_delta, mouse_delta);
+}
+
+#[test]
+fn mouse_motion_turns_the_camera_in_whole_steps() {
+    let mut player_info = PlayerInfo::default();
+    let mut turned = Vec2::ZERO;
+    for _ in 0..10 {
+        player_info.record_mouse_delta(Vec2::new(0.01, -0.05));
+        assert_eq!(player_info.mouse_delta, QUANTIZATION.quantize_mouse_delta(player_info.mouse_delta));
+        turned += player_info.mouse_delta;
+    }
+
+    // Motion below a step isn't lost, it turns the camera once enough of it adds up
+    assert!((turned - Vec2::new(0.1, -0.5)).abs().max_element() <= QUANTIZATION.mouse_precision / 2.0);
+    assert_eq!(QUANTIZATION.quantize_mouse_delta(player_info.accumulated_mouse_delta), player_info.accumulated_mouse_delta);
+}
@@ -0,0 +1,100 @@
+use crate::components::common::Vec3;
+use crate::components::player::Player;
+use crate::network::net_message::INPUT_HISTORY_LEN;
+use crate::network::net_reconciliation::{Divergence, ReconcileBuffer, ReconcilePolicy, ReconcileTrigger, TickRecord, BUFFER_SIZE};
+use bevy::math::Vec2;
+use std::f32::consts::PI;
+
+/// Runs the buffer up to `sequence_counter`, recording an input on each of `sequences` on the way.
+fn buffer_with_inputs(sequences: impl IntoIterator<Item = u16>, sequence_counter: u16) -> ReconcileBuffer {
+    let mut buffer = ReconcileBuffer::default();
+    let mut sequences = sequences.into_iter().peekable();
+    while sequences.peek().is_some() || buffer.sequence_counter != sequence_counter {
+        let sequence = buffer.sequence_counter;
+        if sequences.next_if_eq(&sequence).is_some() {
+            buffer.record(sequence, TickRecord {
+                keymask: sequence,
+                mouse_delta: Vec2::splat(sequence as f32),
+                ..Default::default()
+            });
+        }
+        buffer.increment_sequence_num();
+    }
+    buffer
+}
+
+#[test]
+fn recent_inputs_are_newest_first_across_wrap() {
+    let buffer = buffer_with_inputs([BUFFER_SIZE - 2, BUFFER_SIZE - 1, 0, 1], 2);
+    let sequences: Vec<u16> = buffer.recent_inputs(3).iter().map(|f| f.sequence_number).collect();
+    assert_eq!(sequences, vec![1, 0, BUFFER_SIZE - 1]);
+
+    let newest = buffer.recent_inputs(1)[0];
+    assert_eq!(newest.keymask, 1);
+    assert_eq!(newest.mouse_delta, Vec2::ONE);
+}
+
+#[test]
+fn recent_inputs_stop_at_missing_ticks() {
+    let buffer = buffer_with_inputs([5, 6], 7);
+    assert_eq!(buffer.recent_inputs(10).len(), 2);
+    assert!(buffer.recent_inputs(0).is_empty());
+}
+
+#[test]
+fn recent_inputs_stay_inline() {
+    let buffer = buffer_with_inputs(0..20, 20);
+    let history = buffer.recent_inputs(20);
+    assert_eq!(history.len(), INPUT_HISTORY_LEN);
+    assert!(!history.spilled());
+}
+
+#[test]
+fn recent_inputs_skip_ticks_from_an_earlier_lap() {
+    let mut buffer = buffer_with_inputs([5, 6], 7);
+    for _ in 0..BUFFER_SIZE {
+        buffer.increment_sequence_num();
+    }
+    assert_eq!(buffer.sequence_counter, 7);
+    assert!(buffer.recent_inputs(3).is_empty());
+    assert!(buffer.get(6).is_none());
+}
+
+#[test]
+fn ticks_share_a_slot_a_lap_apart() {
+    let mut buffer = buffer_with_inputs([3], 4);
+    buffer.record(3 + BUFFER_SIZE, TickRecord { keymask: 9, ..Default::default() });
+    assert_eq!(buffer.get(3).map(|t| t.keymask), Some(9));
+
+    buffer.get_mut(3).unwrap().confirmed = true;
+    assert!(buffer.get(3 + BUFFER_SIZE).unwrap().confirmed);
+
+    buffer.clear();
+    assert!(buffer.get(3).is_none());
+}
+
+#[test]
+fn divergence_reports_only_fields_past_their_tolerance() {
+    let policy = ReconcilePolicy::default();
+    let server = Player { position: Vec3::new(1.0, 0.0, 0.0), yaw: PI, ..Default::default() };
+    let predicted = Player { position: Vec3::new(1.005, 0.0, 0.0), yaw: -PI, pitch: 0.1, ..Default::default() };
+
+    // Yaw a full turn apart is the same heading, the position is within tolerance
+    let divergence = policy.divergence(&server, &predicted);
+    assert_eq!(divergence.position, None);
+    assert_eq!(divergence.yaw, None);
+    assert!(divergence.pitch.is_some_and(|e| (e - 0.1).abs() < 1e-6));
+    assert!(!divergence.is_empty());
+    assert!(policy.divergence(&server, &server).is_empty());
+    assert_eq!(Divergence::default(), policy.divergence(&predicted, &predicted));
+}
+
+#[test]
+fn trigger_sets_the_misses_it_takes() {
+    let mut policy = ReconcilePolicy { trigger: ReconcileTrigger::Immediate, ..Default::default() };
+    assert_eq!(policy.misses_to_reconcile(), 1);
+    policy.trigger = ReconcileTrigger::AfterMisses(0);
+    assert_eq!(policy.misses_to_reconcile(), 1);
+    policy.trigger = ReconcileTrigger::AfterMisses(5);
+    assert_eq!(policy.misses_to_reconcile(), 5);
+}
@@ -0,0 +1,146 @@
+use crate::components::camera::CameraInfo;
+use crate::components::common::{self, Id};
+use crate::components::player::animation::{AnimationState, PlayerAnimationState};
+use crate::components::player::{Player, PlayerInfo, PlayerMarker, ResimulatePlayer};
+use crate::network::net_quantize::quantize_player;
+use crate::network::net_reconciliation::{ReconcileBuffer, TickRecord, BUFFER_SIZE};
+use crate::network::net_rollback::{restore_rollback_components, save_rollback_components, ResimulateSchedule, RollbackAppExt, RollbackHistory, Rollback};
+use avian3d::prelude::{LinearVelocity, Position, Rotation};
+use avian3d::PhysicsPlugins;
+use bevy::prelude::*;
+use bevy::render::mesh::MeshPlugin;
+use bevy::scene::ScenePlugin;
+
+#[derive(Component, Clone, Debug, PartialEq)]
+struct Health(u32);
+
+#[derive(Component, Clone, Debug, PartialEq)]
+struct Ammo(u32);
+
+fn rollback_app() -> App {
+    let mut app = App::new();
+    app.init_resource::<ReconcileBuffer>();
+    app.register_rollback_component::<Health>().register_rollback_component::<Ammo>();
+    app
+}
+
+#[test]
+fn registered_components_on_marked_entities_roll_back() {
+    let mut app = rollback_app();
+    let world = app.world_mut();
+    let marked = world.spawn((Rollback, Health(100), Ammo(30))).id();
+    let unmarked = world.spawn(Health(100)).id();
+
+    world.resource_mut::<ReconcileBuffer>().sequence_counter = 7;
+    save_rollback_components(world);
+
+    *world.get_mut::<Health>(marked).unwrap() = Health(40);
+    *world.get_mut::<Ammo>(marked).unwrap() = Ammo(2);
+    *world.get_mut::<Health>(unmarked).unwrap() = Health(40);
+
+    restore_rollback_components(world, 7);
+    assert_eq!(world.get::<Health>(marked), Some(&Health(100)));
+    assert_eq!(world.get::<Ammo>(marked), Some(&Ammo(30)));
+    assert_eq!(world.get::<Health>(unmarked), Some(&Health(40)));
+}
+
+#[test]
+fn history_is_indexed_like_the_reconcile_buffer() {
+    let mut app = rollback_app();
+    // Registering twice keeps a single history
+    app.register_rollback_component::<Health>();
+    let world = app.world_mut();
+    let entity = world.spawn((Rollback, Health(1))).id();
+
+    world.resource_mut::<ReconcileBuffer>().sequence_counter = 3;
+    save_rollback_components(world);
+    let history = world.resource::<RollbackHistory<Health>>();
+    assert_eq!(history.get(3, 0), Some(&[(entity, Health(1))][..]));
+    assert_eq!(history.get(3 + BUFFER_SIZE, 0), history.get(3, 0));
+
+    // Entities gone since are skipped
+    world.despawn(entity);
+    restore_rollback_components(world, 3);
+    assert!(world.get_entity(entity).is_err());
+}
+
+#[test]
+fn slots_from_an_earlier_lap_are_not_restored() {
+    let mut app = rollback_app();
+    let world = app.world_mut();
+    let entity = world.spawn((Rollback, Health(100))).id();
+    save_rollback_components(world);
+
+    for _ in 0..BUFFER_SIZE {
+        world.resource_mut::<ReconcileBuffer>().increment_sequence_num();
+    }
+    *world.get_mut::<Health>(entity).unwrap() = Health(40);
+    restore_rollback_components(world, 0);
+    assert_eq!(world.get::<Health>(entity), Some(&Health(40)));
+}
+
+fn bleed(mut health: Query<&mut Health>) {
+    for mut health in health.iter_mut() {
+        health.0 -= 1;
+    }
+}
+
+#[test]
+fn resimulating_saves_where_the_live_tick_does() {
+    let mut app = App::new();
+    app.add_plugins((MinimalPlugins, TransformPlugin, AssetPlugin::default(), MeshPlugin, ScenePlugin, PhysicsPlugins::default()));
+    app.init_resource::<ReconcileBuffer>();
+    app.insert_resource(PlayerInfo { current_player_id: Id(1), ..Default::default() });
+    app.register_rollback_component::<Health>();
+    app.add_systems(ResimulateSchedule, bleed);
+    app.finish();
+    app.cleanup();
+
+    let world = app.world_mut();
+    let player = world.spawn((
+        PlayerMarker,
+        Id(1),
+        Rollback,
+        Position::default(),
+        LinearVelocity::default(),
+        Rotation::default(),
+        CameraInfo { yaw: 0.0, pitch: 0.0 },
+        PlayerAnimationState(AnimationState::Idle),
+        Health(100),
+    )).id();
+    // Remote players carry the marker too
+    let remote = world.spawn((
+        PlayerMarker,
+        Id(2),
+        Position(Vec3::new(5.0, 0.0, 0.0)),
+        LinearVelocity::default(),
+        Rotation::default(),
+        CameraInfo { yaw: 0.0, pitch: 0.0 },
+        PlayerAnimationState(AnimationState::Idle),
+    )).id();
+
+    // Live ticks 0 to 5, each recording its input and running its logic before the save
+    for sequence in 0..=5 {
+        if sequence > 0 {
+            world.resource_mut::<ReconcileBuffer>().increment_sequence_num();
+        }
+        world.resource_mut::<ReconcileBuffer>().record(sequence, TickRecord::default());
+        world.run_schedule(ResimulateSchedule);
+        save_rollback_components(world);
+    }
+    assert_eq!(world.get::<Health>(player), Some(&Health(94)));
+
+    let server_state = Player { position: common::Vec3::new(1.0, 2.0, 3.0), ..Default::default() };
+    ResimulatePlayer { received_sequence_number: 2, server_state }.apply(world);
+
+    // Back to tick 2 and through 3 to 5 again, every slot as the live tick left it
+    assert_eq!(world.get::<Health>(player), Some(&Health(94)));
+    let history = world.resource::<RollbackHistory<Health>>();
+    for sequence in 3..=5u16 {
+        assert_eq!(history.get(sequence, sequence as u32), Some(&[(player, Health(99 - sequence as u32))][..]));
+    }
+
+    let expected = quantize_player(&server_state).position;
+    assert_eq!(world.get::<Position>(player).unwrap().0, Vec3::new(expected.x, expected.y, expected.z));
+    assert_eq!(world.get::<Position>(remote).unwrap().0, Vec3::new(5.0, 0.0, 0.0));
+}
@@ -0,0 +1,143 @@
+use crate::components::player::{PlayerInfo, PredictionCheck};
+use crate::network::net_channel::{UdpChannel, UdpPacket};
+use crate::network::net_fragment::{fragment_payload, Reassembler};
+use crate::network::net_manage::{Communication, TcpConnection, UdpConnection};
+use crate::network::net_message::{CUdpType, STcpType, SUdpType};
+use crate::network::net_session::{prove_udp_session, UdpSession, CONNECT_RESEND_INTERVAL};
+use crate::network::net_system::{tcp_client_net_receive, udp_client_net_receive, udp_client_net_send};
+use crate::network::net_tasks::{handle_tcp_message, handle_udp_message};
+use crate::network::net_transport::{LoopbackTransport, Transport};
+use crate::network::NetworkStatePlugin;
+use bevy::prelude::{App, IntoScheduleConfigs, Real, Time, Update};
+use bincode::config;
+use std::time::Duration;
+
+/// Plays the server's side of the session over the other end of the client's transport.
+struct Server {
+    transport: LoopbackTransport,
+    channel: UdpChannel<SUdpType, CUdpType>,
+    reassembler: Reassembler,
+}
+
+impl Server {
+    fn send_tcp(&mut self, message: STcpType) {
+        let bytes = bincode::serde::encode_to_vec(vec![message], config::standard()).unwrap();
+        self.transport.send_reliable(bytes).unwrap();
+    }
+
+    fn send_udp(&mut self, message: SUdpType) {
+        let packet = self.channel.build_packet(vec![message], Vec::new(), Duration::ZERO);
+        let bytes = bincode::serde::encode_to_vec(&packet, config::standard()).unwrap();
+        for datagram in fragment_payload(&bytes, 1200, 0).unwrap() {
+            self.transport.send_unreliable(datagram).unwrap();
+        }
+    }
+
+    /// Tokens of the `Connect` messages received since the last call.
+    fn connects(&mut self) -> Vec<u64> {
+        let mut tokens = Vec::new();
+        while let Some(datagram) = self.transport.receive_unreliable() {
+            let Some(bytes) = self.reassembler.push(&datagram, Duration::ZERO).unwrap() else {
+                continue;
+            };
+            let (packet, _): (UdpPacket<CUdpType>, usize) = bincode::serde::decode_from_slice(&bytes, config::standard()).unwrap();
+            for message in self.channel.receive_packet(packet, Duration::ZERO).unwrap() {
+                if let CUdpType::Connect { token } = message {
+                    tokens.push(token);
+                }
+            }
+        }
+        tokens
+    }
+}
+
+/// The client's session handling from the TCP token to the UDP answer, with the sockets up.
+fn client() -> (App, Server) {
+    let (client, server) = LoopbackTransport::pair();
+
+    let mut app = App::new();
+    app.add_plugins(NetworkStatePlugin);
+    app.init_resource::<PlayerInfo>();
+    app.init_resource::<PredictionCheck>();
+    app.init_resource::<Time<Real>>();
+    app.insert_resource(Communication::new(client));
+    app.world_mut().resource_mut::<TcpConnection>().ready = true;
+    app.world_mut().resource_mut::<UdpConnection>().ready = true;
+    app.add_systems(
+        Update,
+        (
+            tcp_client_net_receive,
+            handle_tcp_message,
+            prove_udp_session,
+            udp_client_net_send,
+            udp_client_net_receive,
+            handle_udp_message,
+        ).chain(),
+    );
+
+    // The first run only starts the clock
+    advance(&mut app, Duration::ZERO);
+    let server = Server { transport: server, channel: UdpChannel::new(), reassembler: Reassembler::default() };
+    (app, server)
+}
+
+fn advance(app: &mut App, by: Duration) {
+    app.world_mut().resource_mut::<Time<Real>>().update_with_duration(by);
+    app.update();
+}
+
+fn session(app: &App) -> UdpSession {
+    app.world().resource::<UdpConnection>().session
+}
+
+#[test]
+fn token_is_proven_until_the_server_binds_it() {
+    let (mut app, mut server) = client();
+
+    server.send_tcp(STcpType::SessionToken { token: 42 });
+    advance(&mut app, Duration::from_millis(10));
+    assert_eq!(server.connects(), vec![42]);
+
+    // Repeated every interval, not every frame
+    advance(&mut app, CONNECT_RESEND_INTERVAL / 2);
+    assert!(server.connects().is_empty());
+    advance(&mut app, CONNECT_RESEND_INTERVAL / 2);
+    assert_eq!(server.connects(), vec![42]);
+
+    server.send_udp(SUdpType::SessionBound);
+    advance(&mut app, Duration::from_millis(10));
+    assert_eq!(session(&app), UdpSession::Bound);
+
+    for _ in 0..5 {
+        advance(&mut app, CONNECT_RESEND_INTERVAL);
+    }
+    assert!(server.connects().is_empty());
+}
+
+#[test]
+fn a_token_the_server_wont_take_gives_way_to_a_new_one() {
+    let (mut app, mut server) = client();
+
+    // A stray answer before there is anything to prove binds nothing
+    server.send_udp(SUdpType::SessionBound);
+    advance(&mut app, Duration::from_millis(10));
+    assert_eq!(session(&app), UdpSession::Unbound);
+
+    // Rejected or expired, the token goes unanswered and the client keeps at it
+    server.send_tcp(STcpType::SessionToken { token: 1 });
+    for _ in 0..4 {
+        advance(&mut app, CONNECT_RESEND_INTERVAL);
+    }
+    assert_eq!(server.connects(), vec![1; 4]);
+    assert!(matches!(session(&app), UdpSession::Proving { token: 1, .. }));
+
+    // A reissued token is proven right away instead of waiting out the interval
+    advance(&mut app, CONNECT_RESEND_INTERVAL / 2);
+    server.send_tcp(STcpType::SessionToken { token: 2 });
+    advance(&mut app, Duration::from_millis(1));
+    assert_eq!(server.connects(), vec![2]);
+
+    server.send_udp(SUdpType::SessionBound);
+    advance(&mut app, Duration::from_millis(10));
+    assert_eq!(session(&app), UdpSession::Bound);
+}
@@ -0,0 +1,54 @@
+use crate::components::common::Id;
+use crate::components::player::smoothing::{record_correction, CorrectionOffset, ErrorSmoothing, SmoothingBlend};
+use crate::components::player::{PlayerInfo, PlayerMarker};
+use avian3d::prelude::Position;
+use bevy::math::Vec3;
+use bevy::prelude::World;
+
+#[test]
+fn time_blend_closes_the_offset_on_time() {
+    let smoothing = ErrorSmoothing { blend: SmoothingBlend::Time(0.2), snap_distance: 2.0 };
+    let mut correction = CorrectionOffset::default();
+    correction.correct(Vec3::new(1.0, 0.0, 0.0), Vec3::ZERO, &smoothing);
+    assert_eq!(correction.offset, Vec3::new(1.0, 0.0, 0.0));
+
+    correction.decay(0.1, &smoothing);
+    assert!((correction.offset.x - 0.5).abs() < 1e-5);
+    correction.decay(0.1, &smoothing);
+    assert_eq!(correction.offset, Vec3::ZERO);
+}
+
+#[test]
+fn distance_blend_closes_at_a_fixed_speed() {
+    let smoothing = ErrorSmoothing { blend: SmoothingBlend::Distance(1.0), snap_distance: 2.0 };
+    let mut correction = CorrectionOffset::default();
+    correction.correct(Vec3::ZERO, Vec3::new(0.0, 0.0, 1.5), &smoothing);
+
+    correction.decay(1.0, &smoothing);
+    assert!((correction.offset.z + 0.5).abs() < 1e-5);
+    correction.decay(1.0, &smoothing);
+    assert_eq!(correction.offset, Vec3::ZERO);
+}
+
+#[test]
+fn large_corrections_snap() {
+    let smoothing = ErrorSmoothing::default();
+    let mut correction = CorrectionOffset::default();
+    correction.correct(Vec3::new(0.5, 0.0, 0.0), Vec3::ZERO, &smoothing);
+    correction.correct(Vec3::new(smoothing.snap_distance, 0.0, 0.0), Vec3::ZERO, &smoothing);
+    assert_eq!(correction.offset, Vec3::ZERO);
+}
+
+#[test]
+fn corrections_follow_the_local_player_among_remote_ones() {
+    let mut world = World::new();
+    world.insert_resource(PlayerInfo { current_player_id: Id(2), ..Default::default() });
+    world.init_resource::<ErrorSmoothing>();
+    world.init_resource::<CorrectionOffset>();
+    world.spawn((PlayerMarker, Id(1), Position(Vec3::new(5.0, 0.0, 0.0))));
+    world.spawn((PlayerMarker, Id(2), Position(Vec3::new(0.0, 0.0, 1.0))));
+    world.spawn((PlayerMarker, Id(3), Position(Vec3::new(-5.0, 0.0, 0.0))));
+
+    record_correction(&mut world, Some(Vec3::new(0.0, 0.0, 1.5)));
+    assert_eq!(world.resource::<CorrectionOffset>().offset, Vec3::new(0.0, 0.0, 0.5));
+}
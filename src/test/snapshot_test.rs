@@ -0,0 +1,49 @@
+use crate::components::common::{Id, Vec3};
+use crate::components::player::Player;
+use crate::network::net_snapshot::{PlayerDelta, SnapshotBuffer, SnapshotDelta, SNAPSHOT_RING};
+use std::collections::HashMap;
+
+fn player(x: f32, yaw: f32) -> Player {
+    Player {
+        position: Vec3 { x, y: 1.0, z: 2.0 },
+        yaw,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn delta_applies_only_what_changed() {
+    let baseline = HashMap::from([(Id(1), player(0.0, 0.0)), (Id(2), player(5.0, 1.0)), (Id(3), player(9.0, 0.0))]);
+    let delta = SnapshotDelta {
+        changed: HashMap::from([
+            (Id(1), PlayerDelta { position: Some(Vec3 { x: 0.5, y: 1.0, z: 2.0 }), ..Default::default() }),
+            (Id(4), PlayerDelta { position: Some(Vec3 { x: 3.0, y: 1.0, z: 2.0 }), yaw: Some(2.0), ..Default::default() }),
+        ]),
+        removed: vec![Id(3)],
+    };
+
+    let rebuilt = delta.apply(&baseline);
+    let expected = HashMap::from([(Id(1), player(0.5, 0.0)), (Id(2), player(5.0, 1.0)), (Id(4), player(3.0, 2.0))]);
+    assert_eq!(rebuilt, expected);
+}
+
+#[test]
+fn rebuild_needs_a_stored_baseline() {
+    let mut snapshots = SnapshotBuffer::default();
+    let first = HashMap::from([(Id(1), player(0.0, 0.0))]);
+    snapshots.insert(10, first.clone());
+
+    let delta = SnapshotDelta {
+        changed: HashMap::from([(Id(1), PlayerDelta { yaw: Some(0.5), ..Default::default() })]),
+        removed: Vec::new(),
+    };
+    assert_eq!(snapshots.rebuild(11, 10, &delta).unwrap(), HashMap::from([(Id(1), player(0.0, 0.5))]));
+    assert_eq!(snapshots.latest, Some(11));
+    assert!(snapshots.get(11).is_some());
+
+    assert!(snapshots.rebuild(12, 9, &delta).is_err());
+
+    // Overwritten once the ring wraps around
+    snapshots.insert(10 + SNAPSHOT_RING as u16, first);
+    assert!(snapshots.get(10).is_none());
+}
@@ -0,0 +1,78 @@
+use crate::network::net_manage::UdpConnection;
+use crate::network::net_stats::{sample_net_stats, NetStats, SAMPLE_INTERVAL};
+use bevy::prelude::{App, Real, Time, Update};
+use std::time::Duration;
+
+#[test]
+fn rtt_and_jitter_are_smoothed() {
+    let mut stats = NetStats::default();
+
+    // The first measurement is taken as it is
+    stats.record_rtt(100.0);
+    assert_eq!(stats.smoothed_rtt_ms, 100.0);
+    assert_eq!(stats.jitter_ms, 0.0);
+
+    stats.record_rtt(200.0);
+    assert!((stats.smoothed_rtt_ms - 110.0).abs() < 1e-4);
+    assert!((stats.jitter_ms - 10.0).abs() < 1e-4);
+
+    // Jitter follows the change between measurements, not their distance to the average
+    stats.record_rtt(150.0);
+    assert!((stats.smoothed_rtt_ms - 114.0).abs() < 1e-4);
+    assert!((stats.jitter_ms - 14.0).abs() < 1e-4);
+}
+
+/// Moves real time on by `by` and runs the app. The first call only starts the clock.
+fn advance(app: &mut App, by: Duration) {
+    app.world_mut().resource_mut::<Time<Real>>().update_with_duration(by);
+    app.update();
+}
+
+#[test]
+fn samples_rates_and_loss_over_the_interval() {
+    let mut app = App::new();
+    app.insert_resource(Time::<Real>::default());
+    app.insert_resource(UdpConnection::new());
+    app.init_resource::<NetStats>();
+    app.add_systems(Update, sample_net_stats);
+
+    // The first run only takes note of the counters
+    advance(&mut app, Duration::ZERO);
+    assert!(app.world().resource::<NetStats>().history.is_empty());
+
+    let mut stats = app.world_mut().resource_mut::<NetStats>();
+    stats.udp.record_in(600);
+    stats.udp.record_in(400);
+    stats.udp.record_out(250);
+    stats.tcp.record_in(50);
+    stats.tcp.record_out(100);
+    stats.snapshots_received += 30;
+    stats.record_rtt(80.0);
+    let mut connection = app.world_mut().resource_mut::<UdpConnection>();
+    connection.channel.packets_received = 27;
+    connection.channel.packets_missing = 3;
+
+    // Nothing until a whole interval went by
+    advance(&mut app, SAMPLE_INTERVAL / 2);
+    assert!(app.world().resource::<NetStats>().history.is_empty());
+
+    advance(&mut app, SAMPLE_INTERVAL * 3 / 2);
+    let stats = app.world().resource::<NetStats>();
+    assert_eq!(stats.history.len(), 1);
+    let sample = stats.history[0];
+    // 500ms since the first run
+    assert_eq!(sample.udp_in_bytes, 2000.0);
+    assert_eq!(sample.udp_out_bytes, 500.0);
+    assert_eq!(sample.tcp_in_bytes, 100.0);
+    assert_eq!(sample.tcp_out_bytes, 200.0);
+    assert_eq!(sample.snapshot_rate, 60.0);
+    assert!((sample.loss - 0.1).abs() < 1e-6);
+    assert_eq!(sample.rtt_ms, 80.0);
+
+    // A quiet interval keeps the last loss estimate
+    advance(&mut app, SAMPLE_INTERVAL);
+    let stats = app.world().resource::<NetStats>();
+    assert_eq!(stats.history.len(), 2);
+    assert_eq!(stats.history[1].udp_in_bytes, 0.0);
+    assert!((stats.history[1].loss - 0.1).abs() < 1e-6);
+}
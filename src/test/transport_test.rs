@@ -0,0 +1,125 @@
+use crate::network::net_conditioner::{run_conditioner, LinkChannel, LinkConditioner, LinkProfile};
+use crate::network::net_framing::FrameDecoder;
+use crate::network::net_manage::{start_tcp_task, LinkEvent};
+use crate::network::net_transport::{FlushRequest, LoopbackTransport, SocketTransport, Transport, FLUSH_TIMEOUT};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+
+#[test]
+fn loopback_is_ready_from_the_start() {
+    let (mut client, _server) = LoopbackTransport::pair();
+    assert_eq!(client.poll_event(), Some(LinkEvent::ReliableReady));
+    assert_eq!(client.poll_event(), Some(LinkEvent::UnreliableReady));
+    assert_eq!(client.poll_event(), None);
+}
+
+#[test]
+fn loopback_keeps_reliable_and_unreliable_apart() {
+    let (mut client, mut server) = LoopbackTransport::pair();
+
+    client.send_reliable(b"hello".to_vec()).unwrap();
+    client.send_unreliable(b"input 1".to_vec()).unwrap();
+    client.send_unreliable(b"input 2".to_vec()).unwrap();
+    server.send_reliable(b"welcome".to_vec()).unwrap();
+
+    assert_eq!(server.receive_reliable(), Some(b"hello".to_vec()));
+    assert_eq!(server.receive_reliable(), None);
+    assert_eq!(server.receive_unreliable(), Some(b"input 1".to_vec()));
+    assert_eq!(server.receive_unreliable(), Some(b"input 2".to_vec()));
+    assert_eq!(client.receive_reliable(), Some(b"welcome".to_vec()));
+    assert_eq!(client.receive_unreliable(), None);
+}
+
+#[test]
+fn loopback_reports_close_after_pending_payloads() {
+    let (mut client, mut server) = LoopbackTransport::pair();
+    while client.poll_event().is_some() {}
+
+    server.send_reliable(b"bye".to_vec()).unwrap();
+    drop(server);
+
+    assert_eq!(client.poll_event(), None);
+    assert_eq!(client.receive_reliable(), Some(b"bye".to_vec()));
+    assert_eq!(client.poll_event(), Some(LinkEvent::ReliableClosed));
+    assert_eq!(client.poll_event(), None);
+    assert!(client.send_reliable(b"anyone?".to_vec()).is_err());
+}
+
+/// A socket transport writing to a local stream through a conditioner with `tcp` applied, and
+/// the server end of that stream.
+async fn conditioned_transport(tcp: LinkProfile) -> (SocketTransport, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let stream = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let (server, _) = listener.accept().await.unwrap();
+
+    let (tcp_send_tx, tcp_send_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_socket_send_tx, tcp_socket_send_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_receive_tx, tcp_receive_rx) = mpsc::channel::<(Vec<u8>, Arc<TcpStream>)>(1_000);
+    let (tcp_flush_tx, tcp_flush_rx) = mpsc::channel::<FlushRequest>(1);
+    let (status_tx, status_rx) = mpsc::channel::<LinkEvent>(16);
+    let (udp_tx, _) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1);
+    let (_, udp_rx) = mpsc::channel::<(Vec<u8>, SocketAddr)>(1);
+
+    let (_settings, conditioner) = watch::channel(LinkConditioner { enabled: true, tcp, ..Default::default() });
+    tokio::spawn(run_conditioner(tcp_send_rx, tcp_socket_send_tx, conditioner, LinkChannel::Tcp, |m| m.0.is_empty()));
+    start_tcp_task(stream, tcp_socket_send_rx, tcp_flush_rx, tcp_receive_tx, status_tx);
+    let mut transport = SocketTransport::new(udp_tx, udp_rx, tcp_send_tx, tcp_receive_rx, tcp_flush_tx, status_rx);
+
+    while transport.poll_event() != Some(LinkEvent::ReliableReady) {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+    (transport, server)
+}
+
+/// Reads frames off `server` up to and including `last`.
+async fn frames_until(server: &mut TcpStream, last: &[u8]) -> Vec<Vec<u8>> {
+    let mut decoder = FrameDecoder::new();
+    let mut frames = Vec::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    while frames.last().is_none_or(|f: &Vec<u8>| f != last) {
+        let n = server.read(&mut buf).await.unwrap();
+        assert_ne!(n, 0, "stream closed before the last frame");
+        decoder.push(&buf[..n]);
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame);
+        }
+    }
+    frames
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reliable_now_goes_out_after_what_was_queued() {
+    // Queued payloads take a while to reach the writer, the flush has to wait for them
+    let (mut transport, mut server) = conditioned_transport(LinkProfile { latency_ms: 50.0, ..Default::default() }).await;
+
+    let queued: Vec<Vec<u8>> = (0..64u8).map(|i| vec![i; 16 * 1024]).collect();
+    for payload in queued.iter() {
+        transport.send_reliable(payload.clone()).unwrap();
+    }
+    let sent = tokio::task::spawn_blocking(move || transport.send_reliable_now(b"leave".to_vec()));
+    let frames = frames_until(&mut server, b"leave").await;
+
+    sent.await.unwrap().unwrap();
+    assert_eq!(frames.len(), queued.len() + 1);
+    assert_eq!(&frames[..queued.len()], &queued[..]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reliable_now_doesnt_wait_for_what_the_conditioner_dropped() {
+    let (mut transport, mut server) = conditioned_transport(LinkProfile { loss: 1.0, ..Default::default() }).await;
+
+    for i in 0..8u8 {
+        transport.send_reliable(vec![i; 16]).unwrap();
+    }
+    let started = std::time::Instant::now();
+    let sent = tokio::task::spawn_blocking(move || transport.send_reliable_now(b"leave".to_vec()));
+    let frames = frames_until(&mut server, b"leave").await;
+
+    sent.await.unwrap().unwrap();
+    assert!(started.elapsed() < FLUSH_TIMEOUT / 2);
+    assert_eq!(frames, vec![b"leave".to_vec()]);
+}